@@ -0,0 +1,306 @@
+// API keys let automation accounts (CI bots, scripts) use the journal without a human password
+
+/*
+    A key looks like `rj_<id>_<secret>`. The id is public and is how we find the key again; the secret
+    is only ever shown once, when the key is created. We store a SHA-256 hash of the whole key, never
+    the key itself, so a leaked key file can't be used to act as the bots.
+
+    Each key carries the scopes it's allowed to use, an optional expiry and the last time it was used,
+    which makes forgotten keys easy to spot and revoke.
+ */
+
+use crate::auth::to_hex;
+use crate::secret::Secret;
+use crate::storage::write_json_atomically;
+use chrono::serde::{ts_seconds, ts_seconds_option};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const PREFIX: &str = "rj";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Scope {
+    #[serde(rename = "journal:read")]
+    JournalRead,
+    #[serde(rename = "journal:write")]
+    JournalWrite,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::JournalRead => write!(f, "journal:read"),
+            Scope::JournalWrite => write!(f, "journal:write"),
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "journal:read" => Ok(Scope::JournalRead),
+            "journal:write" => Ok(Scope::JournalWrite),
+            _ => Err(format!("unknown scope '{}', expected journal:read or journal:write", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    key_hash: Secret<String>,
+    pub scopes: Vec<Scope>,
+
+    #[serde(with = "ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds_option")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(with = "ts_seconds_option")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(with = "ts_seconds_option")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scopes: Vec<String> = self.scopes.iter().map(Scope::to_string).collect();
+        let date = |at: Option<DateTime<Utc>>| at.map_or("-".to_string(), |at| at.format("%F %H:%M").to_string());
+        let status = if self.revoked_at.is_some() { "revoked" } else { "active" };
+        write!(
+            f,
+            "{:<10} {:<20} {:<28} expires: {:<16} last used: {:<16} {}",
+            self.id,
+            self.name,
+            scopes.join(","),
+            date(self.expires_at),
+            date(self.last_used_at),
+            status
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ApiKeyError {
+    Malformed,
+    Unknown,
+    Revoked,
+    Expired,
+    MissingScope(Scope),
+    Storage(String),
+}
+
+impl fmt::Display for ApiKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiKeyError::Malformed => write!(f, "API key is malformed"),
+            ApiKeyError::Unknown => write!(f, "API key is not recognised"),
+            ApiKeyError::Revoked => write!(f, "API key has been revoked"),
+            ApiKeyError::Expired => write!(f, "API key has expired"),
+            ApiKeyError::MissingScope(scope) => write!(f, "API key lacks the {} scope", scope),
+            ApiKeyError::Storage(error) => write!(f, "API key store: {}", error),
+        }
+    }
+}
+
+impl From<std::io::Error> for ApiKeyError {
+    fn from(error: std::io::Error) -> Self {
+        ApiKeyError::Storage(error.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ApiKeyStore {
+    keys: Vec<ApiKey>,
+    path: Option<PathBuf>,
+}
+
+impl ApiKeyStore {
+    /// A store that only keeps its keys in memory.
+    pub fn new() -> Self {
+        ApiKeyStore::default()
+    }
+
+    /// A store backed by a JSON file. Existing keys in the file are loaded.
+    pub fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let keys = match serde_json::from_reader(file) {
+            Ok(keys) => keys,
+            Err(e) if e.is_eof() => Vec::new(),
+            Err(e) => Err(e)?,
+        };
+
+        Ok(ApiKeyStore { keys, path: Some(path) })
+    }
+
+    /// Creates a key. The returned plain-text key is the only copy; it can't be recovered later.
+    pub fn create(
+        &mut self,
+        name: &str,
+        scopes: Vec<Scope>,
+        expires_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<(Secret<String>, ApiKey), ApiKeyError> {
+        let mut rng = rand::thread_rng();
+        let id = to_hex(&rng.gen::<[u8; 4]>());
+        let secret = Secret::new(to_hex(&rng.gen::<[u8; 24]>()));
+        let plain = Secret::new(format!("{}_{}_{}", PREFIX, id, secret.expose_secret()));
+
+        let key = ApiKey {
+            id,
+            name: name.to_string(),
+            key_hash: hash_key(plain.expose_secret()),
+            scopes,
+            created_at: now,
+            expires_at,
+            last_used_at: None,
+            revoked_at: None,
+        };
+
+        self.keys.push(key.clone());
+        self.save()?;
+        Ok((plain, key))
+    }
+
+    pub fn list(&self) -> &[ApiKey] {
+        &self.keys
+    }
+
+    /// Revokes a key by id. Returns false if no active key has that id.
+    pub fn revoke(&mut self, id: &str, now: DateTime<Utc>) -> Result<bool, ApiKeyError> {
+        match self.keys.iter_mut().find(|key| key.id == id && key.revoked_at.is_none()) {
+            Some(key) => {
+                key.revoked_at = Some(now);
+                self.save()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Checks a presented key and records that it was used.
+    pub fn authenticate(&mut self, presented: &str, scope: Scope, now: DateTime<Utc>) -> Result<&ApiKey, ApiKeyError> {
+        let id = match presented.trim().split('_').collect::<Vec<_>>()[..] {
+            [PREFIX, id, secret] if !id.is_empty() && !secret.is_empty() => id.to_string(),
+            _ => return Err(ApiKeyError::Malformed),
+        };
+
+        let hash = hash_key(presented.trim());
+        let index = self
+            .keys
+            .iter()
+            .position(|key| key.id == id && key.key_hash == hash)
+            .ok_or(ApiKeyError::Unknown)?;
+
+        let key = &self.keys[index];
+        if key.revoked_at.is_some() {
+            return Err(ApiKeyError::Revoked);
+        }
+        if key.expires_at.is_some_and(|expires_at| now >= expires_at) {
+            return Err(ApiKeyError::Expired);
+        }
+        if !key.scopes.contains(&scope) {
+            return Err(ApiKeyError::MissingScope(scope));
+        }
+
+        self.keys[index].last_used_at = Some(now);
+        self.save()?;
+        Ok(&self.keys[index])
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            write_json_atomically(path, &self.keys)?;
+        }
+        Ok(())
+    }
+}
+
+fn hash_key(key: &str) -> Secret<String> {
+    Secret::new(to_hex(&Sha256::digest(key.as_bytes())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn only_the_hash_is_stored() {
+        let mut store = ApiKeyStore::new();
+        let (plain, key) = store.create("ci-bot", vec![Scope::JournalRead], None, now()).unwrap();
+
+        assert!(plain.expose_secret().starts_with(&format!("rj_{}_", key.id)));
+        assert!(!serde_json::to_string(&store.keys).unwrap().contains(plain.expose_secret().as_str()));
+    }
+
+    #[test]
+    fn authenticate_checks_scope_and_records_use() {
+        let mut store = ApiKeyStore::new();
+        let (plain, _) = store.create("ci-bot", vec![Scope::JournalRead], None, now()).unwrap();
+
+        assert_eq!(
+            store.authenticate(plain.expose_secret(), Scope::JournalWrite, now()),
+            Err(ApiKeyError::MissingScope(Scope::JournalWrite))
+        );
+
+        let key = store.authenticate(plain.expose_secret(), Scope::JournalRead, now()).unwrap();
+        assert_eq!(key.name, "ci-bot");
+        assert_eq!(key.last_used_at, Some(now()));
+    }
+
+    #[test]
+    fn expired_revoked_and_unknown_keys_are_rejected() {
+        let mut store = ApiKeyStore::new();
+        let expires_at = now() + Duration::days(30);
+        let (plain, key) = store.create("ci-bot", vec![Scope::JournalRead], Some(expires_at), now()).unwrap();
+
+        assert_eq!(store.authenticate(plain.expose_secret(), Scope::JournalRead, expires_at), Err(ApiKeyError::Expired));
+        assert_eq!(store.authenticate("rj_0000_beef", Scope::JournalRead, now()), Err(ApiKeyError::Unknown));
+        assert_eq!(store.authenticate("hunter2", Scope::JournalRead, now()), Err(ApiKeyError::Malformed));
+
+        assert!(store.revoke(&key.id, now()).unwrap());
+        assert!(!store.revoke(&key.id, now()).unwrap());
+        assert_eq!(store.authenticate(plain.expose_secret(), Scope::JournalRead, now()), Err(ApiKeyError::Revoked));
+    }
+
+    #[test]
+    fn keys_survive_restart() {
+        let path = std::env::temp_dir().join(format!("api-keys-{}.json", uuid::Uuid::new_v4()));
+
+        let mut store = ApiKeyStore::open(path.clone()).unwrap();
+        let (plain, _) = store.create("ci-bot", vec![Scope::JournalWrite], None, now()).unwrap();
+        drop(store);
+
+        let mut reopened = ApiKeyStore::open(path.clone()).unwrap();
+        assert_eq!(reopened.list().len(), 1);
+        assert!(reopened.authenticate(plain.expose_secret(), Scope::JournalWrite, now()).is_ok());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn scopes_parse_from_cli_names() {
+        assert_eq!("journal:write".parse(), Ok(Scope::JournalWrite));
+        assert!("journal:admin".parse::<Scope>().is_err());
+    }
+}
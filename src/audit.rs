@@ -0,0 +1,305 @@
+// A tamper-evident, append-only log of who did what and when
+
+/*
+    Each entry is one line of JSON, and the file is only ever opened in append mode. Every entry
+    records the hash of the entry before it, and its own hash covers its contents plus that previous
+    hash. Editing an entry changes its hash, which no longer matches the `previous_hash` stored in the
+    next entry; removing an entry leaves a gap in the sequence numbers and a broken link in the chain.
+
+    The chain can't tell on its own that entries were cut off the *end* of the file. verify() returns
+    the number of entries and the hash of the last one, so they can be written down elsewhere and
+    compared later.
+ */
+
+use crate::auth::to_hex;
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Result, Write};
+use std::path::PathBuf;
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditEvent {
+    LoginSucceeded { source: String },
+    LoginFailed { source: String, reason: String },
+    PasswordChanged,
+    TaskAdded { text: String },
+    TaskCompleted { position: usize, text: String },
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditEvent::LoginSucceeded { source } => write!(f, "login succeeded from {}", source),
+            AuditEvent::LoginFailed { source, reason } => write!(f, "login failed from {}: {}", source, reason),
+            AuditEvent::PasswordChanged => write!(f, "password changed"),
+            AuditEvent::TaskAdded { text } => write!(f, "task added: {}", text),
+            AuditEvent::TaskCompleted { position, text } => write!(f, "task {} completed: {}", position, text),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    #[serde(with = "ts_seconds")]
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,
+    pub event: AuditEvent,
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        // The event is hashed in its JSON form, which is also exactly what ends up on disk
+        let event = serde_json::to_string(&self.event).expect("audit events always serialize");
+        let digest = Sha256::new()
+            .chain_update(self.sequence.to_be_bytes())
+            .chain_update(self.timestamp.timestamp().to_be_bytes())
+            .chain_update((self.actor.len() as u64).to_be_bytes())
+            .chain_update(self.actor.as_bytes())
+            .chain_update(event.as_bytes())
+            .chain_update(self.previous_hash.as_bytes())
+            .finalize();
+        to_hex(&digest)
+    }
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} [{}] {}: {}", self.sequence, self.timestamp.format("%F %H:%M:%S"), self.actor, self.event)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuditError {
+    Io(String),
+    Malformed { line: usize },
+    // An entry's contents no longer match its own hash
+    Tampered { sequence: u64 },
+    // An entry doesn't point at the hash of the entry before it
+    BrokenChain { sequence: u64 },
+    // Sequence numbers skipped or repeated, so entries were removed or reordered
+    Missing { expected: u64, found: u64 },
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::Io(error) => write!(f, "could not read audit log: {}", error),
+            AuditError::Malformed { line } => write!(f, "line {} is not a valid audit entry", line),
+            AuditError::Tampered { sequence } => write!(f, "entry #{} has been edited", sequence),
+            AuditError::BrokenChain { sequence } => {
+                write!(f, "entry #{} does not follow the entry before it", sequence)
+            }
+            AuditError::Missing { expected, found } => {
+                write!(f, "expected entry #{} but found #{}: entries are missing", expected, found)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for AuditError {
+    fn from(error: std::io::Error) -> Self {
+        AuditError::Io(error.to_string())
+    }
+}
+
+/// What a successful verification saw.
+#[derive(Debug, PartialEq)]
+pub struct AuditSummary {
+    pub entries: u64,
+    pub head_hash: String,
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    next_sequence: u64,
+    head_hash: String,
+}
+
+impl AuditLog {
+    /// Opens (or creates) the log and picks up the chain where the last entry left it.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        OpenOptions::new().create(true).append(true).open(&path)?;
+
+        let mut log = AuditLog {
+            path,
+            next_sequence: 0,
+            head_hash: GENESIS_HASH.to_string(),
+        };
+
+        let file = File::open(&log.path)?;
+        let last_line = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| !line.trim().is_empty())
+            .last();
+        if let Some(last) = last_line {
+            let entry: AuditEntry = serde_json::from_str(&last)?;
+            log.next_sequence = entry.sequence + 1;
+            log.head_hash = entry.hash;
+        }
+
+        Ok(log)
+    }
+
+    pub fn append(&mut self, actor: &str, event: AuditEvent, now: DateTime<Utc>) -> Result<AuditEntry> {
+        let mut entry = AuditEntry {
+            sequence: self.next_sequence,
+            timestamp: now,
+            actor: actor.to_string(),
+            event,
+            previous_hash: self.head_hash.clone(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+
+        self.next_sequence += 1;
+        self.head_hash = entry.hash.clone();
+        Ok(entry)
+    }
+}
+
+/// Reads every entry in the log, in order.
+pub fn read_entries(path: PathBuf) -> std::result::Result<Vec<AuditEntry>, AuditError> {
+    let file = File::open(path)?;
+    let mut entries = Vec::new();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|_| AuditError::Malformed { line: index + 1 })?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Walks the whole chain and reports the first entry that was edited, removed or reordered.
+pub fn verify(path: PathBuf) -> std::result::Result<AuditSummary, AuditError> {
+    let mut expected_sequence = 0;
+    let mut previous_hash = GENESIS_HASH.to_string();
+
+    for entry in read_entries(path)? {
+        if entry.sequence != expected_sequence {
+            return Err(AuditError::Missing { expected: expected_sequence, found: entry.sequence });
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(AuditError::Tampered { sequence: entry.sequence });
+        }
+        if entry.previous_hash != previous_hash {
+            return Err(AuditError::BrokenChain { sequence: entry.sequence });
+        }
+        expected_sequence += 1;
+        previous_hash = entry.hash;
+    }
+
+    Ok(AuditSummary {
+        entries: expected_sequence,
+        head_hash: previous_hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::path::Path;
+
+    fn temp_log() -> PathBuf {
+        std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()))
+    }
+
+    fn write_sample(path: &Path) {
+        let at = Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap();
+        let mut log = AuditLog::open(path.to_path_buf()).unwrap();
+        log.append("charles", AuditEvent::LoginSucceeded { source: "tty1".to_string() }, at).unwrap();
+        log.append("charles", AuditEvent::TaskAdded { text: "buy milk".to_string() }, at).unwrap();
+        log.append(
+            "charles",
+            AuditEvent::TaskCompleted { position: 1, text: "buy milk".to_string() },
+            at,
+        )
+        .unwrap();
+    }
+
+    fn rewrite_lines(path: &Path, edit: impl FnOnce(&mut Vec<String>)) {
+        let mut lines: Vec<String> = std::fs::read_to_string(path).unwrap().lines().map(String::from).collect();
+        edit(&mut lines);
+        std::fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn intact_log_verifies_and_reopens_on_the_chain() {
+        let path = temp_log();
+        write_sample(&path);
+
+        // A second process picks the chain up where the first one stopped
+        let mut log = AuditLog::open(path.clone()).unwrap();
+        let entry = log.append("ci-bot", AuditEvent::PasswordChanged, Utc::now()).unwrap();
+        assert_eq!(entry.sequence, 3);
+
+        let summary = verify(path.clone()).unwrap();
+        assert_eq!(summary.entries, 4);
+        assert_eq!(summary.head_hash, entry.hash);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn edited_entry_is_detected() {
+        let path = temp_log();
+        write_sample(&path);
+        rewrite_lines(&path, |lines| lines[1] = lines[1].replace("charles", "mallory"));
+
+        assert_eq!(verify(path.clone()), Err(AuditError::Tampered { sequence: 1 }));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn edited_entry_with_recomputed_hash_breaks_the_chain() {
+        let path = temp_log();
+        write_sample(&path);
+        rewrite_lines(&path, |lines| {
+            let mut entry: AuditEntry = serde_json::from_str(&lines[1]).unwrap();
+            entry.actor = "mallory".to_string();
+            entry.hash = entry.compute_hash();
+            lines[1] = serde_json::to_string(&entry).unwrap();
+        });
+
+        assert_eq!(verify(path.clone()), Err(AuditError::BrokenChain { sequence: 2 }));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_entry_is_detected() {
+        let path = temp_log();
+        write_sample(&path);
+        rewrite_lines(&path, |lines| {
+            lines.remove(1);
+        });
+
+        assert_eq!(verify(path.clone()), Err(AuditError::Missing { expected: 1, found: 2 }));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
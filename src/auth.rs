@@ -1,17 +1,26 @@
-use std::hash::{Hash, Hasher};
 // This is the primary trait to use when generating random values
 use rand::Rng;
+use sha2::{Digest, Sha256};
+use crate::audit::{AuditEvent, AuditLog};
+use crate::clock::Clock;
+use crate::lockout::{Denial, LoginThrottle};
+use crate::secret::Secret;
+use crate::totp::{TotpConfig, TwoFactor};
+use std::fmt;
 
+#[derive(Debug)]
 pub struct User {
     username: String,
-    password_hash: u64,
+    password_hash: Secret<String>,
+    two_factor: Option<TwoFactor>,
 }
 
 impl User {
     pub fn new(username: &str, password: &str) -> Self {
         User {
             username: username.to_string(),
-            password_hash: hash_password(&password.to_owned()),
+            password_hash: Secret::new(hash_password(password, &generate_salt())),
+            two_factor: None,
         }
     }
 
@@ -22,20 +31,343 @@ impl User {
 
     // A setter takes in a mutable reference to its struct object, mutates it with the new value, and returns nothing
     pub fn set_password(&mut self, new_password: &str) {
-        self.password_hash = hash_password(&new_password.to_owned())
+        self.password_hash = Secret::new(hash_password(new_password, &generate_salt()))
     }
 
-    pub fn to_string(&self) -> String {
-        format!("Username: {}\nPassword: {}", &self.username, &self.password_hash)
+    pub fn verify_password(&self, password: &str) -> bool {
+        // The stored hash is "<salt>$<digest>", so the salt can be recovered to hash the candidate the same way
+        let stored = self.password_hash.expose_secret();
+        match stored.split_once('$') {
+            Some((salt, _)) => constant_time_eq(hash_password(password, salt).as_bytes(), stored.as_bytes()),
+            None => false,
+        }
+    }
+
+    /// Turns on TOTP for this user and returns the one-time recovery codes.
+    /// Any previous secret and recovery codes stop working.
+    pub fn enable_two_factor(&mut self, config: TotpConfig) -> Vec<Secret<String>> {
+        let (two_factor, recovery_codes) = TwoFactor::enroll(config);
+        self.two_factor = Some(two_factor);
+        recovery_codes
+    }
+
+    pub fn disable_two_factor(&mut self) {
+        self.two_factor = None;
+    }
+
+    pub fn two_factor(&self) -> Option<&TwoFactor> {
+        self.two_factor.as_ref()
     }
 }
 
-fn hash_password<T: Hash>(t: &T) -> u64 {
+// The password hash is a Secret, so it prints as [REDACTED]
+impl fmt::Display for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Username: {}\nPassword: {}", &self.username, &self.password_hash)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoginError {
+    InvalidCredentials,
+    // The password was right, but the user has 2FA enabled and no code was given
+    SecondFactorRequired,
+    InvalidSecondFactor,
+    // The attempt was refused without checking the password
+    Denied(Denial),
+    // The login itself may have been fine, but the failure counters couldn't be saved
+    Storage(String),
+}
+
+impl From<std::io::Error> for LoginError {
+    fn from(error: std::io::Error) -> Self {
+        LoginError::Storage(error.to_string())
+    }
+}
+
+/*
+    The Authenticator is the only way to log a user in. It asks the throttle whether the attempt is
+    allowed at all before it compares passwords, and it reports the outcome back to the throttle.
+    The clock is a type parameter so tests can drive the backoff and lockout windows with a FakeClock.
+    With an audit log attached, every login attempt and password change is recorded in it.
+ */
+pub struct Authenticator<C: Clock> {
+    throttle: LoginThrottle,
+    clock: C,
+    audit: Option<AuditLog>,
+}
+
+impl<C: Clock> Authenticator<C> {
+    pub fn new(throttle: LoginThrottle, clock: C) -> Self {
+        Authenticator { throttle, clock, audit: None }
+    }
+
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /*
+        Logs `user` in from `source` (an IP address, a terminal, ...).
+        When the user has 2FA enabled, `second_factor` must hold either the current TOTP code or one of
+        the recovery codes. A wrong code counts as a failed attempt, just like a wrong password.
+     */
+    pub fn login(
+        &mut self,
+        user: &mut User,
+        password: &str,
+        second_factor: Option<&str>,
+        source: &str,
+    ) -> Result<(), LoginError> {
+        let outcome = self.attempt_login(user, password, second_factor, source);
+
+        let event = match &outcome {
+            Ok(()) => AuditEvent::LoginSucceeded { source: source.to_string() },
+            Err(error) => AuditEvent::LoginFailed {
+                source: source.to_string(),
+                reason: format!("{:?}", error),
+            },
+        };
+        self.record(&user.username, event)?;
+
+        outcome
+    }
+
+    /// Changes the password after checking the current one.
+    pub fn change_password(&mut self, user: &mut User, current: &str, new_password: &str) -> Result<(), LoginError> {
+        if !user.verify_password(current) {
+            return Err(LoginError::InvalidCredentials);
+        }
+        user.set_password(new_password);
+        self.record(&user.username, AuditEvent::PasswordChanged)
+    }
+
+    fn attempt_login(
+        &mut self,
+        user: &mut User,
+        password: &str,
+        second_factor: Option<&str>,
+        source: &str,
+    ) -> Result<(), LoginError> {
+        let now = self.clock.now();
+        self.throttle
+            .check(&user.username, source, now)
+            .map_err(LoginError::Denied)?;
+
+        if !user.verify_password(password) {
+            self.throttle.record_failure(&user.username, source, now)?;
+            return Err(LoginError::InvalidCredentials);
+        }
+
+        if let Some(two_factor) = user.two_factor.as_mut() {
+            let code = match second_factor {
+                Some(code) => code.trim(),
+                None => return Err(LoginError::SecondFactorRequired),
+            };
+            let accepted = if code.chars().all(|c| c.is_ascii_digit()) {
+                two_factor.verify(code, now)
+            } else {
+                two_factor.redeem_recovery_code(code)
+            };
+            if !accepted {
+                self.throttle.record_failure(&user.username, source, now)?;
+                return Err(LoginError::InvalidSecondFactor);
+            }
+        }
+
+        self.throttle.record_success(&user.username)?;
+        Ok(())
+    }
+
+    /// Admin operation: lifts any lockout or backoff on the user.
+    pub fn unlock(&mut self, username: &str) -> Result<bool, LoginError> {
+        Ok(self.throttle.unlock_user(username)?)
+    }
+
+    /// Admin operation: lifts any lockout or backoff on a source, such as a terminal or an address.
+    pub fn unlock_source(&mut self, source: &str) -> Result<bool, LoginError> {
+        Ok(self.throttle.unlock_source(source)?)
+    }
+
+    pub fn throttle(&self) -> &LoginThrottle {
+        &self.throttle
+    }
+
+    fn record(&mut self, actor: &str, event: AuditEvent) -> Result<(), LoginError> {
+        if let Some(audit) = self.audit.as_mut() {
+            audit.append(actor, event, self.clock.now())?;
+        }
+        Ok(())
+    }
+}
+
+fn generate_salt() -> String {
     /*
         The rand::thread_rng() function provides a random number generator
         local to the current thread, and rng.gen() generates a random value
-        appropriate for the type specified (in this case u64).
+        appropriate for the type specified (in this case [u8; 16]).
      */
-    rand::thread_rng().gen()
+    let salt: [u8; 16] = rand::thread_rng().gen();
+    to_hex(&salt)
+}
+
+fn hash_password(password: &str, salt: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(salt.as_bytes())
+        .chain_update(password.as_bytes())
+        .finalize();
+    format!("{}${}", salt, to_hex(&digest))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Compares every byte even after a mismatch, so the time taken doesn't leak how much of a hash matched
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use crate::lockout::LockoutPolicy;
+    use crate::totp::totp;
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn authenticator(clock: &FakeClock) -> Authenticator<&FakeClock> {
+        Authenticator::new(LoginThrottle::new(LockoutPolicy::default()), clock)
+    }
+
+    #[test]
+    fn password_is_salted_and_verifiable() {
+        let a = User::new("Charles", "super-secret");
+        let b = User::new("Charles", "super-secret");
+
+        assert_ne!(a.password_hash, b.password_hash);
+        assert!(a.verify_password("super-secret"));
+        assert!(!a.verify_password("super-secreT"));
+    }
+
+    #[test]
+    fn login_is_locked_out_after_repeated_failures() {
+        let clock = FakeClock::new(Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap());
+        let mut auth = authenticator(&clock);
+        let mut user = User::new("Charles", "super-secret");
+        let policy = LockoutPolicy::default();
+
+        for _ in 0..policy.max_failures {
+            assert_eq!(auth.login(&mut user, "guess", None, "tty1"), Err(LoginError::InvalidCredentials));
+            clock.advance(Duration::seconds(policy.max_delay_secs));
+        }
+
+        // Even the right password is refused while locked out
+        assert!(matches!(
+            auth.login(&mut user, "super-secret", None, "tty1"),
+            Err(LoginError::Denied(Denial::LockedOut { .. }))
+        ));
+
+        // Unlocking the user still leaves the source that made the guesses locked out
+        assert!(auth.unlock("Charles").unwrap());
+        assert!(matches!(
+            auth.login(&mut user, "super-secret", None, "tty1"),
+            Err(LoginError::Denied(Denial::LockedOut { .. }))
+        ));
+        assert!(auth.unlock_source("tty1").unwrap());
+        assert!(!auth.unlock_source("tty1").unwrap());
+        assert_eq!(auth.login(&mut user, "super-secret", None, "tty1"), Ok(()));
+    }
+
+    #[test]
+    fn login_backs_off_between_attempts() {
+        let clock = FakeClock::new(Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap());
+        let mut auth = authenticator(&clock);
+        let mut user = User::new("Charles", "super-secret");
+
+        assert_eq!(auth.login(&mut user, "guess", None, "tty1"), Err(LoginError::InvalidCredentials));
+        assert!(matches!(
+            auth.login(&mut user, "super-secret", None, "tty1"),
+            Err(LoginError::Denied(Denial::Throttled { .. }))
+        ));
+
+        clock.advance(Duration::seconds(1));
+        assert_eq!(auth.login(&mut user, "super-secret", None, "tty1"), Ok(()));
+        assert_eq!(auth.throttle().failures_for_user("Charles"), 0);
+    }
+
+    #[test]
+    fn login_requires_second_factor_when_enabled() {
+        let clock = FakeClock::new(Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap());
+        let mut auth = authenticator(&clock);
+        let mut user = User::new("Charles", "super-secret");
+        let recovery_codes = user.enable_two_factor(TotpConfig::default());
+
+        assert_eq!(
+            auth.login(&mut user, "super-secret", None, "tty1"),
+            Err(LoginError::SecondFactorRequired)
+        );
+        assert_eq!(
+            auth.login(&mut user, "super-secret", Some("000000"), "tty1"),
+            Err(LoginError::InvalidSecondFactor)
+        );
+        assert_eq!(auth.throttle().failures_for_user("Charles"), 1);
+
+        clock.advance(Duration::seconds(1));
+        let two_factor = user.two_factor().unwrap();
+        let secret = base32_to_secret(two_factor.secret_base32().expose_secret());
+        let code = totp(&secret, clock.now(), two_factor.config());
+        assert_eq!(auth.login(&mut user, "super-secret", Some(&code), "tty1"), Ok(()));
+
+        assert_eq!(auth.login(&mut user, "super-secret", Some(recovery_codes[0].expose_secret()), "tty1"), Ok(()));
+        assert_eq!(
+            auth.login(&mut user, "super-secret", Some(recovery_codes[0].expose_secret()), "tty1"),
+            Err(LoginError::InvalidSecondFactor)
+        );
+    }
+
+    #[test]
+    fn logins_and_password_changes_are_audited() {
+        let clock = FakeClock::new(Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap());
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let mut auth = authenticator(&clock).with_audit_log(AuditLog::open(path.clone()).unwrap());
+        let mut user = User::new("Charles", "super-secret");
+
+        assert!(auth.login(&mut user, "guess", None, "10.0.0.1").is_err());
+        clock.advance(Duration::seconds(1));
+        assert!(auth.login(&mut user, "super-secret", None, "10.0.0.1").is_ok());
+        assert!(auth.change_password(&mut user, "super-secret", "new-password").is_ok());
+
+        let entries = crate::audit::read_entries(path.clone()).unwrap();
+        let events: Vec<&AuditEvent> = entries.iter().map(|entry| &entry.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                &AuditEvent::LoginFailed { source: "10.0.0.1".to_string(), reason: "InvalidCredentials".to_string() },
+                &AuditEvent::LoginSucceeded { source: "10.0.0.1".to_string() },
+                &AuditEvent::PasswordChanged,
+            ]
+        );
+        assert!(entries.iter().all(|entry| entry.actor == "Charles"));
+        assert_eq!(entries[1].timestamp, clock.now());
+        assert!(user.verify_password("new-password"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // What an authenticator app does with the secret it scanned
+    fn base32_to_secret(encoded: &str) -> Vec<u8> {
+        const ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+        let mut bytes = Vec::new();
+        let (mut buffer, mut bits) = (0u32, 0);
+        for c in encoded.chars() {
+            buffer = (buffer << 5) | ALPHABET.find(c).unwrap() as u32;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                bytes.push((buffer >> bits) as u8);
+            }
+        }
+        bytes
+    }
 }
-// fn hash_password<T: Hash>(t: &T) -> u64 {/* ... */}
\ No newline at end of file
@@ -2,30 +2,45 @@
 
 // enum is like a Scala sealed trait's companion object and struct is like a mutable Scala case class
 
-#[derive(PartialEq, Debug)]
+use crate::catalog::Catalog;
+use crate::condition::{Condition, MileageBand};
+use chrono::{Datelike, Utc};
+use crate::vin::{Vin, VinError};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]
 pub struct Car {
     make: String,
     model: String,
-    pub color: String,
+    pub year: Option<u16>,
+    pub color: Color,
     pub motor: Transmission,
     pub roof: bool,
-    pub age: (Age, u32)
+    pub age: (Age, u32),
+    pub condition: Condition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vin: Option<Vin>,
+    /// The make's world manufacturer identifier from the catalog, needed for a VIN.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wmi: Option<String>,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Transmission {
     Manual,
     SemiAuto,
     Automatic
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Age {
     New,
     Used
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Color {
     Blue,
     Green,
@@ -33,46 +48,628 @@ pub enum Color {
     Silver
 }
 
-// Best practice: a struct should own its own data
+/// Whether the car has a fixed roof (Car::roof is true) or is a convertible.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Roof {
+    Hardtop,
+    Convertible
+}
+
+impl From<bool> for Roof {
+    fn from(roof: bool) -> Self {
+        if roof { Roof::Hardtop } else { Roof::Convertible }
+    }
+}
 
-pub fn car_factory(order: i32, miles: u32) -> Car {
-    let colors = ["Blue", "Green", "Red", "Silver"];
-    // Prevent panic: Check color index for colors array, reset as needed
-    // Valid color = 1, 2, 3, or 4
-    // usize is typically used as the type for array indices and pointers
-    let mut color = order as usize;
-    while color > 4 {
-        color = color - colors.len();
+impl From<Roof> for bool {
+    fn from(roof: Roof) -> Self {
+        roof == Roof::Hardtop
     }
+}
 
-    // Add variety to orders for motor type and roof type
-    let mut motor = Transmission::Manual;
-    let mut roof = true;
+impl Car {
+    pub fn make(&self) -> &str {
+        &self.make
+    }
 
-    if order % 3 == 0 {          // 3, 6, 9
-        motor = Transmission::Automatic;
+    pub fn model(&self) -> &str {
+        &self.model
     }
-    else if order % 2 == 0 {   // 2, 4, 8, 10
-        motor = Transmission::SemiAuto;
-        roof = false;
-    }                            // 1, 5, 7, 11
 
-    Car {
-        make: String::from("Maserati"),
-        model: String::from("Folgore"),
-        color: String::from(colors[(color-1) as usize]),
-        motor,
-        roof,
-        age: car_quality(miles)
+    /// Gives the car a VIN built from its manufacturer code, model and year, unless it already has one.
+    pub fn assign_vin(&mut self, plant: char, serial: u32) -> Result<&Vin, VinError> {
+        if self.vin.is_none() {
+            let year = self.year.ok_or(VinError::MissingYear)?;
+            let wmi = self.wmi.as_deref().ok_or(VinError::MissingWmi)?;
+            self.vin = Some(Vin::generate(wmi, &self.model, year, plant, serial)?);
+        }
+        Ok(self.vin.as_ref().expect("assigned above"))
     }
 }
 
-fn car_quality(miles: u32) -> (Age, u32) {
-    if miles < 10 {
-        (Age::New, miles)
+impl Color {
+    pub const ALL: [Color; 4] = [Color::Blue, Color::Green, Color::Red, Color::Silver];
+}
+
+impl Transmission {
+    pub const ALL: [Transmission; 3] = [Transmission::Manual, Transmission::SemiAuto, Transmission::Automatic];
+}
+
+/// Returned when text doesn't name any variant of a car option.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseOptionError {
+    pub option: &'static str,
+    pub input: String,
+    pub expected: Vec<String>,
+}
+
+impl fmt::Display for ParseOptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' is not a known {}, expected one of: {}",
+            self.input,
+            self.option,
+            self.expected.join(", ")
+        )
+    }
+}
+
+/*
+    Parsing is case-insensitive and ignores spaces, dashes and underscores, so "semi-auto",
+    "Semi Auto" and "SEMI_AUTO" all mean Transmission::SemiAuto. Display prints the name that reads
+    best, and that name always parses back to the same variant.
+ */
+fn normalize(input: &str) -> String {
+    input
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '_'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/*
+    This declarative macro writes the FromStr, Display and serde impls for each option enum from a
+    single table of (variant, display name, extra aliases). Serializing goes through Display and
+    deserializing goes through FromStr, so files get the same forgiving parsing as the command line.
+ */
+macro_rules! car_option {
+    ($option:ident, $label:literal, { $($variant:ident => $name:literal $(| $alias:literal)*),+ $(,)? }) => {
+        impl fmt::Display for $option {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    $($option::$variant => f.pad($name),)+
+                }
+            }
+        }
+
+        impl FromStr for $option {
+            type Err = ParseOptionError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let wanted = normalize(s);
+                $(
+                    if wanted == normalize($name) $(|| wanted == normalize($alias))* {
+                        return Ok($option::$variant);
+                    }
+                )+
+                Err(ParseOptionError {
+                    option: $label,
+                    input: s.to_string(),
+                    expected: vec![$($name.to_string()),+],
+                })
+            }
+        }
+
+        impl Serialize for $option {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $option {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let text = String::deserialize(deserializer)?;
+                text.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+car_option!(Color, "color", {
+    Blue => "Blue",
+    Green => "Green",
+    Red => "Red",
+    Silver => "Silver" | "grey" | "gray",
+});
+
+car_option!(Transmission, "transmission", {
+    Manual => "Manual",
+    SemiAuto => "Semi-Auto" | "semi-automatic",
+    Automatic => "Automatic" | "auto",
+});
+
+car_option!(Roof, "roof", {
+    Hardtop => "Hardtop" | "fixed",
+    Convertible => "Convertible" | "open",
+});
+
+car_option!(Age, "age", {
+    New => "New",
+    Used => "Used",
+});
+
+// Best practice: a struct should own its own data
+
+/*
+    A CarOrder collects the options one at a time, builder style, and only turns into a Car once
+    build() has checked that every option was chosen. Nothing is guessed from the order number any more;
+    that old mapping lives on as legacy_assignment() for callers that still number their orders.
+
+    build() also checks that the options make sense together:
+
+      - the model year is at most next year (next year's models go on sale during this one),
+      - a car with more than delivery miles has a model year, since a used car's condition is
+        graded by its age, and
+      - a car with delivery miles has no accidents or service records; it hasn't been on the road.
+ */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CarOrder {
+    make: Option<String>,
+    model: Option<String>,
+    year: Option<u16>,
+    color: Option<Color>,
+    motor: Option<Transmission>,
+    roof: Option<bool>,
+    miles: u32,
+    certified: bool,
+    accidents: u32,
+    service_records: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CarOrderError {
+    // An option that has to be chosen explicitly wasn't
+    MissingOption(&'static str),
+    BlankField(&'static str),
+    // Legacy order numbers start at 1
+    InvalidOrderNumber(i32),
+    ColorNotOffered { model: String, color: Color, offered: Vec<Color> },
+    // The options below are checked against a vehicle Catalog
+    UnknownModel { make: String, model: String },
+    YearNotOffered { model: String, year: u16, offered: Vec<u16> },
+    TransmissionNotOffered { model: String, motor: Transmission, offered: Vec<Transmission> },
+    RoofNotOffered { model: String, roof: Roof, offered: Vec<Roof> },
+    // The options below don't fit together
+    FutureModelYear { year: u16, latest: u16 },
+    UsedWithoutYear { miles: u32 },
+    HistoryOnNewCar { accidents: u32, service_records: u32 },
+}
+
+impl fmt::Display for CarOrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CarOrderError::MissingOption(option) => write!(f, "no {} was chosen for the order", option),
+            CarOrderError::BlankField(field) => write!(f, "the {} of the order is blank", field),
+            CarOrderError::InvalidOrderNumber(order) => {
+                write!(f, "order number {} is invalid, order numbers start at 1", order)
+            }
+            CarOrderError::ColorNotOffered { model, color, offered } => {
+                write!(f, "the {} is not offered in {}, choose one of: {}", model, color, list(offered))
+            }
+            CarOrderError::UnknownModel { make, model } => {
+                write!(f, "the catalog has no {} {}", make, model)
+            }
+            CarOrderError::YearNotOffered { model, year, offered } => {
+                write!(f, "the {} is not offered for {}, choose one of: {}", model, year, list(offered))
+            }
+            CarOrderError::TransmissionNotOffered { model, motor, offered } => {
+                write!(f, "the {} is not offered with a {} transmission, choose one of: {}", model, motor, list(offered))
+            }
+            CarOrderError::RoofNotOffered { model, roof, offered } => {
+                write!(f, "the {} is not offered as a {}, choose one of: {}", model, roof, list(offered))
+            }
+            CarOrderError::FutureModelYear { year, latest } => {
+                write!(f, "model year {} is in the future, the latest is {}", year, latest)
+            }
+            CarOrderError::UsedWithoutYear { miles } => {
+                write!(f, "a car with {} miles needs a model year", miles)
+            }
+            CarOrderError::HistoryOnNewCar { accidents, service_records } => write!(
+                f,
+                "a car with delivery miles can't have {} accidents and {} service records",
+                accidents, service_records
+            ),
+        }
     }
-    else {
-        (Age::Used, miles)
+}
+
+fn list<T: fmt::Display>(items: &[T]) -> String {
+    items.iter().map(T::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// An order for the same car, so a car that came from outside can be checked and built again.
+impl From<&Car> for CarOrder {
+    fn from(car: &Car) -> Self {
+        CarOrder {
+            make: Some(car.make.clone()),
+            model: Some(car.model.clone()),
+            year: car.year,
+            color: Some(car.color),
+            motor: Some(car.motor),
+            roof: Some(car.roof),
+            miles: car.condition.miles,
+            certified: car.condition.certified,
+            accidents: car.condition.accidents,
+            service_records: car.condition.service_records,
+        }
     }
 }
 
+impl CarOrder {
+    pub fn new() -> Self {
+        CarOrder::default()
+    }
+
+    pub fn make(mut self, make: &str) -> Self {
+        self.make = Some(make.to_string());
+        self
+    }
+
+    pub fn model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    pub fn year(mut self, year: u16) -> Self {
+        self.year = Some(year);
+        self
+    }
+
+    pub fn color(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn transmission(mut self, motor: Transmission) -> Self {
+        self.motor = Some(motor);
+        self
+    }
+
+    pub fn roof(mut self, roof: bool) -> Self {
+        self.roof = Some(roof);
+        self
+    }
+
+    pub fn miles(mut self, miles: u32) -> Self {
+        self.miles = miles;
+        self
+    }
+
+    /// Sold as certified pre-owned; see Condition::certifiable for what that takes.
+    pub fn certified(mut self, certified: bool) -> Self {
+        self.certified = certified;
+        self
+    }
+
+    pub fn accidents(mut self, accidents: u32) -> Self {
+        self.accidents = accidents;
+        self
+    }
+
+    pub fn service_records(mut self, service_records: u32) -> Self {
+        self.service_records = service_records;
+        self
+    }
+
+    /// Builds the car as of this year on the wall clock. See build_at.
+    pub fn build(self) -> Result<Car, CarOrderError> {
+        self.build_at(Utc::now().year() as u16)
+    }
+
+    /// Builds the car as of the given year; model years up to the next one can be ordered.
+    pub fn build_at(self, current_year: u16) -> Result<Car, CarOrderError> {
+        let make = required_text(self.make, "make")?;
+        let model = required_text(self.model, "model")?;
+        let color = self.color.ok_or(CarOrderError::MissingOption("color"))?;
+        let motor = self.motor.ok_or(CarOrderError::MissingOption("transmission"))?;
+        let roof = self.roof.ok_or(CarOrderError::MissingOption("roof"))?;
+
+        let latest = current_year + 1;
+        match self.year {
+            Some(year) if year > latest => return Err(CarOrderError::FutureModelYear { year, latest }),
+            None if MileageBand::of(self.miles) != MileageBand::Delivery => {
+                return Err(CarOrderError::UsedWithoutYear { miles: self.miles })
+            }
+            _ => {}
+        }
+        if MileageBand::of(self.miles) == MileageBand::Delivery && (self.accidents > 0 || self.service_records > 0) {
+            return Err(CarOrderError::HistoryOnNewCar { accidents: self.accidents, service_records: self.service_records });
+        }
+        let condition = Condition {
+            miles: self.miles,
+            model_year: self.year,
+            certified: self.certified,
+            accidents: self.accidents,
+            service_records: self.service_records,
+        };
+
+        Ok(Car {
+            make,
+            model,
+            year: self.year,
+            color,
+            motor,
+            roof,
+            age: (condition.age(), self.miles),
+            condition,
+            vin: None,
+            wmi: None,
+        })
+    }
+
+    /*
+        Builds the car against a vehicle catalog: the make and model must be in it, a model year must be
+        chosen, and every option must be one the model is offered with. Options that weren't chosen at
+        all are reported by build() as usual. Make and model are spelled the way the catalog spells them,
+        and the car carries the make's manufacturer code so it can be given a VIN. Like build(), this
+        goes by the wall clock; build_against_at takes the current year instead.
+     */
+    pub fn build_against(self, catalog: &Catalog) -> Result<Car, CarOrderError> {
+        self.build_against_at(catalog, Utc::now().year() as u16)
+    }
+
+    pub fn build_against_at(self, catalog: &Catalog, current_year: u16) -> Result<Car, CarOrderError> {
+        let make = required_text(self.make.clone(), "make")?;
+        let model_name = required_text(self.model.clone(), "model")?;
+        let (found_make, model) = catalog.find(&make, &model_name).ok_or(CarOrderError::UnknownModel {
+            make: make.clone(),
+            model: model_name.clone(),
+        })?;
+        let name = format!("{} {}", found_make.name, model.name);
+
+        let year = self.year.ok_or(CarOrderError::MissingOption("model year"))?;
+        if !model.years.contains(&year) {
+            return Err(CarOrderError::YearNotOffered { model: name, year, offered: model.years.clone() });
+        }
+        if let Some(motor) = self.motor {
+            if !model.transmissions.contains(&motor) {
+                return Err(CarOrderError::TransmissionNotOffered {
+                    model: name,
+                    motor,
+                    offered: model.transmissions.clone(),
+                });
+            }
+        }
+        if let Some(roof) = self.roof.map(Roof::from) {
+            if !model.roofs.contains(&roof) {
+                return Err(CarOrderError::RoofNotOffered { model: name, roof, offered: model.roofs.clone() });
+            }
+        }
+        if let Some(color) = self.color {
+            if !model.colors.contains(&color) {
+                return Err(CarOrderError::ColorNotOffered { model: name, color, offered: model.colors.clone() });
+            }
+        }
+
+        // The car gets the catalog's spelling of the make and model, whatever case they were ordered in
+        let (make, model) = (found_make.name.clone(), model.name.clone());
+        let mut car = self.make(&make).model(&model).build_at(current_year)?;
+        car.wmi = Some(found_make.wmi.clone());
+        Ok(car)
+    }
+
+    /*
+        The legacy assignment strategy: every car is a Maserati Folgore, and the order number picks
+        the rest. Colors cycle Blue, Green, Red, Silver; multiples of 3 are automatic with a roof,
+        other even numbers are semi-automatic without one, and everything else is manual with a roof.
+        Legacy orders have no model year, so used ones need one given with year() before build().
+     */
+    pub fn legacy_assignment(order: i32, miles: u32) -> Result<CarOrder, CarOrderError> {
+        if order < 1 {
+            return Err(CarOrderError::InvalidOrderNumber(order));
+        }
+
+        // usize is typically used as the type for array indices and pointers
+        let color = Color::ALL[(order as usize - 1) % Color::ALL.len()];
+
+        let (motor, roof) = if order % 3 == 0 {          // 3, 6, 9
+            (Transmission::Automatic, true)
+        } else if order % 2 == 0 {                      // 2, 4, 8, 10
+            (Transmission::SemiAuto, false)
+        } else {                                        // 1, 5, 7, 11
+            (Transmission::Manual, true)
+        };
+
+        Ok(CarOrder::new()
+            .make("Maserati")
+            .model("Folgore")
+            .color(color)
+            .transmission(motor)
+            .roof(roof)
+            .miles(miles))
+    }
+}
+
+fn required_text(value: Option<String>, field: &'static str) -> Result<String, CarOrderError> {
+    match value {
+        None => Err(CarOrderError::MissingOption(field)),
+        Some(text) if text.trim().is_empty() => Err(CarOrderError::BlankField(field)),
+        Some(text) => Ok(text.trim().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_uses_the_chosen_options() {
+        let car = CarOrder::new()
+            .make("Ferrari")
+            .model("Roma")
+            .color(Color::Red)
+            .transmission(Transmission::Automatic)
+            .roof(false)
+            .year(2023)
+            .miles(12)
+            .build()
+            .unwrap();
+
+        assert_eq!(car.color, Color::Red);
+        assert_eq!(car.motor, Transmission::Automatic);
+        assert!(!car.roof);
+        assert_eq!(car.age, (Age::Used, 12));
+        assert_eq!(car.condition.miles, 12);
+    }
+
+    #[test]
+    fn build_rejects_missing_and_blank_options() {
+        let complete = CarOrder::new()
+            .make("Maserati")
+            .model("Folgore")
+            .color(Color::Blue)
+            .transmission(Transmission::Manual)
+            .roof(true);
+
+        assert_eq!(CarOrder::new().build(), Err(CarOrderError::MissingOption("make")));
+        assert_eq!(complete.clone().make("  ").build(), Err(CarOrderError::BlankField("make")));
+        assert_eq!(
+            CarOrder { color: None, ..complete.clone() }.build(),
+            Err(CarOrderError::MissingOption("color"))
+        );
+        assert_eq!(
+            CarOrder { roof: None, ..complete.clone() }.build(),
+            Err(CarOrderError::MissingOption("roof"))
+        );
+        assert!(complete.build().is_ok());
+    }
+
+    #[test]
+    fn legacy_assignment_keeps_the_old_mapping() {
+        let expected = [
+            (1, Color::Blue, Transmission::Manual, true),
+            (2, Color::Green, Transmission::SemiAuto, false),
+            (3, Color::Red, Transmission::Automatic, true),
+            (4, Color::Silver, Transmission::SemiAuto, false),
+            (5, Color::Blue, Transmission::Manual, true),
+            (9, Color::Blue, Transmission::Automatic, true),
+        ];
+
+        for (order, color, motor, roof) in expected {
+            let car = CarOrder::legacy_assignment(order, 0).unwrap().build().unwrap();
+            assert_eq!(car.color, color, "order {}", order);
+            assert_eq!(car.motor, motor, "order {}", order);
+            assert_eq!(car.roof, roof, "order {}", order);
+        }
+    }
+
+    #[test]
+    fn legacy_assignment_rejects_order_zero_instead_of_panicking() {
+        assert_eq!(CarOrder::legacy_assignment(0, 0), Err(CarOrderError::InvalidOrderNumber(0)));
+        assert_eq!(CarOrder::legacy_assignment(-3, 0), Err(CarOrderError::InvalidOrderNumber(-3)));
+    }
+
+    #[test]
+    fn options_parse_case_insensitively() {
+        assert_eq!("blue".parse(), Ok(Color::Blue));
+        assert_eq!("SILVER".parse(), Ok(Color::Silver));
+        assert_eq!("Gray".parse(), Ok(Color::Silver));
+        assert_eq!("semi-auto".parse(), Ok(Transmission::SemiAuto));
+        assert_eq!("SEMI_AUTO".parse(), Ok(Transmission::SemiAuto));
+        assert_eq!("auto".parse(), Ok(Transmission::Automatic));
+        assert_eq!("used".parse(), Ok(Age::Used));
+    }
+
+    #[test]
+    fn parse_error_lists_the_valid_choices() {
+        let error = "purple".parse::<Color>().unwrap_err();
+        assert_eq!(error.to_string(), "'purple' is not a known color, expected one of: Blue, Green, Red, Silver");
+
+        let error = "cvt".parse::<Transmission>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "'cvt' is not a known transmission, expected one of: Manual, Semi-Auto, Automatic"
+        );
+    }
+
+    #[test]
+    fn display_round_trips_through_parse_and_serde() {
+        for color in Color::ALL {
+            assert_eq!(color.to_string().parse(), Ok(color));
+        }
+        for motor in Transmission::ALL {
+            assert_eq!(motor.to_string().parse(), Ok(motor));
+            let json = serde_json::to_string(&motor).unwrap();
+            assert_eq!(serde_json::from_str::<Transmission>(&json).unwrap(), motor);
+        }
+        assert_eq!(serde_json::to_string(&Age::New).unwrap(), "\"New\"");
+        assert_eq!(serde_json::from_str::<Color>("\"rEd\"").unwrap(), Color::Red);
+        assert!(serde_json::from_str::<Color>("\"teal\"").is_err());
+    }
+
+    #[test]
+    fn a_car_can_be_ordered_again() {
+        let car = CarOrder::new()
+            .make("Maserati")
+            .model("Folgore")
+            .year(2022)
+            .color(Color::Green)
+            .transmission(Transmission::Manual)
+            .roof(false)
+            .miles(12_000)
+            .certified(true)
+            .accidents(1)
+            .service_records(2)
+            .build()
+            .unwrap();
+
+        assert_eq!(CarOrder::from(&car).build(), Ok(car));
+    }
+
+    #[test]
+    fn a_car_with_a_history_is_used() {
+        let order = CarOrder::legacy_assignment(1, 0).unwrap();
+        assert_eq!(order.clone().build().unwrap().age.0, Age::New);
+
+        let car = order.year(2023).miles(12_000).accidents(1).service_records(2).build().unwrap();
+        assert_eq!(car.age, (Age::Used, 12_000));
+        assert_eq!(car.condition.model_year, Some(2023));
+        assert_eq!(car.condition.grade(2024), crate::condition::Grade::Good);
+    }
+
+    #[test]
+    fn build_rejects_options_that_dont_fit_together() {
+        let order = CarOrder::legacy_assignment(1, 0).unwrap();
+
+        assert!(order.clone().year(2025).build_at(2024).is_ok());
+        assert_eq!(
+            order.clone().year(2026).build_at(2024),
+            Err(CarOrderError::FutureModelYear { year: 2026, latest: 2025 })
+        );
+        assert!(order.clone().year(2026).build_at(2025).is_ok());
+        assert!(order.clone().miles(9).build().is_ok());
+        assert_eq!(order.clone().miles(10).build(), Err(CarOrderError::UsedWithoutYear { miles: 10 }));
+        assert_eq!(
+            order.clone().year(2023).accidents(1).build(),
+            Err(CarOrderError::HistoryOnNewCar { accidents: 1, service_records: 0 })
+        );
+        assert_eq!(
+            order.year(2023).miles(9).service_records(1).build(),
+            Err(CarOrderError::HistoryOnNewCar { accidents: 0, service_records: 1 })
+        );
+    }
+
+    #[test]
+    fn vin_needs_a_model_year_and_a_manufacturer_code_and_is_only_assigned_once() {
+        let mut car = CarOrder::legacy_assignment(1, 0).and_then(CarOrder::build).unwrap();
+        assert_eq!(car.assign_vin('M', 1), Err(VinError::MissingYear));
+
+        car.year = Some(2024);
+        assert_eq!(car.assign_vin('M', 1), Err(VinError::MissingWmi));
+        car.wmi = Some("ZAM".to_string());
+        let vin = car.assign_vin('M', 1).unwrap().clone();
+        assert_eq!(vin.decode().serial, 1);
+        assert_eq!(car.assign_vin('M', 2), Ok(&vin));
+    }
+}
@@ -0,0 +1,294 @@
+// The vehicle catalog: which makes and models the factory builds, and with which options
+
+/*
+    The catalog is a JSON file, read with serde the same way the journal is. Each make lists its
+    models, and each model lists the model years, colors, transmissions and roofs it's offered with,
+    plus its base price in whole dollars. An order is checked against the catalog with
+    CarOrder::build_against, which rejects anything the model doesn't offer.
+
+    Each make also has its WMI, the three-character world manufacturer identifier that starts the
+    VINs of its cars. No two makes may share one.
+ */
+
+use crate::car::{Color, Roof, Transmission};
+use crate::vin;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Catalog {
+    pub makes: Vec<Make>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Make {
+    pub name: String,
+    pub wmi: String,
+    pub models: Vec<Model>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Model {
+    pub name: String,
+    pub years: Vec<u16>,
+    pub colors: Vec<Color>,
+    pub transmissions: Vec<Transmission>,
+    pub roofs: Vec<Roof>,
+    pub base_price: u64,
+}
+
+/// Narrows down a catalog listing. Empty fields match everything.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CatalogFilter {
+    pub make: Option<String>,
+    pub year: Option<u16>,
+    pub color: Option<Color>,
+    pub transmission: Option<Transmission>,
+    pub roof: Option<Roof>,
+}
+
+impl CatalogFilter {
+    pub fn matches(&self, make: &Make, model: &Model) -> bool {
+        self.make.as_ref().is_none_or(|wanted| wanted.eq_ignore_ascii_case(&make.name))
+            && self.year.is_none_or(|year| model.years.contains(&year))
+            && self.color.is_none_or(|color| model.colors.contains(&color))
+            && self.transmission.is_none_or(|motor| model.transmissions.contains(&motor))
+            && self.roof.is_none_or(|roof| model.roofs.contains(&roof))
+    }
+}
+
+impl Catalog {
+    pub fn load(path: PathBuf) -> Result<Catalog> {
+        let file = File::open(path)?;
+        let catalog: Catalog = serde_json::from_reader(file)?;
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    // A model that can't be ordered in any color (or year, ...) is almost certainly a typo in the file
+    fn validate(&self) -> Result<()> {
+        for (index, make) in self.makes.iter().enumerate() {
+            if !vin::is_wmi(&make.wmi.to_ascii_uppercase()) {
+                let message = format!("{} has manufacturer code '{}', which isn't three VIN characters", make.name, make.wmi);
+                return Err(Error::new(ErrorKind::InvalidData, message));
+            }
+            if let Some(other) = self.makes[..index].iter().find(|other| other.wmi.eq_ignore_ascii_case(&make.wmi)) {
+                let message = format!("{} and {} share the manufacturer code {}", other.name, make.name, make.wmi);
+                return Err(Error::new(ErrorKind::InvalidData, message));
+            }
+            for model in &make.models {
+                let empty = [
+                    ("years", model.years.is_empty()),
+                    ("colors", model.colors.is_empty()),
+                    ("transmissions", model.transmissions.is_empty()),
+                    ("roofs", model.roofs.is_empty()),
+                ];
+                if let Some((field, _)) = empty.iter().find(|(_, is_empty)| *is_empty) {
+                    let message = format!("{} {} has no {} in the catalog", make.name, model.name, field);
+                    return Err(Error::new(ErrorKind::InvalidData, message));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks a model up by make and model name, ignoring case.
+    pub fn find(&self, make: &str, model: &str) -> Option<(&Make, &Model)> {
+        self.makes
+            .iter()
+            .filter(|candidate| candidate.name.eq_ignore_ascii_case(make.trim()))
+            .flat_map(|make| make.models.iter().map(move |candidate| (make, candidate)))
+            .find(|(_, candidate)| candidate.name.eq_ignore_ascii_case(model.trim()))
+    }
+
+    /// The make whose VINs start with this world manufacturer identifier.
+    pub fn manufacturer(&self, wmi: &str) -> Option<&Make> {
+        self.makes.iter().find(|make| make.wmi.eq_ignore_ascii_case(wmi.trim()))
+    }
+
+    /// Every model the filter matches, in catalog order.
+    pub fn search(&self, filter: &CatalogFilter) -> Vec<(&Make, &Model)> {
+        self.makes
+            .iter()
+            .flat_map(|make| make.models.iter().map(move |model| (make, model)))
+            .filter(|(make, model)| filter.matches(make, model))
+            .collect()
+    }
+}
+
+// One catalog line per model, as printed by the `catalog` subcommand
+pub struct Listing<'c>(pub &'c Make, pub &'c Model);
+
+impl fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Listing(make, model) = self;
+        let join = |items: Vec<String>| items.join(", ");
+        let years = match (model.years.iter().min(), model.years.iter().max()) {
+            (Some(first), Some(last)) if first != last => format!("{}-{}", first, last),
+            (Some(year), _) => year.to_string(),
+            _ => String::new(),
+        };
+        write!(
+            f,
+            "{:<24} {:<9} from ${:<8} colors: {} | transmissions: {} | roofs: {}",
+            format!("{} {}", make.name, model.name),
+            years,
+            model.base_price,
+            join(model.colors.iter().map(Color::to_string).collect()),
+            join(model.transmissions.iter().map(Transmission::to_string).collect()),
+            join(model.roofs.iter().map(Roof::to_string).collect())
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::{CarOrder, CarOrderError};
+
+    fn catalog() -> Catalog {
+        serde_json::from_str(
+            r#"{ "makes": [
+                { "name": "Maserati", "wmi": "ZAM", "models": [
+                    { "name": "Folgore", "years": [2023, 2024], "colors": ["Blue", "Silver"],
+                      "transmissions": ["Automatic"], "roofs": ["Hardtop", "Convertible"], "base_price": 135000 }
+                ] },
+                { "name": "Ferrari", "wmi": "ZFF", "models": [
+                    { "name": "Roma", "years": [2024], "colors": ["red", "silver"],
+                      "transmissions": ["Semi-Auto"], "roofs": ["Hardtop"], "base_price": 247000 }
+                ] }
+            ] }"#,
+        )
+        .unwrap()
+    }
+
+    fn folgore() -> CarOrder {
+        CarOrder::new()
+            .make("maserati")
+            .model("folgore")
+            .year(2024)
+            .color(Color::Silver)
+            .transmission(Transmission::Automatic)
+            .roof(false)
+    }
+
+    #[test]
+    fn orders_offered_options() {
+        let car = folgore().build_against(&catalog()).unwrap();
+        assert_eq!(car.make(), "Maserati");
+        assert_eq!(car.model(), "Folgore");
+        assert_eq!(car.year, Some(2024));
+        assert_eq!(car.wmi.as_deref(), Some("ZAM"));
+    }
+
+    #[test]
+    fn offered_years_can_still_be_in_the_future() {
+        assert!(folgore().build_against_at(&catalog(), 2023).is_ok());
+        assert_eq!(
+            folgore().build_against_at(&catalog(), 2022),
+            Err(CarOrderError::FutureModelYear { year: 2024, latest: 2023 })
+        );
+    }
+
+    #[test]
+    fn rejects_options_the_model_does_not_offer() {
+        let catalog = catalog();
+
+        assert_eq!(
+            folgore().model("Ghibli").build_against(&catalog),
+            Err(CarOrderError::UnknownModel { make: "maserati".to_string(), model: "Ghibli".to_string() })
+        );
+        assert_eq!(
+            folgore().year(2019).build_against(&catalog),
+            Err(CarOrderError::YearNotOffered {
+                model: "Maserati Folgore".to_string(),
+                year: 2019,
+                offered: vec![2023, 2024],
+            })
+        );
+        assert!(matches!(
+            folgore().transmission(Transmission::Manual).build_against(&catalog),
+            Err(CarOrderError::TransmissionNotOffered { motor: Transmission::Manual, .. })
+        ));
+        assert!(matches!(
+            folgore().color(Color::Red).build_against(&catalog),
+            Err(CarOrderError::ColorNotOffered { color: Color::Red, .. })
+        ));
+        assert!(matches!(
+            CarOrder::new().make("Ferrari").model("Roma").year(2024).color(Color::Red)
+                .transmission(Transmission::SemiAuto).roof(false).build_against(&catalog),
+            Err(CarOrderError::RoofNotOffered { roof: Roof::Convertible, .. })
+        ));
+        assert_eq!(
+            CarOrder::new().make("Ferrari").model("Roma").build_against(&catalog),
+            Err(CarOrderError::MissingOption("model year"))
+        );
+    }
+
+    #[test]
+    fn catalog_limits_colors_per_model() {
+        let catalog = catalog();
+        let order = folgore().model("FOLGORE");
+
+        assert!(order.clone().color(Color::Blue).build_against(&catalog).is_ok());
+        assert_eq!(
+            order.color(Color::Red).build_against(&catalog),
+            Err(CarOrderError::ColorNotOffered {
+                model: "Maserati Folgore".to_string(),
+                color: Color::Red,
+                offered: vec![Color::Blue, Color::Silver],
+            })
+        );
+        // Colors are matched whatever case the catalog spells them in
+        let roma = CarOrder::new().make("Ferrari").model("Roma").year(2024).transmission(Transmission::SemiAuto).roof(true);
+        assert!(roma.clone().color(Color::Red).build_against(&catalog).is_ok());
+        assert!(roma.color(Color::Blue).build_against(&catalog).is_err());
+    }
+
+    #[test]
+    fn search_filters_by_make_and_options() {
+        let catalog = catalog();
+        let names = |filter: CatalogFilter| -> Vec<String> {
+            catalog.search(&filter).iter().map(|(_, model)| model.name.clone()).collect()
+        };
+
+        assert_eq!(names(CatalogFilter::default()), vec!["Folgore", "Roma"]);
+        assert_eq!(names(CatalogFilter { make: Some("FERRARI".to_string()), ..Default::default() }), vec!["Roma"]);
+        assert_eq!(names(CatalogFilter { color: Some(Color::Silver), ..Default::default() }), vec!["Folgore", "Roma"]);
+        assert_eq!(names(CatalogFilter { roof: Some(Roof::Convertible), ..Default::default() }), vec!["Folgore"]);
+        assert!(names(CatalogFilter { year: Some(2023), color: Some(Color::Red), ..Default::default() }).is_empty());
+    }
+
+    #[test]
+    fn sample_catalog_file_loads() {
+        let catalog = Catalog::load(PathBuf::from("catalog.json")).unwrap();
+        assert!(catalog.find("Maserati", "Folgore").is_some());
+    }
+
+    #[test]
+    fn catalog_with_empty_option_list_is_rejected() {
+        let mut catalog = catalog();
+        catalog.makes[1].models[0].colors.clear();
+        let error = catalog.validate().unwrap_err();
+        assert_eq!(error.to_string(), "Ferrari Roma has no colors in the catalog");
+    }
+
+    #[test]
+    fn makes_need_their_own_manufacturer_code() {
+        let catalog = catalog();
+        assert_eq!(catalog.manufacturer("zff").map(|make| make.name.as_str()), Some("Ferrari"));
+        assert!(catalog.manufacturer("ZAR").is_none());
+
+        let mut invalid = catalog.clone();
+        invalid.makes[1].wmi = "ZO".to_string();
+        assert_eq!(invalid.validate().unwrap_err().to_string(), "Ferrari has manufacturer code 'ZO', which isn't three VIN characters");
+
+        let mut shared = catalog;
+        shared.makes[1].wmi = "zam".to_string();
+        assert_eq!(shared.validate().unwrap_err().to_string(), "Maserati and Ferrari share the manufacturer code zam");
+    }
+}
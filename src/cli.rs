@@ -4,6 +4,11 @@
     We'll use a third-party crate called structopt to make this task as easy as defining a struct.
  */
 
+use crate::api_key::Scope;
+use crate::car::{Age, Color, Roof, Transmission};
+use crate::format::OutputFormat;
+use crate::inventory::OrderState;
+use crate::money::Currency;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -21,7 +26,216 @@ pub enum Action {
         position: usize,
     },
     /// List all tasks in the journal file.
+    List {
+        /// How to print the tasks: text, json or csv.
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Manage API keys for automation accounts.
+    Key(KeyAction),
+    /// Check that no entry in the audit log was edited or removed.
+    VerifyAudit,
+    /// Browse the vehicle catalog.
+    Catalog {
+        /// Only show models from this make.
+        #[structopt(long)]
+        make: Option<String>,
+        /// Only show models offered for this model year.
+        #[structopt(long)]
+        year: Option<u16>,
+        /// Only show models offered in this color.
+        #[structopt(long)]
+        color: Option<Color>,
+        /// Only show models offered with this transmission.
+        #[structopt(long)]
+        transmission: Option<Transmission>,
+        /// Only show models offered with this roof (hardtop or convertible).
+        #[structopt(long)]
+        roof: Option<Roof>,
+    },
+    /// Place and follow car orders.
+    Car(CarAction),
+    /// Manage the customers quotes are made out to.
+    Customer(CustomerAction),
+    /// Generate, price and place random car orders in parallel, and report what they add up to.
+    Simulate {
+        /// How many orders to generate.
+        #[structopt(long, default_value = "1000000")]
+        orders: u64,
+        /// Runs with the same seed give the same results.
+        #[structopt(long, default_value = "42")]
+        seed: u64,
+        /// Share of orders for new cars, in percent.
+        #[structopt(long, default_value = "60")]
+        new_percent: u32,
+        /// Number of threads to use. Defaults to one per core.
+        #[structopt(long)]
+        threads: Option<usize>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum KeyAction {
+    /// Create a key. It is printed once and cannot be shown again.
+    Create {
+        /// A name to recognise the key by, e.g. the bot using it.
+        #[structopt()]
+        name: String,
+        /// What the key may do: journal:read or journal:write. Repeat for several scopes.
+        #[structopt(short, long = "scope", required = true)]
+        scopes: Vec<Scope>,
+        /// Number of days until the key expires. Keys without it never expire.
+        #[structopt(long)]
+        expires_in_days: Option<i64>,
+    },
+    /// List all keys, including revoked ones.
+    List,
+    /// Revoke a key by id.
+    Revoke {
+        #[structopt()]
+        id: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum CarAction {
+    /// Place an order for a car from the catalog.
+    Order {
+        #[structopt(long)]
+        make: String,
+        #[structopt(long)]
+        model: String,
+        #[structopt(long)]
+        year: u16,
+        #[structopt(long)]
+        color: Color,
+        #[structopt(long)]
+        transmission: Transmission,
+        /// Hardtop or convertible.
+        #[structopt(long)]
+        roof: Roof,
+        /// Miles already on the car. Cars with 10 miles or more are sold as used.
+        #[structopt(long, default_value = "0")]
+        miles: u32,
+        /// Sell the car as certified pre-owned.
+        #[structopt(long)]
+        certified: bool,
+        /// Accidents on the car's record.
+        #[structopt(long, default_value = "0")]
+        accidents: u32,
+        /// Service records that come with the car.
+        #[structopt(long, default_value = "0")]
+        service_records: u32,
+    },
+    /// List orders, optionally narrowed down by state, color or age.
+    List {
+        /// How to print the orders: text, json or csv.
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+        /// Only show orders in this state: placed, built, shipped, delivered or cancelled.
+        #[structopt(long)]
+        state: Option<OrderState>,
+        #[structopt(long)]
+        color: Option<Color>,
+        /// Only show new or used cars.
+        #[structopt(long)]
+        age: Option<Age>,
+        /// Write the list to this file instead of the terminal.
+        #[structopt(parse(from_os_str), long)]
+        output: Option<PathBuf>,
+    },
+    /// Show an order and the history of its states.
+    Show {
+        #[structopt()]
+        id: u32,
+    },
+    /// Cancel an order that hasn't shipped yet.
+    Cancel {
+        #[structopt()]
+        id: u32,
+    },
+    /// Check a VIN and show what it says about the car.
+    Vin {
+        #[structopt()]
+        vin: String,
+    },
+    /// Count orders by model, color and transmission.
+    Report {
+        /// How to print the report: text, json or csv.
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+        /// Write the report to this file instead of the terminal.
+        #[structopt(parse(from_os_str), long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum CustomerAction {
+    /// Register a customer.
+    Add {
+        #[structopt()]
+        name: String,
+        /// Where invoices go.
+        #[structopt(long)]
+        address: String,
+        #[structopt(long)]
+        tax_id: String,
+        /// The currency the customer pays in. Their quotes are converted into it.
+        #[structopt(long, default_value = "USD")]
+        currency: Currency,
+        /// A standing discount in percent, e.g. 5 or 2.5, for requests that don't bring their own.
+        #[structopt(long, parse(try_from_str = parse_basis_points))]
+        discount_percent: Option<i64>,
+        /// What the standing discount is called on quotes.
+        #[structopt(long, default_value = "Customer discount")]
+        discount_label: String,
+    },
+    /// List all customers.
     List,
+    /// Show a customer's details.
+    Show {
+        #[structopt()]
+        id: i32,
+    },
+    /// Change a customer's details. Only the given fields change.
+    Edit {
+        #[structopt()]
+        id: i32,
+        #[structopt(long)]
+        name: Option<String>,
+        #[structopt(long)]
+        address: Option<String>,
+        #[structopt(long)]
+        tax_id: Option<String>,
+        #[structopt(long)]
+        currency: Option<Currency>,
+        /// A new standing discount in percent.
+        #[structopt(long, parse(try_from_str = parse_basis_points), conflicts_with = "no-discount")]
+        discount_percent: Option<i64>,
+        #[structopt(long, default_value = "Customer discount")]
+        discount_label: String,
+        /// Remove the standing discount.
+        #[structopt(long)]
+        no_discount: bool,
+    },
+    /// List a customer's quotes, oldest first.
+    Quotes {
+        #[structopt()]
+        id: i32,
+    },
+}
+
+// "5", "2.5" or "12.25" percent as basis points
+fn parse_basis_points(text: &str) -> Result<i64, String> {
+    let invalid = || format!("'{}' is not a percentage like 5 or 2.5", text);
+    let (whole, fraction) = text.trim().split_once('.').unwrap_or((text.trim(), ""));
+    if whole.is_empty() || fraction.len() > 2 || !(whole.chars().chain(fraction.chars())).all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let whole: i64 = whole.parse().map_err(|_| invalid())?;
+    let fraction: i64 = format!("{:0<2}", fraction).parse().map_err(|_| invalid())?;
+    whole.checked_mul(100).and_then(|bp| bp.checked_add(fraction)).ok_or_else(invalid)
 }
 
 #[derive(Debug, StructOpt)]
@@ -36,4 +250,32 @@ pub struct CommandLineArgs {
     /// Use a different journal file.
     #[structopt(parse(from_os_str), short, long)]
     pub journal_file: Option<PathBuf>,
+
+    /// Use a different API key file.
+    #[structopt(parse(from_os_str), long, default_value = "api-keys.json")]
+    pub api_key_file: PathBuf,
+
+    /// Use a different vehicle catalog file.
+    #[structopt(parse(from_os_str), long, default_value = "catalog.json")]
+    pub catalog_file: PathBuf,
+
+    /// Use a different car order inventory file.
+    #[structopt(parse(from_os_str), long, default_value = "inventory.json")]
+    pub inventory_file: PathBuf,
+
+    /// Use a different customer file.
+    #[structopt(parse(from_os_str), long, default_value = "customers.json")]
+    pub customers_file: PathBuf,
+
+    /// Use a different quote file.
+    #[structopt(parse(from_os_str), long, default_value = "quotes.json")]
+    pub quotes_file: PathBuf,
+
+    /// Use a different audit log file.
+    #[structopt(parse(from_os_str), long, default_value = "journal-audit.jsonl")]
+    pub audit_file: PathBuf,
+
+    /// Authenticate with an API key instead of as the local user.
+    #[structopt(long, env = "RUSTY_JOURNAL_API_KEY", hide_env_values = true)]
+    pub api_key: Option<String>,
 }
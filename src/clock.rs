@@ -0,0 +1,59 @@
+// A clock is anything that can tell us the current moment in time.
+
+/*
+    Code that depends on the current time is hard to test: calling Utc::now() directly means a test
+    that needs "fifteen minutes later" has to actually wait fifteen minutes. Instead, we pass a Clock
+    trait object (or a generic C: Clock) into the code that needs the time. Production code uses
+    SystemClock, and tests use FakeClock, which only moves when the test tells it to.
+ */
+
+use chrono::{DateTime, Duration, Utc};
+use std::cell::Cell;
+
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real wall clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that stands still until it is told to move. Intended for tests.
+#[derive(Debug)]
+pub struct FakeClock {
+    // Cell gives us interior mutability, so advance() can take &self like Clock::now() does
+    now: Cell<DateTime<Utc>>,
+}
+
+impl FakeClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        FakeClock { now: Cell::new(now) }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.now.get()
+    }
+}
+
+// A shared reference to a clock is also a clock, so callers can keep ownership of a FakeClock
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
@@ -0,0 +1,277 @@
+// Grades the condition of a car from its mileage, model year, accident and service history
+
+/*
+    A car is New only while it has delivery mileage (under 10 miles) and no accident on record;
+    anything else is Used and gets a grade from Excellent down to Poor. Each rule below is its own
+    small function so it can be tested on its own:
+
+      mileage band     sets the starting grade: Low miles start at Excellent, Very High at Poor
+      model year       older cars can't grade better than their age allows, however few the miles
+      accidents        one accident costs a grade, two or more cost two
+      service records  one record is expected per 10,000 miles; a gap costs a grade, none at all two
+      certification    a certified pre-owned car gains a grade (never above Excellent), but only if
+                       it actually qualifies: at most 6 years old, under 75,000 miles, no accidents
+                       and a full service history
+
+    Grading a used car needs the current year for the model year rule, so grade() takes it as an
+    argument instead of reading the clock.
+ */
+
+use crate::car::Age;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MileageBand {
+    /// Under 10 miles, the miles a car picks up between the factory and the showroom.
+    Delivery,
+    /// Under 15,000 miles.
+    Low,
+    /// Under 60,000 miles.
+    Average,
+    /// Under 120,000 miles.
+    High,
+    VeryHigh,
+}
+
+impl MileageBand {
+    pub fn of(miles: u32) -> Self {
+        match miles {
+            0..=9 => MileageBand::Delivery,
+            10..=14_999 => MileageBand::Low,
+            15_000..=59_999 => MileageBand::Average,
+            60_000..=119_999 => MileageBand::High,
+            _ => MileageBand::VeryHigh,
+        }
+    }
+}
+
+/// Best first: a later grade is a worse one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum Grade {
+    New,
+    Excellent,
+    Good,
+    Fair,
+    Poor,
+}
+
+impl Grade {
+    const USED: [Grade; 4] = [Grade::Excellent, Grade::Good, Grade::Fair, Grade::Poor];
+
+    // Moves a used grade by `steps` (positive is worse), staying within Excellent..=Poor
+    fn shift(self, steps: i32) -> Grade {
+        let index = Grade::USED.iter().position(|grade| *grade == self).unwrap_or(0) as i32;
+        Grade::USED[(index + steps).clamp(0, Grade::USED.len() as i32 - 1) as usize]
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Grade::New => "New",
+            Grade::Excellent => "Excellent",
+            Grade::Good => "Good",
+            Grade::Fair => "Fair",
+            Grade::Poor => "Poor",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Condition {
+    pub miles: u32,
+    pub model_year: Option<u16>,
+    /// Sold as certified pre-owned. Only counts if the car qualifies, see certifiable().
+    pub certified: bool,
+    pub accidents: u32,
+    pub service_records: u32,
+}
+
+impl Condition {
+    pub fn new(miles: u32, model_year: Option<u16>) -> Self {
+        Condition { miles, model_year, ..Condition::default() }
+    }
+
+    pub fn mileage_band(&self) -> MileageBand {
+        MileageBand::of(self.miles)
+    }
+
+    pub fn age(&self) -> Age {
+        if self.mileage_band() == MileageBand::Delivery && self.accidents == 0 {
+            Age::New
+        } else {
+            Age::Used
+        }
+    }
+
+    /// Whether the car meets the certified pre-owned requirements in `as_of_year`.
+    pub fn certifiable(&self, as_of_year: u16) -> bool {
+        self.model_year.is_some_and(|year| as_of_year.saturating_sub(year) <= 6)
+            && self.miles < 75_000
+            && self.accidents == 0
+            && service_penalty(self.miles, self.service_records) == 0
+    }
+
+    pub fn grade(&self, as_of_year: u16) -> Grade {
+        if self.age() == Age::New {
+            return Grade::New;
+        }
+
+        let start = mileage_grade(self.mileage_band()).max(year_cap(self.model_year, as_of_year));
+        let penalty = accident_penalty(self.accidents) + service_penalty(self.miles, self.service_records);
+        let bonus = if self.certified && self.certifiable(as_of_year) { 1 } else { 0 };
+        start.shift(penalty as i32 - bonus)
+    }
+}
+
+fn mileage_grade(band: MileageBand) -> Grade {
+    match band {
+        // A car with delivery miles that isn't new has been in an accident; it starts like a low-mileage one
+        MileageBand::Delivery | MileageBand::Low => Grade::Excellent,
+        MileageBand::Average => Grade::Good,
+        MileageBand::High => Grade::Fair,
+        MileageBand::VeryHigh => Grade::Poor,
+    }
+}
+
+// The best grade a car of this model year can get. A car without a model year is taken to be middle-aged.
+fn year_cap(model_year: Option<u16>, as_of_year: u16) -> Grade {
+    match model_year.map(|year| as_of_year.saturating_sub(year)) {
+        Some(0..=3) => Grade::Excellent,
+        Some(4..=7) | None => Grade::Good,
+        Some(8..=12) => Grade::Fair,
+        Some(_) => Grade::Poor,
+    }
+}
+
+fn accident_penalty(accidents: u32) -> u32 {
+    accidents.min(2)
+}
+
+fn service_penalty(miles: u32, service_records: u32) -> u32 {
+    let expected = miles / 10_000;
+    if service_records >= expected {
+        0
+    } else if service_records == 0 && expected >= 2 {
+        2
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THIS_YEAR: u16 = 2024;
+
+    fn used(miles: u32, model_year: u16) -> Condition {
+        Condition { service_records: miles / 10_000, ..Condition::new(miles, Some(model_year)) }
+    }
+
+    #[test]
+    fn mileage_bands() {
+        let cases = [
+            (0, MileageBand::Delivery),
+            (9, MileageBand::Delivery),
+            (10, MileageBand::Low),
+            (14_999, MileageBand::Low),
+            (15_000, MileageBand::Average),
+            (60_000, MileageBand::High),
+            (119_999, MileageBand::High),
+            (120_000, MileageBand::VeryHigh),
+        ];
+        for (miles, band) in cases {
+            assert_eq!(MileageBand::of(miles), band, "{} miles", miles);
+        }
+    }
+
+    #[test]
+    fn new_means_delivery_miles_and_no_accidents() {
+        let cases = [(0, 0, Age::New), (9, 0, Age::New), (10, 0, Age::Used), (5, 1, Age::Used)];
+        for (miles, accidents, age) in cases {
+            let condition = Condition { accidents, ..Condition::new(miles, Some(THIS_YEAR)) };
+            assert_eq!(condition.age(), age, "{} miles, {} accidents", miles, accidents);
+            assert_eq!(condition.grade(THIS_YEAR) == Grade::New, age == Age::New);
+        }
+    }
+
+    #[test]
+    fn mileage_sets_the_starting_grade() {
+        let cases = [
+            (12_000, Grade::Excellent),
+            (40_000, Grade::Good),
+            (90_000, Grade::Fair),
+            (150_000, Grade::Poor),
+        ];
+        for (miles, grade) in cases {
+            assert_eq!(used(miles, THIS_YEAR).grade(THIS_YEAR), grade, "{} miles", miles);
+        }
+    }
+
+    #[test]
+    fn model_year_caps_the_grade() {
+        let cases = [
+            (Some(2024), Grade::Excellent),
+            (Some(2021), Grade::Excellent),
+            (Some(2020), Grade::Good),
+            (Some(2017), Grade::Good),
+            (Some(2016), Grade::Fair),
+            (Some(2012), Grade::Fair),
+            (Some(2011), Grade::Poor),
+            (None, Grade::Good),
+        ];
+        for (model_year, grade) in cases {
+            assert_eq!(year_cap(model_year, THIS_YEAR), grade, "{:?}", model_year);
+            let condition = Condition { model_year, ..used(100, THIS_YEAR) };
+            assert_eq!(condition.grade(THIS_YEAR), grade, "{:?}", model_year);
+        }
+    }
+
+    #[test]
+    fn accidents_lower_the_grade() {
+        let cases = [(0, Grade::Excellent), (1, Grade::Good), (2, Grade::Fair), (5, Grade::Fair)];
+        for (accidents, grade) in cases {
+            let condition = Condition { accidents, ..used(12_000, THIS_YEAR) };
+            assert_eq!(condition.grade(THIS_YEAR), grade, "{} accidents", accidents);
+        }
+    }
+
+    #[test]
+    fn missing_service_records_lower_the_grade() {
+        let cases = [
+            (5_000, 0, 0),
+            (30_000, 3, 0),
+            (30_000, 5, 0),
+            (30_000, 2, 1),
+            (30_000, 0, 2),
+            (12_000, 0, 1),
+        ];
+        for (miles, records, penalty) in cases {
+            assert_eq!(service_penalty(miles, records), penalty, "{} miles, {} records", miles, records);
+        }
+        let neglected = Condition { service_records: 0, ..used(40_000, THIS_YEAR) };
+        assert_eq!(neglected.grade(THIS_YEAR), Grade::Poor);
+    }
+
+    #[test]
+    fn certification_raises_the_grade_only_when_the_car_qualifies() {
+        let cases = [
+            // miles, model year, accidents, certifiable, grade when certified
+            (40_000, 2022, 0, true, Grade::Excellent),
+            (12_000, 2023, 0, true, Grade::Excellent),
+            (70_000, 2019, 0, true, Grade::Good),
+            (80_000, 2022, 0, false, Grade::Fair),
+            (40_000, 2017, 0, false, Grade::Good),
+            (40_000, 2022, 1, false, Grade::Fair),
+        ];
+        for (miles, model_year, accidents, certifiable, grade) in cases {
+            let condition = Condition { certified: true, accidents, ..used(miles, model_year) };
+            assert_eq!(condition.certifiable(THIS_YEAR), certifiable, "{} miles, {}", miles, model_year);
+            assert_eq!(condition.grade(THIS_YEAR), grade, "{} miles, {}", miles, model_year);
+        }
+    }
+}
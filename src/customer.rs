@@ -0,0 +1,307 @@
+// The customers quotes are made out to: who they are, where to bill them and what they get by default
+
+/*
+    Customers get ids from 1 up, in the order they are added, and keep them for good; a
+    PostRequest's customer_id is one of these ids. Besides the name, billing address and tax ID
+    that go on their documents, a customer has two defaults the quote service picks up:
+
+      - the currency they pay in, which their quotes are converted into, and
+      - a standing discount, used when a request doesn't bring any discounts of its own.
+
+    Tax IDs are unique. They are compared without spaces, dashes or dots and ignoring case, so
+    "DE 123-456" and "de123456" are the same ID.
+
+    The registry is saved to a JSON file and replaced atomically on every change.
+ */
+
+use crate::money::Currency;
+use crate::pricing::Discount;
+use crate::storage::write_json_atomically;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Customer {
+    pub id: i32,
+    pub name: String,
+    pub billing_address: String,
+    pub tax_id: String,
+    /// The currency the customer pays in.
+    #[serde(default)]
+    pub currency: Currency,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_discount: Option<Discount>,
+}
+
+impl Customer {
+    /// A customer paying in US dollars with no standing discount. The registry gives them an id
+    /// when they are added.
+    pub fn new(name: &str, billing_address: &str, tax_id: &str) -> Self {
+        Customer {
+            id: 0,
+            name: name.trim().to_string(),
+            billing_address: billing_address.trim().to_string(),
+            tax_id: tax_id.trim().to_string(),
+            currency: Currency::default(),
+            default_discount: None,
+        }
+    }
+
+    pub fn paying_in(mut self, currency: Currency) -> Self {
+        self.currency = currency;
+        self
+    }
+
+    pub fn with_discount(mut self, discount: Discount) -> Self {
+        self.default_discount = Some(discount);
+        self
+    }
+
+    /// The standing discount as a short description, e.g. "Fleet 5.00%" or "Goodwill $100.00".
+    pub fn discount_summary(&self) -> String {
+        match &self.default_discount {
+            None => "-".to_string(),
+            Some(Discount::Percent { label, basis_points }) => {
+                format!("{} {}.{:02}%", label, basis_points / 100, basis_points % 100)
+            }
+            Some(Discount::Fixed { label, amount }) => format!("{} {}", label, amount),
+        }
+    }
+}
+
+impl fmt::Display for Customer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{:<5} {:<28} {:<16} {:<4} {}",
+            self.id,
+            self.name,
+            self.tax_id,
+            self.currency,
+            self.discount_summary()
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CustomerError {
+    UnknownCustomer(i32),
+    Invalid(String),
+    DuplicateTaxId { tax_id: String, customer: i32 },
+    Storage(String),
+}
+
+impl fmt::Display for CustomerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CustomerError::UnknownCustomer(id) => write!(f, "there is no customer #{}", id),
+            CustomerError::Invalid(reason) => write!(f, "{}", reason),
+            CustomerError::DuplicateTaxId { tax_id, customer } => {
+                write!(f, "tax ID {} already belongs to customer #{}", tax_id, customer)
+            }
+            CustomerError::Storage(error) => write!(f, "customer file: {}", error),
+        }
+    }
+}
+
+impl From<std::io::Error> for CustomerError {
+    fn from(error: std::io::Error) -> Self {
+        CustomerError::Storage(error.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CustomerRegistry {
+    customers: Vec<Customer>,
+    path: Option<PathBuf>,
+}
+
+impl CustomerRegistry {
+    /// A registry that only lives in memory.
+    pub fn new() -> Self {
+        CustomerRegistry::default()
+    }
+
+    /// A registry backed by a JSON file. Customers already in the file are loaded.
+    pub fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let customers = match serde_json::from_reader(file) {
+            Ok(customers) => customers,
+            Err(e) if e.is_eof() => Vec::new(),
+            Err(e) => Err(e)?,
+        };
+
+        Ok(CustomerRegistry { customers, path: Some(path) })
+    }
+
+    /// Adds a customer and returns the id they were given.
+    pub fn add(&mut self, mut customer: Customer) -> Result<i32, CustomerError> {
+        customer.id = self.customers.iter().map(|customer| customer.id).max().unwrap_or(0) + 1;
+        self.check(&customer)?;
+        let id = customer.id;
+        self.customers.push(customer);
+        self.save()?;
+        Ok(id)
+    }
+
+    /// Replaces the details of the customer with the same id.
+    pub fn update(&mut self, customer: Customer) -> Result<&Customer, CustomerError> {
+        let index = self
+            .customers
+            .iter()
+            .position(|existing| existing.id == customer.id)
+            .ok_or(CustomerError::UnknownCustomer(customer.id))?;
+        self.check(&customer)?;
+        self.customers[index] = customer;
+        self.save()?;
+        Ok(&self.customers[index])
+    }
+
+    pub fn get(&self, id: i32) -> Option<&Customer> {
+        self.customers.iter().find(|customer| customer.id == id)
+    }
+
+    pub fn list(&self) -> &[Customer] {
+        &self.customers
+    }
+
+    pub fn len(&self) -> usize {
+        self.customers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.customers.is_empty()
+    }
+
+    fn check(&self, customer: &Customer) -> Result<(), CustomerError> {
+        let invalid = |reason: &str| Err(CustomerError::Invalid(reason.to_string()));
+        if customer.name.trim().is_empty() {
+            return invalid("a customer needs a name");
+        }
+        if customer.billing_address.trim().is_empty() {
+            return invalid("a customer needs a billing address");
+        }
+        if normalized_tax_id(&customer.tax_id).is_empty() {
+            return invalid("a customer needs a tax ID");
+        }
+        match &customer.default_discount {
+            Some(Discount::Percent { basis_points, .. }) if !(0..=10_000).contains(basis_points) => {
+                return invalid("a standing discount must be between 0% and 100%");
+            }
+            Some(Discount::Fixed { amount, .. }) if amount.is_negative() => {
+                return invalid("a standing discount can't be negative");
+            }
+            _ => {}
+        }
+
+        let tax_id = normalized_tax_id(&customer.tax_id);
+        match self.customers.iter().find(|other| other.id != customer.id && normalized_tax_id(&other.tax_id) == tax_id) {
+            Some(other) => Err(CustomerError::DuplicateTaxId { tax_id: customer.tax_id.clone(), customer: other.id }),
+            None => Ok(()),
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            write_json_atomically(path, &self.customers)?;
+        }
+        Ok(())
+    }
+}
+
+fn normalized_tax_id(tax_id: &str) -> String {
+    tax_id
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '.'))
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Money;
+
+    fn acme() -> Customer {
+        Customer::new("Acme Rentals", "1 Main St, Springfield", "US 12-3456789")
+    }
+
+    #[test]
+    fn customers_get_ids_in_order() {
+        let mut registry = CustomerRegistry::new();
+        let first = registry.add(acme()).unwrap();
+        let second = registry
+            .add(Customer::new("Bauer GmbH", "Hauptstr. 5, Berlin", "DE123456789").paying_in(Currency::EUR))
+            .unwrap();
+
+        assert_eq!((first, second), (1, 2));
+        assert_eq!(registry.get(2).unwrap().currency, Currency::EUR);
+        assert_eq!(registry.get(1).unwrap().currency, Currency::USD);
+        assert!(registry.get(3).is_none());
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn incomplete_customers_are_refused() {
+        let mut registry = CustomerRegistry::new();
+
+        assert!(matches!(registry.add(Customer::new(" ", "1 Main St", "123")), Err(CustomerError::Invalid(_))));
+        assert!(matches!(registry.add(Customer::new("Acme", "", "123")), Err(CustomerError::Invalid(_))));
+        assert!(matches!(registry.add(Customer::new("Acme", "1 Main St", " - ")), Err(CustomerError::Invalid(_))));
+        let generous = acme().with_discount(Discount::Percent { label: "Fleet".to_string(), basis_points: 12_000 });
+        assert!(matches!(registry.add(generous), Err(CustomerError::Invalid(_))));
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn tax_ids_are_unique_however_they_are_written() {
+        let mut registry = CustomerRegistry::new();
+        registry.add(acme()).unwrap();
+
+        assert_eq!(
+            registry.add(Customer::new("Acme Leasing", "2 Main St", "us123456789")),
+            Err(CustomerError::DuplicateTaxId { tax_id: "us123456789".to_string(), customer: 1 })
+        );
+    }
+
+    #[test]
+    fn updates_keep_the_id_and_are_checked() {
+        let mut registry = CustomerRegistry::new();
+        let id = registry.add(acme()).unwrap();
+        let other = registry.add(Customer::new("Bauer GmbH", "Hauptstr. 5, Berlin", "DE123456789")).unwrap();
+
+        let mut changed = registry.get(id).unwrap().clone();
+        changed.default_discount = Some(Discount::Fixed { label: "Goodwill".to_string(), amount: Money::from_dollars(100) });
+        assert_eq!(registry.update(changed).unwrap().discount_summary(), "Goodwill $100.00");
+
+        let mut clash = registry.get(other).unwrap().clone();
+        clash.tax_id = "US-12-3456789".to_string();
+        assert!(matches!(registry.update(clash), Err(CustomerError::DuplicateTaxId { customer: 1, .. })));
+        let mut stranger = acme();
+        stranger.id = 9;
+        assert_eq!(registry.update(stranger), Err(CustomerError::UnknownCustomer(9)));
+    }
+
+    #[test]
+    fn registry_survives_restart() {
+        let path = std::env::temp_dir().join(format!("customers-{}.json", uuid::Uuid::new_v4()));
+
+        let mut registry = CustomerRegistry::open(path.clone()).unwrap();
+        let fleet = Discount::Percent { label: "Fleet".to_string(), basis_points: 500 };
+        let id = registry.add(acme().paying_in(Currency::GBP).with_discount(fleet)).unwrap();
+
+        let reopened = CustomerRegistry::open(path.clone()).unwrap();
+        assert_eq!(reopened.get(id), registry.get(id));
+        assert_eq!(reopened.get(id).unwrap().discount_summary(), "Fleet 5.00%");
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
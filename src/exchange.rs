@@ -0,0 +1,350 @@
+// Converting quote totals between currencies with exchange-rate tables kept in a local file
+
+/*
+    Rates are never fetched from the network. The rates file holds one table per effective date,
+    each quoting every currency against the same base currency:
+
+        { "base": "USD",
+          "tables": [
+            { "effective": "2023-06-01", "rates": { "EUR": "0.921400", "JPY": "139.480000" } },
+            { "effective": "2023-07-01", "rates": { "EUR": "0.916500", "JPY": "144.310000" } } ] }
+
+    "EUR": "0.921400" means one US dollar buys 0.9214 euros. Rates are exact decimals with up to six
+    places, read as whole millionths, so no floating point is involved. A conversion on a given day
+    uses the latest table that took effect on or before that day.
+
+    Converting between two currencies that aren't the base goes through the base without rounding
+    in between: the ExchangeRate keeps both currencies' quotes from the table, and an amount is
+    multiplied by to_rate / from_rate in one step.
+
+    Rounding: each converted amount is rounded once, half even, to the target currency's minor
+    units. A quote's totals are converted figure by figure (subtotal, discount, tax) from the
+    quote's own amounts, and the converted total is the sum of the converted figures, so the
+    converted document still adds up. Nothing is ever converted from an already converted amount:
+    the quote keeps its items in its own currency and records the rate it was converted with, so
+    converting it back (or again, with the same rate) always gives exactly the same figures.
+ */
+
+use crate::money::{Currency, Money, MoneyError, Rounding};
+use crate::totals::Totals;
+use chrono::NaiveDate;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+
+/// An exchange rate as an exact decimal with six places, counted in millionths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rate(u64);
+
+impl Rate {
+    const PLACES: usize = 6;
+    pub const ONE: Rate = Rate(1_000_000);
+
+    pub fn from_millionths(millionths: u64) -> Option<Rate> {
+        (millionths > 0).then_some(Rate(millionths))
+    }
+
+    pub fn millionths(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&format!("{}.{:06}", self.0 / Rate::ONE.0, self.0 % Rate::ONE.0))
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("'{}' is not an exchange rate, expected a positive number with up to 6 decimals", s);
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+        let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty() || !digits(whole) || !digits(fraction) || fraction.len() > Rate::PLACES {
+            return Err(error());
+        }
+        let whole: u64 = whole.parse().map_err(|_| error())?;
+        let fraction: u64 = format!("{:0<6}", fraction).parse().map_err(|_| error())?;
+        whole
+            .checked_mul(Rate::ONE.0)
+            .and_then(|millionths| millionths.checked_add(fraction))
+            .and_then(Rate::from_millionths)
+            .ok_or_else(error)
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExchangeError {
+    /// No table had taken effect yet on that day.
+    NoTable { on: NaiveDate },
+    MissingRate { currency: Currency, effective: NaiveDate },
+    WrongCurrency { expected: Currency, found: Currency },
+    Money(MoneyError),
+    Invalid(String),
+    Storage(String),
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::NoTable { on } => write!(f, "no exchange rates were in effect on {}", on),
+            ExchangeError::MissingRate { currency, effective } => {
+                write!(f, "the exchange rates of {} have no rate for {}", effective, currency)
+            }
+            ExchangeError::WrongCurrency { expected, found } => {
+                write!(f, "the exchange rate converts from {}, not {}", expected, found)
+            }
+            ExchangeError::Money(error) => write!(f, "{}", error),
+            ExchangeError::Invalid(reason) => write!(f, "exchange rates file: {}", reason),
+            ExchangeError::Storage(error) => write!(f, "exchange rates file: {}", error),
+        }
+    }
+}
+
+impl From<std::io::Error> for ExchangeError {
+    fn from(error: std::io::Error) -> Self {
+        ExchangeError::Storage(error.to_string())
+    }
+}
+
+impl From<MoneyError> for ExchangeError {
+    fn from(error: MoneyError) -> Self {
+        ExchangeError::Money(error)
+    }
+}
+
+/// The rate an amount was (or will be) converted with, as it was quoted in the rates file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExchangeRate {
+    pub from: Currency,
+    pub to: Currency,
+    pub base: Currency,
+    /// The effective date of the table the rate came from.
+    pub effective: NaiveDate,
+    /// How much of `from` one unit of `base` buys.
+    pub from_rate: Rate,
+    /// How much of `to` one unit of `base` buys.
+    pub to_rate: Rate,
+}
+
+impl ExchangeRate {
+    /// Leaves amounts as they are.
+    pub fn identity(currency: Currency, on: NaiveDate) -> Self {
+        ExchangeRate { from: currency, to: currency, base: currency, effective: on, from_rate: Rate::ONE, to_rate: Rate::ONE }
+    }
+
+    pub fn inverse(&self) -> Self {
+        ExchangeRate { from: self.to, to: self.from, from_rate: self.to_rate, to_rate: self.from_rate, ..self.clone() }
+    }
+
+    /// The amount in the target currency, rounded half even to its minor units.
+    pub fn convert(&self, amount: Money) -> Result<Money, ExchangeError> {
+        if amount.currency() != self.from {
+            return Err(ExchangeError::WrongCurrency { expected: self.from, found: amount.currency() });
+        }
+        let scale = |currency: Currency| 10_i128.pow(currency.minor_units());
+        let numerator = self.to_rate.millionths() as i128 * scale(self.to);
+        let denominator = self.from_rate.millionths() as i128 * scale(self.from);
+        let converted = amount.checked_mul_ratio(numerator, denominator, Rounding::HalfEven)?;
+        Ok(Money::new(converted.minor_units(), self.to))
+    }
+}
+
+impl fmt::Display for ExchangeRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} at {} {} / {} {} per {} (rates of {})",
+            self.from, self.to, self.to_rate, self.to, self.from_rate, self.from, self.base, self.effective
+        )
+    }
+}
+
+/// A quote's totals in another currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertedTotals {
+    pub rate: ExchangeRate,
+    pub subtotal: Money,
+    /// A positive amount.
+    pub discount: Money,
+    pub tax: Money,
+    /// Always subtotal - discount + tax of the converted figures.
+    pub total: Money,
+}
+
+impl ConvertedTotals {
+    pub fn convert(totals: &Totals, rate: &ExchangeRate) -> Result<ConvertedTotals, ExchangeError> {
+        let subtotal = rate.convert(totals.subtotal)?;
+        let discount = rate.convert(totals.discount())?;
+        let tax = rate.convert(totals.tax)?;
+        let total = subtotal.checked_sub(discount)?.checked_add(tax)?;
+        Ok(ConvertedTotals { rate: rate.clone(), subtotal, discount, tax, total })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Table {
+    effective: NaiveDate,
+    rates: BTreeMap<Currency, Rate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateTables {
+    base: Currency,
+    tables: Vec<Table>,
+}
+
+impl RateTables {
+    pub fn load(path: &Path) -> Result<RateTables, ExchangeError> {
+        let file = File::open(path)?;
+        let tables: RateTables = serde_json::from_reader(file).map_err(|error| ExchangeError::Invalid(error.to_string()))?;
+        tables.validated()
+    }
+
+    pub fn base(&self) -> Currency {
+        self.base
+    }
+
+    /// The rate for converting `from` into `to` on the given day.
+    pub fn rate(&self, from: Currency, to: Currency, on: NaiveDate) -> Result<ExchangeRate, ExchangeError> {
+        if from == to {
+            return Ok(ExchangeRate::identity(from, on));
+        }
+        let table = self.tables.iter().rev().find(|table| table.effective <= on).ok_or(ExchangeError::NoTable { on })?;
+        let quote = |currency: Currency| match table.rates.get(&currency) {
+            Some(rate) => Ok(*rate),
+            None if currency == self.base => Ok(Rate::ONE),
+            None => Err(ExchangeError::MissingRate { currency, effective: table.effective }),
+        };
+        Ok(ExchangeRate { from, to, base: self.base, effective: table.effective, from_rate: quote(from)?, to_rate: quote(to)? })
+    }
+
+    // Sorts the tables by date, and refuses two tables for one date or a base that isn't worth one base
+    fn validated(mut self) -> Result<RateTables, ExchangeError> {
+        self.tables.sort_by_key(|table| table.effective);
+        if let Some(pair) = self.tables.windows(2).find(|pair| pair[0].effective == pair[1].effective) {
+            return Err(ExchangeError::Invalid(format!("two tables take effect on {}", pair[0].effective)));
+        }
+        for table in &self.tables {
+            if table.rates.get(&self.base).is_some_and(|rate| *rate != Rate::ONE) {
+                return Err(ExchangeError::Invalid(format!("the table of {} doesn't rate {} at 1", table.effective, self.base)));
+            }
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::totals::{LineItem, TaxRate};
+    use std::fs;
+
+    const RATES: &str = r#"{ "base": "USD", "tables": [
+        { "effective": "2023-07-01", "rates": { "EUR": "0.916500", "GBP": "0.786300", "JPY": "144.31" } },
+        { "effective": "2023-06-01", "rates": { "EUR": "0.9214", "JPY": "139.480000", "USD": "1" } }
+    ] }"#;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn tables() -> RateTables {
+        serde_json::from_str::<RateTables>(RATES).unwrap().validated().unwrap()
+    }
+
+    #[test]
+    fn rates_are_exact_decimals() {
+        assert_eq!("0.9214".parse(), Ok(Rate(921_400)));
+        assert_eq!("144.31".parse(), Ok(Rate(144_310_000)));
+        assert_eq!(Rate(921_400).to_string(), "0.921400");
+        for bad in ["0", "-1", "1.1234567", ".5", "1e3", ""] {
+            assert!(bad.parse::<Rate>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn uses_the_latest_table_in_effect() {
+        let tables = tables();
+        let june = tables.rate(Currency::USD, Currency::EUR, date(2023, 6, 30)).unwrap();
+        assert_eq!((june.effective, june.to_rate), (date(2023, 6, 1), Rate(921_400)));
+        let july = tables.rate(Currency::USD, Currency::EUR, date(2023, 7, 1)).unwrap();
+        assert_eq!((july.effective, july.to_rate), (date(2023, 7, 1), Rate(916_500)));
+
+        assert_eq!(tables.rate(Currency::USD, Currency::EUR, date(2023, 5, 31)), Err(ExchangeError::NoTable { on: date(2023, 5, 31) }));
+        assert_eq!(
+            tables.rate(Currency::USD, Currency::GBP, date(2023, 6, 2)),
+            Err(ExchangeError::MissingRate { currency: Currency::GBP, effective: date(2023, 6, 1) })
+        );
+    }
+
+    #[test]
+    fn converts_with_half_even_rounding() {
+        let tables = tables();
+        let to_dollars = tables.rate(Currency::EUR, Currency::USD, date(2023, 6, 1)).unwrap();
+        // 100 / 0.9214 = 108.53049...
+        assert_eq!(to_dollars.convert(Money::new(10_000, Currency::EUR)), Ok(Money::from_cents(10_853)));
+        // Through the base without rounding in between: 10,000 yen x 0.9214 / 139.48 = 66.0596... euros
+        let yen_to_euros = tables.rate(Currency::JPY, Currency::EUR, date(2023, 6, 1)).unwrap();
+        assert_eq!(yen_to_euros.convert(Money::new(10_000, Currency::JPY)), Ok(Money::new(6_606, Currency::EUR)));
+        // At 2 yen to the dollar, 25 cents is half a yen, which rounds to the even 0, and 75 cents rounds to 2
+        let two = ExchangeRate { to_rate: Rate(2_000_000), ..ExchangeRate::identity(Currency::USD, date(2023, 6, 1)) };
+        let two = ExchangeRate { to: Currency::JPY, ..two };
+        assert_eq!(two.inverse().convert(Money::new(1, Currency::JPY)), Ok(Money::from_cents(50)));
+        assert_eq!(two.convert(Money::from_cents(25)), Ok(Money::new(0, Currency::JPY)));
+        assert_eq!(two.convert(Money::from_cents(75)), Ok(Money::new(2, Currency::JPY)));
+
+        assert_eq!(
+            to_dollars.convert(Money::from_cents(1)),
+            Err(ExchangeError::WrongCurrency { expected: Currency::EUR, found: Currency::USD })
+        );
+    }
+
+    #[test]
+    fn converted_totals_still_add_up() {
+        let items = [LineItem::new("A", "A", 3, Money::from_cents(3_333)).taxed(TaxRate::new("Sales tax", 825))];
+        let totals = Totals::compute(&items, &[]).unwrap();
+        let rate = tables().rate(Currency::USD, Currency::JPY, date(2023, 6, 1)).unwrap();
+
+        let converted = ConvertedTotals::convert(&totals, &rate).unwrap();
+        assert_eq!(converted.subtotal, Money::new(13_947, Currency::JPY));
+        assert_eq!(converted.tax, Money::new(1_151, Currency::JPY));
+        assert_eq!(converted.total, converted.subtotal - converted.discount + converted.tax);
+        assert_eq!(ConvertedTotals::convert(&totals, &rate).unwrap(), converted);
+    }
+
+    #[test]
+    fn loads_and_checks_the_rates_file() {
+        let dir = std::env::temp_dir().join(format!("rates-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("rates.json");
+
+        fs::write(&path, RATES).unwrap();
+        assert_eq!(RateTables::load(&path).unwrap(), tables());
+
+        fs::write(&path, RATES.replace("2023-07-01", "2023-06-01")).unwrap();
+        assert!(matches!(RateTables::load(&path), Err(ExchangeError::Invalid(reason)) if reason.contains("two tables")));
+        fs::write(&path, RATES.replace("\"USD\": \"1\"", "\"USD\": \"1.1\"")).unwrap();
+        assert!(matches!(RateTables::load(&path), Err(ExchangeError::Invalid(reason)) if reason.contains("at 1")));
+        assert!(matches!(RateTables::load(&dir.join("missing.json")), Err(ExchangeError::Storage(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
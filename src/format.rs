@@ -0,0 +1,73 @@
+// Output formats shared by the listing commands (journal list, car list, car report)
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Aligned columns for people.
+    #[default]
+    Text,
+    /// A pretty-printed JSON array, for scripts.
+    Json,
+    /// Comma-separated values with a header row, for spreadsheets.
+    Csv,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown format '{}', expected text, json or csv", s)),
+        }
+    }
+}
+
+/// One CSV line (without the line break). Fields holding commas, quotes or line breaks are quoted,
+/// with quotes doubled, as RFC 4180 describes.
+pub fn csv_row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_row(&["1", "Buy milk", "2023-06-01"]), "1,Buy milk,2023-06-01");
+        assert_eq!(csv_row(&["eggs, bread", "say \"hi\""]), "\"eggs, bread\",\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn formats_parse_case_insensitively() {
+        assert_eq!("JSON".parse(), Ok(OutputFormat::Json));
+        assert_eq!(OutputFormat::default(), OutputFormat::Text);
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
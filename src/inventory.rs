@@ -0,0 +1,853 @@
+// The dealership's inventory: car orders on their way to customers, and the cars in stock on the lot
+
+/*
+    Every order moves through the same states, and only forwards:
+
+        Placed -> Built -> Shipped -> Delivered
+           \         \
+            +---------+--> Cancelled
+
+    Once a car has shipped it can no longer be cancelled, and Delivered and Cancelled are final.
+    Each change is kept in the order's history with the time it happened.
+
+    Stock is counted per make, model and color. Orders and stock are saved to one JSON file, read
+    with serde the same way the journal is and replaced atomically on every change.
+
+    The file carries a schema version. When the layout of an order changes, SCHEMA_VERSION goes up
+    and migrate() learns how to bring the previous version's files up to date, so old files keep
+    loading:
+
+      1  no version field; cars have no condition (added to Car after the file format was set)
+      2  version field; every car has a condition
+
+    Optional fields that old files simply don't have, like the quote an order came from, don't
+    need a new version.
+
+    The order id doubles as the serial number in the car's VIN, which has six digits, so an
+    inventory holds at most MAX_ORDERS orders. Only cars built against the catalog, which have a
+    model year and their make's manufacturer code, get a VIN.
+
+    Orders placed for an accepted quote carry its UUID. place_for_quote() places every car on the
+    quote in one go and refuses if any order already carries that UUID, so a quote can't be
+    ordered twice.
+ */
+
+use crate::car::{Age, Car, Color, Roof};
+use crate::condition::Condition;
+use crate::format::{csv_row, OutputFormat};
+use crate::storage::write_json_atomically;
+use crate::vin::{Vin, VinError};
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Local, Utc};
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// The plant code in the VINs of cars ordered through the inventory.
+pub const PLANT: char = 'M';
+
+/// Order ids are the six-digit serial numbers of the cars' VINs.
+pub const MAX_ORDERS: u32 = 999_999;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderState {
+    Placed,
+    Built,
+    Shipped,
+    Delivered,
+    Cancelled,
+}
+
+impl OrderState {
+    pub const ALL: [OrderState; 5] =
+        [OrderState::Placed, OrderState::Built, OrderState::Shipped, OrderState::Delivered, OrderState::Cancelled];
+
+    pub fn can_become(self, next: OrderState) -> bool {
+        use OrderState::*;
+        matches!(
+            (self, next),
+            (Placed, Built) | (Built, Shipped) | (Shipped, Delivered) | (Placed, Cancelled) | (Built, Cancelled)
+        )
+    }
+
+    pub fn is_final(self) -> bool {
+        OrderState::ALL.iter().all(|next| !self.can_become(*next))
+    }
+}
+
+impl fmt::Display for OrderState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OrderState::Placed => "placed",
+            OrderState::Built => "built",
+            OrderState::Shipped => "shipped",
+            OrderState::Delivered => "delivered",
+            OrderState::Cancelled => "cancelled",
+        };
+        // pad() so the state lines up in listings
+        f.pad(name)
+    }
+}
+
+impl FromStr for OrderState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OrderState::ALL
+            .iter()
+            .find(|state| state.to_string().eq_ignore_ascii_case(s.trim()))
+            .copied()
+            .ok_or_else(|| format!("unknown order state '{}', expected placed, built, shipped, delivered or cancelled", s))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StateChange {
+    pub state: OrderState,
+
+    #[serde(with = "ts_seconds")]
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Order {
+    pub id: u32,
+    pub car: Car,
+    pub state: OrderState,
+    pub history: Vec<StateChange>,
+    /// The accepted quote the order was placed for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote: Option<Uuid>,
+}
+
+impl Order {
+    pub fn placed_at(&self) -> DateTime<Utc> {
+        self.history[0].at
+    }
+
+    pub const CSV_HEADER: [&'static str; 12] =
+        ["id", "vin", "make", "model", "year", "color", "transmission", "roof", "age", "miles", "state", "placed_at"];
+
+    /// The order's fields in CSV_HEADER order.
+    pub fn csv_fields(&self) -> Vec<String> {
+        let car = &self.car;
+        vec![
+            self.id.to_string(),
+            car.vin.as_ref().map_or(String::new(), Vin::to_string),
+            car.make().to_string(),
+            car.model().to_string(),
+            car.year.map_or(String::new(), |year| year.to_string()),
+            car.color.to_string(),
+            car.motor.to_string(),
+            Roof::from(car.roof).to_string(),
+            car.age.0.to_string(),
+            car.age.1.to_string(),
+            self.state.to_string(),
+            self.placed_at().to_rfc3339(),
+        ]
+    }
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let car = &self.car;
+        let year = car.year.map_or("-".to_string(), |year| year.to_string());
+        let placed_at = self.placed_at().with_timezone(&Local).format("%F %H:%M");
+        write!(
+            f,
+            "#{:<5} {:<24} {:<5} {:<7} {:<10} {:<5} {:<10} [{}]",
+            self.id,
+            format!("{} {}", car.make(), car.model()),
+            year,
+            car.color,
+            car.motor,
+            car.age.0,
+            self.state,
+            placed_at
+        )
+    }
+}
+
+// How many orders share a model, color or transmission
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Tally {
+    pub name: String,
+    pub count: usize,
+}
+
+/// Open and fulfilled orders counted by model, color and transmission. Cancelled orders are only
+/// counted in `cancelled`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OrderReport {
+    pub orders: usize,
+    pub cancelled: usize,
+    pub by_model: Vec<Tally>,
+    pub by_color: Vec<Tally>,
+    pub by_transmission: Vec<Tally>,
+}
+
+impl OrderReport {
+    pub const CSV_HEADER: [&'static str; 3] = ["group", "name", "count"];
+
+    /// One row per tally, in CSV_HEADER order.
+    pub fn csv_rows(&self) -> Vec<[String; 3]> {
+        [("model", &self.by_model), ("color", &self.by_color), ("transmission", &self.by_transmission)]
+            .iter()
+            .flat_map(|(group, tallies)| {
+                tallies.iter().map(move |tally| [group.to_string(), tally.name.clone(), tally.count.to_string()])
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for OrderReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} orders ({} cancelled orders not counted below)", self.orders, self.cancelled)?;
+        for (heading, tallies) in [("Model", &self.by_model), ("Color", &self.by_color), ("Transmission", &self.by_transmission)] {
+            writeln!(f, "\n{}", heading)?;
+            for tally in tallies {
+                writeln!(f, "  {:<28} {:>5}", tally.name, tally.count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OrderReport {
+    /// The report as aligned text, a JSON object or CSV rows with a header.
+    pub fn export(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Text => self.to_string(),
+            OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(self).expect("reports always serialize")),
+            OutputFormat::Csv => csv_lines(&OrderReport::CSV_HEADER, self.csv_rows()),
+        }
+    }
+}
+
+/// Orders as one text line each, a JSON array or CSV rows with a header.
+pub fn export_orders(orders: &[&Order], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => orders.iter().map(|order| format!("{}\n", order)).collect(),
+        OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(orders).expect("orders always serialize")),
+        OutputFormat::Csv => csv_lines(&Order::CSV_HEADER, orders.iter().map(|order| order.csv_fields())),
+    }
+}
+
+fn csv_lines<R: AsRef<[String]>>(header: &[&str], rows: impl IntoIterator<Item = R>) -> String {
+    let mut lines = vec![csv_row(header)];
+    lines.extend(rows.into_iter().map(|row| csv_row(row.as_ref())));
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+// Counts by name, most common first and alphabetically among equals
+fn tally(names: impl Iterator<Item = String>) -> Vec<Tally> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for name in names {
+        *counts.entry(name).or_default() += 1;
+    }
+    let mut tallies: Vec<Tally> = counts.into_iter().map(|(name, count)| Tally { name, count }).collect();
+    tallies.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    tallies
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StockLevel {
+    pub make: String,
+    pub model: String,
+    pub color: Color,
+    pub on_hand: u32,
+}
+
+impl StockLevel {
+    fn is(&self, make: &str, model: &str, color: Color) -> bool {
+        self.make.eq_ignore_ascii_case(make.trim()) && self.model.eq_ignore_ascii_case(model.trim()) && self.color == color
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InventoryError {
+    UnknownOrder(u32),
+    InvalidTransition { id: u32, from: OrderState, to: OrderState },
+    OutOfStock { model: String, color: Color, on_hand: u32 },
+    AlreadyOrdered { quote: Uuid, orders: Vec<u32> },
+    Full,
+    Vin(VinError),
+    Storage(String),
+}
+
+impl fmt::Display for InventoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InventoryError::UnknownOrder(id) => write!(f, "there is no order #{}", id),
+            InventoryError::InvalidTransition { id, from, to } => {
+                write!(f, "order #{} is {} and can't be marked {}", id, from, to)
+            }
+            InventoryError::OutOfStock { model, color, on_hand } => {
+                write!(f, "only {} {} {} in stock", on_hand, color, model)
+            }
+            InventoryError::AlreadyOrdered { quote, orders } => {
+                let orders: Vec<String> = orders.iter().map(|id| format!("#{}", id)).collect();
+                write!(f, "quote {} was already ordered as {}", quote, orders.join(", "))
+            }
+            InventoryError::Full => {
+                write!(f, "the inventory is full: order ids are six-digit VIN serials, at most {}", MAX_ORDERS)
+            }
+            InventoryError::Vin(error) => write!(f, "can't give the car a VIN: {}", error),
+            InventoryError::Storage(error) => write!(f, "inventory file: {}", error),
+        }
+    }
+}
+
+impl From<std::io::Error> for InventoryError {
+    fn from(error: std::io::Error) -> Self {
+        InventoryError::Storage(error.to_string())
+    }
+}
+
+pub const SCHEMA_VERSION: u64 = 2;
+
+// What's saved to the inventory file
+#[derive(Debug, Deserialize, Serialize)]
+struct Records {
+    version: u64,
+    orders: Vec<Order>,
+    stock: Vec<StockLevel>,
+}
+
+impl Default for Records {
+    fn default() -> Self {
+        Records { version: SCHEMA_VERSION, orders: Vec::new(), stock: Vec::new() }
+    }
+}
+
+// Brings the JSON of an inventory file of any known version up to SCHEMA_VERSION, one version at a time
+fn migrate(mut records: Value) -> std::io::Result<Value> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+    let mut version = match records.get("version") {
+        None => 1,
+        Some(version) => version.as_u64().ok_or_else(|| invalid(format!("schema version {} is not a number", version)))?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(invalid(format!(
+            "inventory file has schema version {}, this program only knows up to {}",
+            version, SCHEMA_VERSION
+        )));
+    }
+
+    while version < SCHEMA_VERSION {
+        match version {
+            1 => {
+                // Cars get a condition built from the mileage and model year they already had
+                let orders = records.get_mut("orders").and_then(Value::as_array_mut).into_iter().flatten();
+                for car in orders.filter_map(|order| order.get_mut("car")).filter_map(Value::as_object_mut) {
+                    if !car.contains_key("condition") {
+                        let condition = Condition::new(
+                            car.get("age").and_then(|age| age.get(1)).and_then(Value::as_u64).unwrap_or(0) as u32,
+                            car.get("year").and_then(Value::as_u64).map(|year| year as u16),
+                        );
+                        car.insert("condition".to_string(), serde_json::to_value(condition)?);
+                    }
+                }
+            }
+            _ => unreachable!("every version below SCHEMA_VERSION has a migration"),
+        }
+        version += 1;
+        records["version"] = Value::from(version);
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Default)]
+pub struct Inventory {
+    records: Records,
+    path: Option<PathBuf>,
+}
+
+impl Inventory {
+    /// An inventory that only lives in memory.
+    pub fn new() -> Self {
+        Inventory::default()
+    }
+
+    /// An inventory backed by a JSON file. Orders and stock already in the file are loaded.
+    pub fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let mut records: Records = match serde_json::from_reader(file) {
+            Ok(records) => serde_json::from_value(migrate(records)?)?,
+            Err(e) if e.is_eof() => Records::default(),
+            Err(e) => Err(e)?,
+        };
+        // Orders are kept in id order so they can be looked up by binary search
+        records.orders.sort_by_key(|order| order.id);
+
+        Ok(Inventory { records, path: Some(path) })
+    }
+
+    /// Places an order for the car and returns its id. Ids start at 1 and are never reused.
+    pub fn place(&mut self, car: Car, now: DateTime<Utc>) -> Result<u32, InventoryError> {
+        let order = self.new_order(car, None, now)?;
+        let id = order.id;
+        self.records.orders.push(order);
+        if let Err(error) = self.save() {
+            self.records.orders.pop();
+            return Err(error.into());
+        }
+        Ok(id)
+    }
+
+    /// Places an order for each car on an accepted quote and returns their ids. Nothing is placed
+    /// if the quote was ordered before or any of the cars can't be ordered.
+    pub fn place_for_quote(&mut self, quote: Uuid, cars: Vec<Car>, now: DateTime<Utc>) -> Result<Vec<u32>, InventoryError> {
+        let existing: Vec<u32> = self.for_quote(quote).iter().map(|order| order.id).collect();
+        if !existing.is_empty() {
+            return Err(InventoryError::AlreadyOrdered { quote, orders: existing });
+        }
+
+        let placed = self.records.orders.len();
+        for car in cars {
+            match self.new_order(car, Some(quote), now) {
+                Ok(order) => self.records.orders.push(order),
+                Err(error) => {
+                    self.records.orders.truncate(placed);
+                    return Err(error);
+                }
+            }
+        }
+        if let Err(error) = self.save() {
+            self.records.orders.truncate(placed);
+            return Err(error.into());
+        }
+        Ok(self.records.orders[placed..].iter().map(|order| order.id).collect())
+    }
+
+    /// The orders placed for a quote, in the order they were placed.
+    pub fn for_quote(&self, quote: Uuid) -> Vec<&Order> {
+        self.records.orders.iter().filter(|order| order.quote == Some(quote)).collect()
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Order> {
+        self.position(id).map(|index| &self.records.orders[index])
+    }
+
+    pub fn orders(&self) -> &[Order] {
+        &self.records.orders
+    }
+
+    /// Moves an order to its next state, if the lifecycle allows it.
+    pub fn advance(&mut self, id: u32, to: OrderState, now: DateTime<Utc>) -> Result<&Order, InventoryError> {
+        let index = self.position(id).ok_or(InventoryError::UnknownOrder(id))?;
+
+        let order = &mut self.records.orders[index];
+        if !order.state.can_become(to) {
+            return Err(InventoryError::InvalidTransition { id, from: order.state, to });
+        }
+        let from = order.state;
+        order.state = to;
+        order.history.push(StateChange { state: to, at: now });
+
+        if let Err(error) = self.save() {
+            let order = &mut self.records.orders[index];
+            order.state = from;
+            order.history.pop();
+            return Err(error.into());
+        }
+        Ok(&self.records.orders[index])
+    }
+
+    pub fn cancel(&mut self, id: u32, now: DateTime<Utc>) -> Result<&Order, InventoryError> {
+        self.advance(id, OrderState::Cancelled, now)
+    }
+
+    pub fn by_state(&self, state: OrderState) -> Vec<&Order> {
+        self.records.orders.iter().filter(|order| order.state == state).collect()
+    }
+
+    pub fn by_color(&self, color: Color) -> Vec<&Order> {
+        self.records.orders.iter().filter(|order| order.car.color == color).collect()
+    }
+
+    pub fn by_age(&self, age: Age) -> Vec<&Order> {
+        self.records.orders.iter().filter(|order| order.car.age.0 == age).collect()
+    }
+
+    pub fn report(&self) -> OrderReport {
+        let active: Vec<&Order> =
+            self.records.orders.iter().filter(|order| order.state != OrderState::Cancelled).collect();
+        OrderReport {
+            orders: active.len(),
+            cancelled: self.records.orders.len() - active.len(),
+            by_model: tally(active.iter().map(|order| format!("{} {}", order.car.make(), order.car.model()))),
+            by_color: tally(active.iter().map(|order| order.car.color.to_string())),
+            by_transmission: tally(active.iter().map(|order| order.car.motor.to_string())),
+        }
+    }
+
+    pub fn stock(&self) -> &[StockLevel] {
+        &self.records.stock
+    }
+
+    pub fn on_hand(&self, make: &str, model: &str, color: Color) -> u32 {
+        self.records
+            .stock
+            .iter()
+            .find(|level| level.is(make, model, color))
+            .map_or(0, |level| level.on_hand)
+    }
+
+    /// Adds cars to the lot and returns how many of them are now on hand.
+    pub fn receive_stock(&mut self, make: &str, model: &str, color: Color, count: u32) -> Result<u32, InventoryError> {
+        let before = self.records.stock.clone();
+        let on_hand = match self.records.stock.iter_mut().find(|level| level.is(make, model, color)) {
+            Some(level) => {
+                level.on_hand += count;
+                level.on_hand
+            }
+            None => {
+                self.records.stock.push(StockLevel {
+                    make: make.trim().to_string(),
+                    model: model.trim().to_string(),
+                    color,
+                    on_hand: count,
+                });
+                count
+            }
+        };
+        if let Err(error) = self.save() {
+            self.records.stock = before;
+            return Err(error.into());
+        }
+        Ok(on_hand)
+    }
+
+    /// Takes cars off the lot and returns how many are left. Fails without changing anything if
+    /// there aren't enough.
+    pub fn take_stock(&mut self, make: &str, model: &str, color: Color, count: u32) -> Result<u32, InventoryError> {
+        let level = self.records.stock.iter_mut().find(|level| level.is(make, model, color));
+        let on_hand = level.as_ref().map_or(0, |level| level.on_hand);
+        match level {
+            Some(level) if on_hand >= count => {
+                level.on_hand -= count;
+                let left = level.on_hand;
+                if let Err(error) = self.save() {
+                    self.records.stock.iter_mut().find(|level| level.is(make, model, color)).expect("found above").on_hand += count;
+                    return Err(error.into());
+                }
+                Ok(left)
+            }
+            _ => Err(InventoryError::OutOfStock { model: format!("{} {}", make.trim(), model.trim()), color, on_hand }),
+        }
+    }
+
+    fn new_order(&self, mut car: Car, quote: Option<Uuid>, now: DateTime<Utc>) -> Result<Order, InventoryError> {
+        let id = self.records.orders.last().map_or(0, |order| order.id) + 1;
+        if id > MAX_ORDERS {
+            return Err(InventoryError::Full);
+        }
+        // The order id doubles as the VIN's serial number
+        if car.year.is_some() && car.wmi.is_some() {
+            car.assign_vin(PLANT, id).map_err(InventoryError::Vin)?;
+        }
+        Ok(Order {
+            id,
+            car,
+            state: OrderState::Placed,
+            history: vec![StateChange { state: OrderState::Placed, at: now }],
+            quote,
+        })
+    }
+
+    fn position(&self, id: u32) -> Option<usize> {
+        self.records.orders.binary_search_by_key(&id, |order| order.id).ok()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            write_json_atomically(path, &self.records)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::{CarOrder, Transmission};
+    use crate::catalog::Catalog;
+    use chrono::{Duration, TimeZone};
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 6, 1, 9, 0, 0).unwrap()
+    }
+
+    fn car(color: Color, miles: u32) -> Car {
+        CarOrder::new()
+            .make("Maserati")
+            .model("Folgore")
+            .year(2024)
+            .color(color)
+            .transmission(Transmission::Automatic)
+            .roof(true)
+            .miles(miles)
+            .build_against(&Catalog::load(PathBuf::from("catalog.json")).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn orders_follow_the_lifecycle() {
+        let mut inventory = Inventory::new();
+        let id = inventory.place(car(Color::Blue, 0), now()).unwrap();
+
+        for (hours, state) in [(1, OrderState::Built), (2, OrderState::Shipped), (3, OrderState::Delivered)] {
+            inventory.advance(id, state, now() + Duration::hours(hours)).unwrap();
+        }
+
+        let order = inventory.get(id).unwrap();
+        assert_eq!(order.state, OrderState::Delivered);
+        assert_eq!(order.history.len(), 4);
+        assert_eq!(order.placed_at(), now());
+        assert_eq!(order.history[3].at, now() + Duration::hours(3));
+        assert_eq!(order.car.vin.as_ref().map(|vin| vin.decode().serial), Some(id));
+    }
+
+    #[test]
+    fn disallowed_transitions_are_rejected() {
+        let mut inventory = Inventory::new();
+        let id = inventory.place(car(Color::Blue, 0), now()).unwrap();
+
+        assert_eq!(
+            inventory.advance(id, OrderState::Shipped, now()),
+            Err(InventoryError::InvalidTransition { id, from: OrderState::Placed, to: OrderState::Shipped })
+        );
+
+        inventory.advance(id, OrderState::Built, now()).unwrap();
+        inventory.advance(id, OrderState::Shipped, now()).unwrap();
+        assert!(matches!(inventory.cancel(id, now()), Err(InventoryError::InvalidTransition { .. })));
+        assert_eq!(inventory.cancel(99, now()), Err(InventoryError::UnknownOrder(99)));
+        assert!(OrderState::Delivered.is_final() && OrderState::Cancelled.is_final());
+        assert!(!OrderState::Built.is_final());
+    }
+
+    #[test]
+    fn orders_can_be_queried() {
+        let mut inventory = Inventory::new();
+        let blue_new = inventory.place(car(Color::Blue, 0), now()).unwrap();
+        let red_used = inventory.place(car(Color::Red, 1_400), now()).unwrap();
+        let blue_used = inventory.place(car(Color::Blue, 700), now()).unwrap();
+        inventory.cancel(red_used, now()).unwrap();
+
+        let ids = |orders: Vec<&Order>| orders.iter().map(|order| order.id).collect::<Vec<_>>();
+        assert_eq!(ids(inventory.by_color(Color::Blue)), vec![blue_new, blue_used]);
+        assert_eq!(ids(inventory.by_age(Age::Used)), vec![red_used, blue_used]);
+        assert_eq!(ids(inventory.by_age(Age::New)), vec![blue_new]);
+        assert_eq!(ids(inventory.by_state(OrderState::Cancelled)), vec![red_used]);
+    }
+
+    #[test]
+    fn stock_is_tracked_per_model_and_color() {
+        let mut inventory = Inventory::new();
+        assert_eq!(inventory.receive_stock("Maserati", "Folgore", Color::Blue, 2), Ok(2));
+        assert_eq!(inventory.receive_stock("maserati", "folgore", Color::Blue, 1), Ok(3));
+        assert_eq!(inventory.on_hand("Maserati", "Folgore", Color::Red), 0);
+
+        assert_eq!(inventory.take_stock("Maserati", "Folgore", Color::Blue, 2), Ok(1));
+        assert_eq!(
+            inventory.take_stock("Maserati", "Folgore", Color::Blue, 2),
+            Err(InventoryError::OutOfStock { model: "Maserati Folgore".to_string(), color: Color::Blue, on_hand: 1 })
+        );
+        assert_eq!(inventory.on_hand("Maserati", "Folgore", Color::Blue), 1);
+    }
+
+    #[test]
+    fn report_counts_open_orders_by_model_color_and_transmission() {
+        let mut inventory = Inventory::new();
+        inventory.place(car(Color::Blue, 0), now()).unwrap();
+        inventory.place(car(Color::Red, 0), now()).unwrap();
+        inventory.place(car(Color::Blue, 900), now()).unwrap();
+        let cancelled = inventory.place(car(Color::Silver, 0), now()).unwrap();
+        inventory.cancel(cancelled, now()).unwrap();
+
+        let report = inventory.report();
+        assert_eq!((report.orders, report.cancelled), (3, 1));
+        let counts = |tallies: &[Tally]| tallies.iter().map(|t| (t.name.clone(), t.count)).collect::<Vec<_>>();
+        assert_eq!(counts(&report.by_model), vec![("Maserati Folgore".to_string(), 3)]);
+        assert_eq!(counts(&report.by_color), vec![("Blue".to_string(), 2), ("Red".to_string(), 1)]);
+        assert_eq!(report.csv_rows()[0], ["model".to_string(), "Maserati Folgore".to_string(), "3".to_string()]);
+    }
+
+    #[test]
+    fn orders_and_reports_export_as_json_and_csv() {
+        let mut inventory = Inventory::new();
+        inventory.place(car(Color::Blue, 0), now()).unwrap();
+        inventory.place(car(Color::Red, 0), now()).unwrap();
+        let orders: Vec<&Order> = inventory.orders().iter().collect();
+
+        let csv = export_orders(&orders, OutputFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "id,vin,make,model,year,color,transmission,roof,age,miles,state,placed_at");
+        assert!(lines[2].starts_with("2,ZAMF0LG"));
+
+        let json: Vec<Order> = serde_json::from_str(&export_orders(&orders, OutputFormat::Json)).unwrap();
+        assert_eq!(json, inventory.orders());
+
+        let report: Value = serde_json::from_str(&inventory.report().export(OutputFormat::Json)).unwrap();
+        assert_eq!(report["by_color"][0], serde_json::json!({ "name": "Blue", "count": 1 }));
+        assert_eq!(inventory.report().export(OutputFormat::Csv).lines().next(), Some("group,name,count"));
+    }
+
+    #[test]
+    fn states_parse_from_their_names() {
+        assert_eq!("Shipped".parse(), Ok(OrderState::Shipped));
+        assert!("lost".parse::<OrderState>().is_err());
+    }
+
+    #[test]
+    fn inventory_survives_restart() {
+        let path = std::env::temp_dir().join(format!("inventory-{}.json", uuid::Uuid::new_v4()));
+
+        let mut inventory = Inventory::open(path.clone()).unwrap();
+        let id = inventory.place(car(Color::Silver, 0), now()).unwrap();
+        inventory.advance(id, OrderState::Built, now()).unwrap();
+        inventory.receive_stock("Maserati", "Folgore", Color::Silver, 4).unwrap();
+        drop(inventory);
+
+        let reopened = Inventory::open(path.clone()).unwrap();
+        let mut expected = car(Color::Silver, 0);
+        expected.assign_vin(PLANT, id).unwrap();
+        assert_eq!(reopened.get(id).unwrap().state, OrderState::Built);
+        assert_eq!(reopened.get(id).unwrap().car, expected);
+        assert_eq!(reopened.on_hand("Maserati", "Folgore", Color::Silver), 4);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn version_1_files_are_migrated() {
+        let path = std::env::temp_dir().join(format!("inventory-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{"orders":[{"id":3,"car":{"make":"Ferrari","model":"Roma","year":2023,"color":"Red",
+                "motor":"Semi-Auto","roof":true,"age":["Used",1200]},"state":"built",
+                "history":[{"state":"placed","at":1685610000},{"state":"built","at":1685620000}]}],
+                "stock":[{"make":"Ferrari","model":"Roma","color":"Red","on_hand":2}]}"#,
+        )
+        .unwrap();
+
+        let mut inventory = Inventory::open(path.clone()).unwrap();
+        let order = inventory.get(3).unwrap();
+        assert_eq!(order.car.condition, Condition::new(1_200, Some(2023)));
+        assert_eq!(order.car.vin, None);
+        assert_eq!(inventory.on_hand("Ferrari", "Roma", Color::Red), 2);
+
+        // Saving writes the current version
+        inventory.receive_stock("Ferrari", "Roma", Color::Red, 1).unwrap();
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["version"], SCHEMA_VERSION);
+        assert!(saved["orders"][0]["car"]["condition"].is_object());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn files_from_a_newer_version_are_refused() {
+        let newer = serde_json::json!({ "version": SCHEMA_VERSION + 1, "orders": [], "stock": [] });
+        let error = migrate(newer).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("only knows up to"));
+    }
+
+    #[test]
+    fn a_quote_is_ordered_once_and_all_at_once() {
+        let mut inventory = Inventory::new();
+        let quote = Uuid::new_v4();
+        inventory.place(car(Color::Red, 0), now()).unwrap();
+
+        let ids = inventory.place_for_quote(quote, vec![car(Color::Blue, 0), car(Color::Silver, 0)], now()).unwrap();
+
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(inventory.for_quote(quote).iter().map(|order| order.car.color).collect::<Vec<_>>(), vec![Color::Blue, Color::Silver]);
+        assert_eq!(inventory.get(1).unwrap().quote, None);
+        assert!(inventory.get(3).unwrap().car.vin.is_some());
+        assert_eq!(
+            inventory.place_for_quote(quote, vec![car(Color::Blue, 0)], now()),
+            Err(InventoryError::AlreadyOrdered { quote, orders: vec![2, 3] })
+        );
+
+        // A car that can't be given a VIN leaves the whole quote unordered
+        let mut unknown = CarOrder::new()
+            .make("Trabant")
+            .model("601")
+            .year(1989)
+            .color(Color::Blue)
+            .transmission(Transmission::Manual)
+            .roof(true)
+            .build()
+            .unwrap();
+        unknown.wmi = Some("TRB".to_string());
+        let other = Uuid::new_v4();
+        assert!(matches!(
+            inventory.place_for_quote(other, vec![car(Color::Red, 0), unknown], now()),
+            Err(InventoryError::Vin(_))
+        ));
+        assert!(inventory.for_quote(other).is_empty());
+        assert_eq!(inventory.orders().len(), 3);
+    }
+
+    #[test]
+    fn order_ids_stop_at_the_last_vin_serial() {
+        let mut inventory = Inventory::new();
+        let id = inventory.place(car(Color::Blue, 0), now()).unwrap();
+        inventory.records.orders[0].id = MAX_ORDERS;
+
+        assert_eq!(inventory.place(car(Color::Blue, 0), now()), Err(InventoryError::Full));
+        assert_eq!(inventory.place_for_quote(Uuid::new_v4(), vec![car(Color::Red, 0)], now()), Err(InventoryError::Full));
+        assert_eq!(inventory.orders().len(), id as usize);
+    }
+
+    #[test]
+    fn cars_built_without_the_catalog_get_no_vin() {
+        let mut inventory = Inventory::new();
+        let mut car = car(Color::Blue, 0);
+        car.wmi = None;
+
+        let id = inventory.place(car, now()).unwrap();
+        assert_eq!(inventory.get(id).unwrap().car.vin, None);
+    }
+
+    #[test]
+    fn failed_saves_change_nothing() {
+        let dir = std::env::temp_dir().join(format!("inventory-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let mut inventory = Inventory::open(dir.join("inventory.json")).unwrap();
+        let id = inventory.place(car(Color::Blue, 0), now()).unwrap();
+        inventory.receive_stock("Maserati", "Folgore", Color::Blue, 2).unwrap();
+
+        // With the directory gone nothing can be saved
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(inventory.place(car(Color::Red, 0), now()), Err(InventoryError::Storage(_))));
+        assert!(matches!(inventory.advance(id, OrderState::Built, now()), Err(InventoryError::Storage(_))));
+        assert!(matches!(inventory.place_for_quote(Uuid::new_v4(), vec![car(Color::Red, 0)], now()), Err(InventoryError::Storage(_))));
+        assert!(matches!(inventory.receive_stock("Maserati", "Folgore", Color::Red, 1), Err(InventoryError::Storage(_))));
+        assert!(matches!(inventory.take_stock("Maserati", "Folgore", Color::Blue, 1), Err(InventoryError::Storage(_))));
+
+        assert_eq!(inventory.orders().len(), 1);
+        assert_eq!(inventory.get(id).unwrap().state, OrderState::Placed);
+        assert_eq!(inventory.get(id).unwrap().history.len(), 1);
+        assert_eq!(inventory.stock().len(), 1);
+        assert_eq!(inventory.on_hand("Maserati", "Folgore", Color::Blue), 2);
+    }
+}
@@ -13,6 +13,35 @@
     test the binary's functionality by importing the crate as a library with use.
  */
 
+pub mod api_key;
+pub mod audit;
+pub mod auth;
+pub mod car;
+pub mod catalog;
+pub mod cli;
+pub mod condition;
+pub mod clock;
+pub mod customer;
+pub mod exchange;
+pub mod file;
+pub mod format;
+pub mod inventory;
+pub mod lockout;
+pub mod money;
+pub mod person;
+pub mod pricing;
+pub mod quote;
+pub mod quoting;
+pub mod render;
+pub mod revision;
+pub mod secret;
+pub mod simulation;
+pub mod storage;
+pub mod tasks;
+pub mod totals;
+pub mod totp;
+pub mod vin;
+
 pub struct Pizza {
     pub topping: String,
     pub inches: u8,
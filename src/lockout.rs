@@ -0,0 +1,350 @@
+// Brute-force protection: failed login counters, exponential backoff and temporary lockout
+
+/*
+    Every failed login is counted twice: once against the username being attacked, and once against
+    the source the attempt came from (an IP address, a terminal name, ...). Counting per user stops
+    someone from guessing one account's password; counting per source stops one attacker from
+    spreading guesses across many accounts.
+
+    After each failure the next attempt has to wait base_delay * 2^(failures - 1), capped at max_delay.
+    After max_failures consecutive failures the user or source is locked out for lockout_secs.
+    The counters are written to a JSON file, the same way the journal stores its tasks, so a restart
+    doesn't hand an attacker a fresh set of attempts.
+ */
+
+use crate::storage::write_json_atomically;
+use chrono::serde::ts_seconds_option;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Result;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct LockoutPolicy {
+    pub max_failures: u32,
+    pub base_delay_secs: i64,
+    pub max_delay_secs: i64,
+    pub lockout_secs: i64,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        LockoutPolicy {
+            max_failures: 5,
+            base_delay_secs: 1,
+            max_delay_secs: 5 * 60,
+            lockout_secs: 15 * 60,
+        }
+    }
+}
+
+impl LockoutPolicy {
+    fn backoff(&self, failures: u32) -> Duration {
+        // 2^(failures - 1), without overflowing once failures gets large
+        let factor = 1i64.checked_shl(failures.saturating_sub(1)).unwrap_or(i64::MAX);
+        let delay = self.base_delay_secs.saturating_mul(factor).min(self.max_delay_secs);
+        Duration::seconds(delay)
+    }
+}
+
+/// Why an attempt was refused before the password was even looked at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Denial {
+    Throttled { retry_at: DateTime<Utc> },
+    LockedOut { until: DateTime<Utc> },
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+struct AttemptRecord {
+    failures: u32,
+    #[serde(with = "ts_seconds_option")]
+    next_attempt_at: Option<DateTime<Utc>>,
+    #[serde(with = "ts_seconds_option")]
+    locked_until: Option<DateTime<Utc>>,
+}
+
+impl AttemptRecord {
+    fn check(&self, now: DateTime<Utc>) -> std::result::Result<(), Denial> {
+        match (self.locked_until, self.next_attempt_at) {
+            (Some(until), _) if now < until => Err(Denial::LockedOut { until }),
+            (_, Some(retry_at)) if now < retry_at => Err(Denial::Throttled { retry_at }),
+            _ => Ok(()),
+        }
+    }
+
+    fn fail(&mut self, policy: &LockoutPolicy, now: DateTime<Utc>) {
+        // A lockout that has run its course wipes the slate clean
+        if matches!(self.locked_until, Some(until) if now >= until) {
+            *self = AttemptRecord::default();
+        }
+
+        self.failures += 1;
+        self.next_attempt_at = Some(now + policy.backoff(self.failures));
+
+        if self.failures >= policy.max_failures {
+            self.locked_until = Some(now + Duration::seconds(policy.lockout_secs));
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Counters {
+    users: HashMap<String, AttemptRecord>,
+    sources: HashMap<String, AttemptRecord>,
+}
+
+#[derive(Debug)]
+pub struct LoginThrottle {
+    policy: LockoutPolicy,
+    counters: Counters,
+    // Where the counters are persisted, if anywhere
+    path: Option<PathBuf>,
+}
+
+impl LoginThrottle {
+    /// A throttle that only keeps its counters in memory.
+    pub fn new(policy: LockoutPolicy) -> Self {
+        LoginThrottle {
+            policy,
+            counters: Counters::default(),
+            path: None,
+        }
+    }
+
+    /// A throttle backed by a JSON file. Existing counters in the file are loaded.
+    pub fn open(policy: LockoutPolicy, path: PathBuf) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let counters = match serde_json::from_reader(file) {
+            Ok(counters) => counters,
+            Err(e) if e.is_eof() => Counters::default(),
+            Err(e) => Err(e)?,
+        };
+
+        Ok(LoginThrottle {
+            policy,
+            counters,
+            path: Some(path),
+        })
+    }
+
+    pub fn policy(&self) -> &LockoutPolicy {
+        &self.policy
+    }
+
+    /// Refuses the attempt if either the user or the source is locked out or still backing off.
+    pub fn check(&self, username: &str, source: &str, now: DateTime<Utc>) -> std::result::Result<(), Denial> {
+        let user = self.counters.users.get(username);
+        let source = self.counters.sources.get(source);
+
+        // Report the longer of the two waits, so the caller isn't told to retry too early
+        let mut denial: Option<Denial> = None;
+        for record in [user, source].into_iter().flatten() {
+            if let Err(found) = record.check(now) {
+                denial = Some(match denial {
+                    Some(current) if until(current) >= until(found) => current,
+                    _ => found,
+                });
+            }
+        }
+
+        match denial {
+            Some(denial) => Err(denial),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&mut self, username: &str, source: &str, now: DateTime<Utc>) -> Result<()> {
+        let policy = self.policy;
+        self.counters.users.entry(username.to_string()).or_default().fail(&policy, now);
+        self.counters.sources.entry(source.to_string()).or_default().fail(&policy, now);
+        self.save()
+    }
+
+    /*
+        A successful login clears the user's counter but not the source's: an attacker who owns one
+        account shouldn't be able to reset their budget for guessing everybody else's password.
+     */
+    pub fn record_success(&mut self, username: &str) -> Result<()> {
+        if self.counters.users.remove(username).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn failures_for_user(&self, username: &str) -> u32 {
+        self.counters.users.get(username).map_or(0, |record| record.failures)
+    }
+
+    pub fn failures_for_source(&self, source: &str) -> u32 {
+        self.counters.sources.get(source).map_or(0, |record| record.failures)
+    }
+
+    /// Admin operation: forget every failure recorded against a user. Returns false if there were none.
+    pub fn unlock_user(&mut self, username: &str) -> Result<bool> {
+        let removed = self.counters.users.remove(username).is_some();
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Admin operation: forget every failure recorded against a source. Returns false if there were none.
+    pub fn unlock_source(&mut self, source: &str) -> Result<bool> {
+        let removed = self.counters.sources.remove(source).is_some();
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            write_json_atomically(path, &self.counters)?;
+        }
+        Ok(())
+    }
+}
+
+fn until(denial: Denial) -> DateTime<Utc> {
+    match denial {
+        Denial::Throttled { retry_at } => retry_at,
+        Denial::LockedOut { until } => until,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, FakeClock};
+    use chrono::TimeZone;
+
+    fn clock() -> FakeClock {
+        FakeClock::new(Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap())
+    }
+
+    #[test]
+    fn backoff_doubles_until_capped() {
+        let policy = LockoutPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::seconds(1));
+        assert_eq!(policy.backoff(2), Duration::seconds(2));
+        assert_eq!(policy.backoff(4), Duration::seconds(8));
+        assert_eq!(policy.backoff(20), Duration::seconds(300));
+        assert_eq!(policy.backoff(200), Duration::seconds(300));
+    }
+
+    #[test]
+    fn failure_throttles_until_backoff_elapses() {
+        let clock = clock();
+        let mut throttle = LoginThrottle::new(LockoutPolicy::default());
+
+        throttle.record_failure("charles", "10.0.0.1", clock.now()).unwrap();
+        throttle.record_failure("charles", "10.0.0.1", clock.now()).unwrap();
+
+        let retry_at = clock.now() + Duration::seconds(2);
+        assert_eq!(
+            throttle.check("charles", "10.0.0.1", clock.now()),
+            Err(Denial::Throttled { retry_at })
+        );
+
+        clock.advance(Duration::seconds(2));
+        assert_eq!(throttle.check("charles", "10.0.0.1", clock.now()), Ok(()));
+    }
+
+    #[test]
+    fn locks_out_after_max_failures_and_expires() {
+        let clock = clock();
+        let policy = LockoutPolicy::default();
+        let mut throttle = LoginThrottle::new(policy);
+
+        for _ in 0..policy.max_failures {
+            throttle.record_failure("charles", "10.0.0.1", clock.now()).unwrap();
+        }
+
+        let until = clock.now() + Duration::seconds(policy.lockout_secs);
+        // A fresh source doesn't help: the user itself is locked
+        assert_eq!(
+            throttle.check("charles", "10.0.0.2", clock.now()),
+            Err(Denial::LockedOut { until })
+        );
+
+        clock.advance(Duration::seconds(policy.lockout_secs));
+        assert_eq!(throttle.check("charles", "10.0.0.2", clock.now()), Ok(()));
+
+        // The first failure after the lockout starts counting from scratch
+        throttle.record_failure("charles", "10.0.0.2", clock.now()).unwrap();
+        assert_eq!(throttle.failures_for_user("charles"), 1);
+    }
+
+    #[test]
+    fn source_is_throttled_across_users() {
+        let clock = clock();
+        let policy = LockoutPolicy::default();
+        let mut throttle = LoginThrottle::new(policy);
+
+        for (i, user) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            clock.advance(Duration::seconds(1 << i));
+            throttle.record_failure(user, "10.0.0.1", clock.now()).unwrap();
+        }
+
+        assert_eq!(throttle.failures_for_source("10.0.0.1"), policy.max_failures);
+        assert!(matches!(
+            throttle.check("f", "10.0.0.1", clock.now()),
+            Err(Denial::LockedOut { .. })
+        ));
+        assert_eq!(throttle.check("f", "10.0.0.9", clock.now()), Ok(()));
+    }
+
+    #[test]
+    fn success_resets_user_but_not_source() {
+        let clock = clock();
+        let mut throttle = LoginThrottle::new(LockoutPolicy::default());
+
+        throttle.record_failure("charles", "10.0.0.1", clock.now()).unwrap();
+        throttle.record_success("charles").unwrap();
+
+        assert_eq!(throttle.failures_for_user("charles"), 0);
+        assert_eq!(throttle.failures_for_source("10.0.0.1"), 1);
+    }
+
+    #[test]
+    fn admin_unlock_clears_lockout() {
+        let clock = clock();
+        let policy = LockoutPolicy::default();
+        let mut throttle = LoginThrottle::new(policy);
+
+        for _ in 0..policy.max_failures {
+            throttle.record_failure("charles", "10.0.0.1", clock.now()).unwrap();
+        }
+
+        assert!(throttle.unlock_user("charles").unwrap());
+        assert!(throttle.unlock_source("10.0.0.1").unwrap());
+        assert_eq!(throttle.check("charles", "10.0.0.1", clock.now()), Ok(()));
+        assert!(!throttle.unlock_user("charles").unwrap());
+    }
+
+    #[test]
+    fn counters_survive_restart() {
+        let clock = clock();
+        let path = std::env::temp_dir().join(format!("lockout-{}.json", uuid::Uuid::new_v4()));
+
+        let mut throttle = LoginThrottle::open(LockoutPolicy::default(), path.clone()).unwrap();
+        throttle.record_failure("charles", "10.0.0.1", clock.now()).unwrap();
+        throttle.record_failure("charles", "10.0.0.1", clock.now()).unwrap();
+        drop(throttle);
+
+        let reopened = LoginThrottle::open(LockoutPolicy::default(), path.clone()).unwrap();
+        assert_eq!(reopened.failures_for_user("charles"), 2);
+        assert!(reopened.check("charles", "10.0.0.1", clock.now()).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,11 +1,4 @@
-mod quote;
-mod person;
-mod file;
-mod car;
-mod auth;
-mod cli;
-mod tasks;
-
+use Rust_Microsoft_Learn::{api_key, audit, auth, car, catalog, cli, clock, customer, file, format, inventory, money, person, pricing, quote, quoting, simulation, storage, tasks, totals, vin};
 use std::collections::HashMap;
 use rayon::prelude::*;
 use quote::*;
@@ -16,8 +9,23 @@ use std::f64::consts::PI;
 use auth::*;
 use regex::Regex;
 use structopt::StructOpt;
-use cli::{Action::*, CommandLineArgs};
+use cli::{Action, Action::*, CarAction, CommandLineArgs, CustomerAction, KeyAction};
+use catalog::{Catalog as VehicleCatalog, CatalogFilter, Listing};
+use inventory::{Inventory, Order};
+use format::OutputFormat;
+use pricing::{PricingEngine, PricingRules};
+use vin::Vin;
+use money::Money;
+use simulation::SimulationConfig;
+use quoting::QuoteService;
+use totals::LineItem;
+use clock::SystemClock;
+use customer::{Customer, CustomerRegistry};
+use std::path::PathBuf;
 use tasks::Task;
+use api_key::{ApiKeyStore, Scope};
+use audit::{AuditEvent, AuditLog};
+use chrono::{Datelike, Duration, Utc};
 
 
 mod math {
@@ -135,20 +143,26 @@ fn main() {
     let shadow_num = shadow_num * 2;
     println!("The number is {}.", shadow_num);
 
-    let quote = from_quote_factory(11.23, true, Some(sentence));
+    let quote = from_quote_factory(Money::from_cents(1_123), true, Some(sentence));
 
-    println!("{}", quote.amount);
+    println!("{}", quote.amount());
     println!("{}", quote.uuid);
-    println!("{}", quote.entangled.unwrap_or_default());
+    println!("{}", quote.entangled().unwrap_or_default());
     println!("{:#?}", quote);
 
+    // Quotes are only made out to registered customers
+    let mut customers = CustomerRegistry::new();
+    let customer_id = customers
+        .add(Customer::new("Acme Rentals", "1 Main St, Springfield", "US123456789"))
+        .expect("The demo customer is valid");
     let request = PostRequest {
-        customer_id: 123456
-    };
-    let response = PostResponse {
-        quote_uuid: String::from("cabc33f5-5edf-4ef0-b698-d3a33dfc15fe"),
-        amount: 99.99
+        customer_id,
+        items: vec![LineItem::new("MAT-01", "Floor mats", 2, Money::from_cents(4_999))],
+        discounts: Vec::new()
     };
+    // The service prices the request and stores the quote that the response's quote_uuid points to
+    let mut quotes = QuoteService::new(PricingRules::default(), customers, SystemClock);
+    let response = quotes.quote(&request).expect("The demo request is valid");
     let request = Pricing::Request(request);
     let response = Pricing::Response(response);
     println!("\nPricing enum structure: \n\n {:#?} \n\n {:#?}", request, response);
@@ -177,20 +191,24 @@ fn main() {
     // fruit[1] = fruit[1].to_lowercase().as_str();
     println!("Vector: {:?}", fruit); // borrow later used here
 
-    let new_car = car_factory(
-        9,
-        9
-    );
+    let new_car = CarOrder::new()
+        .make("Maserati")
+        .model("Folgore")
+        .color(Color::Blue)
+        .transmission(Transmission::Automatic)
+        .roof(true)
+        .miles(9)
+        .build();
 
     println!("{:?}", new_car);
 
-    let used_car = car_factory(
-        2,
-        150
-    );
+    let used_car = CarOrder::legacy_assignment(2, 150).and_then(|order| order.year(2022).build());
 
     println!("{:?}", used_car);
 
+    // Order number 0 used to panic inside the factory; now it's just an error
+    println!("{:?}", CarOrder::legacy_assignment(0, 0).and_then(CarOrder::build));
+
     let rust = "Programming in Rust";
     let scala = "Programming in Scala";
     let python = "Programming in Python";
@@ -224,18 +242,21 @@ fn main() {
         In essence, the original key binding is no longer a pointer to the HashMap key's value.
      */
 
-    // Define a hash map with <K, V> pairs.
-    // The hash map keys will correspond to the car order numbers.
-    // The hash map values will be the order details for each as defined in a Car struct.
+    // The inventory keeps every order under an order number, and tracks which state it's in.
+    // This one lives in memory; Inventory::open keeps the orders in a JSON file instead.
 
-    let mut car_orders = HashMap::new();
+    let mut car_orders = Inventory::new();
 
     let mut miles = 0;
 
     // A binding in Rust is a named reference to a value, which can be mutable or immutable
     for order in 1 .. 12 {
-        car_orders.insert(order, car_factory(order, miles));
-        println!("Car order {}: {:?}", order, car_orders.get(&order));
+        // Legacy orders have no model year; the used ones need one to be graded
+        let car = CarOrder::legacy_assignment(order, miles)
+            .and_then(|order| order.year(2022).build())
+            .expect("Legacy order numbers start at 1");
+        let id = car_orders.place(car, Utc::now()).expect("In-memory inventory can't fail to save");
+        println!("Car order {}: {:?}", id, car_orders.get(id));
 
         // Reset miles for order variety
         if miles == 2100 {
@@ -745,8 +766,64 @@ fn main() {
     let CommandLineArgs {
         action,
         journal_file,
+        api_key_file,
+        catalog_file,
+        inventory_file,
+        customers_file,
+        quotes_file,
+        audit_file,
+        api_key,
     } = CommandLineArgs::from_args();
 
+    // Only the journal actions need a journal file; everything else is handled on its own.
+    match action {
+        VerifyAudit => verify_audit(audit_file),
+        Key(key_action) => manage_api_keys(api_key_file, key_action),
+        Catalog { make, year, color, transmission, roof } => {
+            let filter = CatalogFilter { make, year, color, transmission, roof };
+            browse_catalog(catalog_file, &filter)
+        }
+        Car(car_action) => manage_car_orders(inventory_file, catalog_file, car_action),
+        Customer(customer_action) => manage_customers(customers_file, quotes_file, customer_action),
+        Simulate { orders, seed, new_percent, threads } => {
+            run_simulation(catalog_file, SimulationConfig { orders, seed, new_percent }, threads)
+        }
+        action => run_journal_action(action, journal_file, api_key, api_key_file, audit_file),
+    }
+}
+
+fn run_journal_action(
+    action: Action,
+    journal_file: Option<PathBuf>,
+    api_key: Option<String>,
+    api_key_file: PathBuf,
+    audit_file: PathBuf,
+) {
+    /*
+        Automation accounts present an API key (usually through the RUSTY_JOURNAL_API_KEY
+        environment variable) instead of logging in. The key must carry the scope the action needs.
+        Whoever is acting ends up in the audit log: the key's name, or else the local OS user.
+     */
+    let actor = match api_key {
+        Some(api_key) => {
+            let scope = match action {
+                List { .. } => Scope::JournalRead,
+                _ => Scope::JournalWrite,
+            };
+            let mut store = ApiKeyStore::open(api_key_file).expect("Failed to open API key file");
+            match store.authenticate(&api_key, scope, Utc::now()) {
+                Ok(key) => format!("api-key:{}", key.name),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| String::from("unknown")),
+    };
+
     /*
         Unpack the journal file.
         Because journal_file is of type Option<PathBuf>, we need to extract the path to our journal
@@ -755,13 +832,259 @@ fn main() {
      */
     let journal_file = journal_file.expect("Failed to find journal file");
 
-    // Perform the action.
+    // Perform the action, recording every change to the journal in the audit log.
+    let mut audit_log = AuditLog::open(audit_file).expect("Failed to open audit log");
+    let performed = match action {
+        Add { text } => tasks::add_task(journal_file, Task::new(text.clone()))
+            .map(|()| Some(AuditEvent::TaskAdded { text })),
+        List { format } => tasks::list_tasks(journal_file, format).map(|()| None),
+        Done { position } => tasks::complete_task(journal_file, position)
+            .map(|task| Some(AuditEvent::TaskCompleted { position, text: task.text })),
+        _ => unreachable!("not a journal action"),
+    };
+    let event = match performed {
+        Ok(event) => event,
+        Err(error) => {
+            eprintln!("Failed to perform action: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    // The journal has already changed by now, so a failure here is reported rather than panicking
+    if let Some(event) = event {
+        let change = event.to_string();
+        if let Err(error) = audit_log.append(&actor, event, Utc::now()) {
+            eprintln!("The journal was changed ({}), but the audit log could not record it: {}", change, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn verify_audit(audit_file: PathBuf) {
+    match audit::verify(audit_file) {
+        Ok(summary) => println!(
+            "Audit log is intact: {} entries, head hash {}",
+            summary.entries, summary.head_hash
+        ),
+        Err(error) => {
+            eprintln!("Audit log verification failed: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn manage_api_keys(api_key_file: PathBuf, action: KeyAction) {
+    let mut store = ApiKeyStore::open(api_key_file).expect("Failed to open API key file");
     match action {
-        Add { text } => tasks::add_task(journal_file, Task::new(text)),
-        List => tasks::list_tasks(journal_file),
-        Done { position } => tasks::complete_task(journal_file, position),
+        KeyAction::Create { name, scopes, expires_in_days } => {
+            let now = Utc::now();
+            let expires_at = expires_in_days.map(|days| now + Duration::days(days));
+            let (plain, key) = store
+                .create(&name, scopes, expires_at, now)
+                .expect("Failed to create API key");
+            println!(
+                "{}\n\nStore this key now, it will not be shown again:\n{}",
+                key,
+                plain.expose_secret()
+            );
+        }
+        KeyAction::List => store.list().iter().for_each(|key| println!("{}", key)),
+        KeyAction::Revoke { id } => {
+            if !store.revoke(&id, Utc::now()).expect("Failed to revoke API key") {
+                println!("No active API key with id {}", id);
+            }
+        }
+    }
+}
+
+fn browse_catalog(catalog_file: PathBuf, filter: &CatalogFilter) {
+    let catalog = VehicleCatalog::load(catalog_file).expect("Failed to load vehicle catalog");
+    let models = catalog.search(filter);
+    if models.is_empty() {
+        println!("No models in the catalog match.");
+    }
+    for (make, model) in models {
+        println!("{}", Listing(make, model));
+    }
+}
+
+fn manage_car_orders(inventory_file: PathBuf, catalog_file: PathBuf, action: CarAction) {
+    let mut inventory = Inventory::open(inventory_file).expect("Failed to open inventory file");
+    let fail = |error: &dyn std::fmt::Display| -> ! {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    };
+
+    match action {
+        CarAction::Order { make, model, year, color, transmission, roof, miles, certified, accidents, service_records } => {
+            let catalog = VehicleCatalog::load(catalog_file).expect("Failed to load vehicle catalog");
+            let car = CarOrder::new()
+                .make(&make)
+                .model(&model)
+                .year(year)
+                .color(color)
+                .transmission(transmission)
+                .roof(roof.into())
+                .miles(miles)
+                .certified(certified)
+                .accidents(accidents)
+                .service_records(service_records)
+                .build_against(&catalog)
+                .unwrap_or_else(|error| fail(&error));
+            let price = PricingEngine::new(&catalog, PricingRules::default())
+                .price(&car, &[])
+                .unwrap_or_else(|error| fail(&error));
+            let id = inventory.place(car, Utc::now()).unwrap_or_else(|error| fail(&error));
+            println!("Placed order #{}\n\n{}", id, price);
+        }
+        CarAction::List { format, state, color, age, output } => {
+            let orders: Vec<&Order> = inventory
+                .orders()
+                .iter()
+                .filter(|order| state.is_none_or(|state| order.state == state))
+                .filter(|order| color.is_none_or(|color| order.car.color == color))
+                .filter(|order| age.is_none_or(|age| order.car.age.0 == age))
+                .collect();
+            if orders.is_empty() && format == OutputFormat::Text && output.is_none() {
+                println!("No car orders.");
+            } else {
+                emit(&inventory::export_orders(&orders, format), output);
+            }
+        }
+        CarAction::Show { id } => {
+            let order = inventory.get(id).unwrap_or_else(|| fail(&format!("there is no order #{}", id)));
+            let grade = order.car.condition.grade(Utc::now().year() as u16);
+            let vin = order.car.vin.as_ref().map_or("-".to_string(), |vin| vin.to_string());
+            println!("{}\nVIN: {}\nCondition: {}", order, vin, grade);
+            if let Some(quote) = order.quote {
+                println!("Quote: {}", quote);
+            }
+            println!();
+            for change in &order.history {
+                println!("  {:<10} {}", change.state, change.at.with_timezone(&chrono::Local).format("%F %H:%M"));
+            }
+        }
+        CarAction::Cancel { id } => {
+            let order = inventory.cancel(id, Utc::now()).unwrap_or_else(|error| fail(&error));
+            println!("Cancelled order #{}", order.id);
+        }
+        CarAction::Vin { vin } => {
+            let parts = Vin::parse(&vin).unwrap_or_else(|error| fail(&error)).decode();
+            let catalog = VehicleCatalog::load(catalog_file).expect("Failed to load vehicle catalog");
+            let manufacturer = catalog.manufacturer(&parts.wmi).map_or("unknown", |make| make.name.as_str());
+            println!("Manufacturer: {} ({})", manufacturer, parts.wmi);
+            println!("Model code:   {}", parts.model_code);
+            println!("Model year:   {}", parts.year);
+            println!("Plant:        {}", parts.plant);
+            println!("Serial:       {:06}", parts.serial);
+        }
+        CarAction::Report { format, output } => emit(&inventory.report().export(format), output),
+    }
+}
+
+fn manage_customers(customers_file: PathBuf, quotes_file: PathBuf, action: CustomerAction) {
+    let customers = CustomerRegistry::open(customers_file).expect("Failed to open customer file");
+    let mut quotes = QuoteService::open(quotes_file, PricingRules::default(), customers, SystemClock)
+        .expect("Failed to open quote file");
+    let fail = |error: &dyn std::fmt::Display| -> ! {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    };
+    let percent = |label: String, basis_points| pricing::Discount::Percent { label, basis_points };
+
+    match action {
+        CustomerAction::Add { name, address, tax_id, currency, discount_percent, discount_label } => {
+            let mut customer = Customer::new(&name, &address, &tax_id).paying_in(currency);
+            if let Some(basis_points) = discount_percent {
+                customer = customer.with_discount(percent(discount_label, basis_points));
+            }
+            let id = quotes.customers_mut().add(customer).unwrap_or_else(|error| fail(&error));
+            println!("Added customer #{}", id);
+        }
+        CustomerAction::List => {
+            if quotes.customers().is_empty() {
+                println!("No customers.");
+            }
+            quotes.customers().list().iter().for_each(|customer| println!("{}", customer));
+        }
+        CustomerAction::Show { id } => {
+            let customer = quotes.customers().get(id).unwrap_or_else(|| fail(&format!("there is no customer #{}", id)));
+            println!("Customer #{}: {}", customer.id, customer.name);
+            println!("Billing address: {}", customer.billing_address);
+            println!("Tax ID:          {}", customer.tax_id);
+            println!("Pays in:         {}", customer.currency);
+            println!("Discount:        {}", customer.discount_summary());
+            println!("Quotes:          {}", quotes.for_customer(id).len());
+        }
+        CustomerAction::Edit { id, name, address, tax_id, currency, discount_percent, discount_label, no_discount } => {
+            let mut customer = quotes
+                .customers()
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| fail(&format!("there is no customer #{}", id)));
+            customer.name = name.unwrap_or(customer.name);
+            customer.billing_address = address.unwrap_or(customer.billing_address);
+            customer.tax_id = tax_id.unwrap_or(customer.tax_id);
+            customer.currency = currency.unwrap_or(customer.currency);
+            if no_discount {
+                customer.default_discount = None;
+            } else if let Some(basis_points) = discount_percent {
+                customer.default_discount = Some(percent(discount_label, basis_points));
+            }
+            let customer = quotes.customers_mut().update(customer).unwrap_or_else(|error| fail(&error));
+            println!("{}", customer);
+        }
+        CustomerAction::Quotes { id } => {
+            if quotes.customers().get(id).is_none() {
+                fail(&format!("there is no customer #{}", id));
+            }
+            let records = quotes.for_customer(id);
+            if records.is_empty() {
+                println!("No quotes for customer #{}.", id);
+            }
+            for record in records {
+                let quote = record.latest();
+                let valid_until = quote
+                    .valid_until()
+                    .map_or("-".to_string(), |at| at.with_timezone(&chrono::Local).format("%F").to_string());
+                let converted =
+                    quote.converted_totals().map_or(String::new(), |converted| format!(" ({})", converted.total));
+                println!(
+                    "{} rev {:<3} {:<10} {:>14}{}  valid until {}",
+                    quote.uuid,
+                    quote.index(),
+                    quote.status(),
+                    quote.amount(),
+                    converted,
+                    valid_until
+                );
+            }
+        }
+    }
+}
+
+// Prints exported text, or saves it to the file given with --output
+fn emit(text: &str, output: Option<PathBuf>) {
+    match output {
+        Some(path) => storage::write_atomically(&path, text.as_bytes()).expect("Failed to write output file"),
+        None => print!("{}", text),
+    }
+}
+
+fn run_simulation(catalog_file: PathBuf, config: SimulationConfig, threads: Option<usize>) {
+    let catalog = VehicleCatalog::load(catalog_file).expect("Failed to load vehicle catalog");
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = threads {
+        pool = pool.num_threads(threads);
+    }
+    let pool = pool.build().expect("Failed to start simulation threads");
+    match pool.install(|| simulation::simulate(&catalog, &PricingRules::default(), &config)) {
+        Ok(report) => println!("{}", report),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
     }
-        .expect("Failed to perform action")
 }
 
 fn is_even(num: i32) -> bool {
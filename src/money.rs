@@ -0,0 +1,582 @@
+// An exact amount of money in one currency, counted in that currency's minor units
+
+/*
+    Floating point numbers can't represent most decimal fractions exactly: 0.1 + 0.2 is not 0.3 in
+    an f32 or f64, and the error grows as amounts are added up. Money stores a whole number of minor
+    units (cents for USD, nothing smaller than a yen for JPY) in an i64 instead, along with its ISO
+    4217 currency. Adding and subtracting are exact, and rounding only ever happens where a
+    fraction of a minor unit comes up, which is when taking a percentage or a ratio. Those take an
+    explicit Rounding:
+
+      HalfUp    halves round away from zero: 0.5 cents -> 1 cent, -0.5 -> -1. What people expect
+                on an invoice; percent() uses it.
+      HalfEven  halves round to the even neighbour: 0.5 -> 0, 1.5 -> 2. Also called banker's
+                rounding; over many amounts the roundings cancel out instead of drifting upwards.
+
+    Percentages are given in basis points, hundredths of a percent, so 8.25% is 825 and no floats
+    are needed anywhere.
+
+    The operators (+, -, +=, ...) panic when the currencies differ or the result overflows, which
+    is always a bug; the checked_ methods return a MoneyError instead, for amounts that come from
+    outside. There is no Sum: adding up no amounts at all has no currency to be in, so sum_in()
+    takes the currency up front. To split an amount, use allocate(): the parts always add up to the
+    original, with any leftover minor units handed out one at a time.
+ */
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Currency {
+    #[default]
+    USD,
+    EUR,
+    GBP,
+    CHF,
+    JPY,
+    KWD,
+}
+
+impl Currency {
+    pub const ALL: [Currency; 6] = [Currency::USD, Currency::EUR, Currency::GBP, Currency::CHF, Currency::JPY, Currency::KWD];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Currency::USD => "USD",
+            Currency::EUR => "EUR",
+            Currency::GBP => "GBP",
+            Currency::CHF => "CHF",
+            Currency::JPY => "JPY",
+            Currency::KWD => "KWD",
+        }
+    }
+
+    /// Digits after the decimal point, as ISO 4217 lists them.
+    pub fn minor_units(self) -> u32 {
+        match self {
+            Currency::JPY => 0,
+            Currency::KWD => 3,
+            _ => 2,
+        }
+    }
+
+    fn symbol(self) -> Option<&'static str> {
+        match self {
+            Currency::USD => Some("$"),
+            Currency::EUR => Some("€"),
+            Currency::GBP => Some("£"),
+            Currency::JPY => Some("¥"),
+            Currency::CHF | Currency::KWD => None,
+        }
+    }
+
+    // Minor units per major unit
+    fn scale(self) -> i64 {
+        10_i64.pow(self.minor_units())
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.code())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Currency::ALL
+            .iter()
+            .find(|currency| currency.code().eq_ignore_ascii_case(s.trim()))
+            .copied()
+            .ok_or_else(|| format!("unknown currency '{}'", s))
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    #[default]
+    HalfUp,
+    HalfEven,
+}
+
+impl Rounding {
+    /// `numerator / denominator` rounded to a whole number.
+    pub fn divide(self, numerator: i128, denominator: i128) -> i128 {
+        let (quotient, remainder) = (numerator / denominator, numerator % denominator);
+        let sign = if (numerator < 0) != (denominator < 0) { -1 } else { 1 };
+        let twice = remainder.abs() * 2;
+        let away = match twice.cmp(&denominator.abs()) {
+            std::cmp::Ordering::Less => false,
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Equal => match self {
+                Rounding::HalfUp => true,
+                Rounding::HalfEven => quotient % 2 != 0,
+            },
+        };
+        if away { quotient + sign } else { quotient }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MoneyError {
+    CurrencyMismatch { expected: Currency, found: Currency },
+    Overflow,
+    NoParts,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch { expected, found } => {
+                write!(f, "can't combine {} with {} without converting", found, expected)
+            }
+            MoneyError::Overflow => write!(f, "amount is too large"),
+            MoneyError::NoParts => write!(f, "can't split an amount into no parts"),
+        }
+    }
+}
+
+/// Ordering only means something between amounts in the same currency: amounts in different
+/// currencies are neither smaller nor larger than each other. Use checked_cmp() or checked_min()
+/// where that has to be an error.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money {
+    minor: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub const ZERO: Money = Money { minor: 0, currency: Currency::USD };
+
+    pub fn new(minor_units: i64, currency: Currency) -> Self {
+        Money { minor: minor_units, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::new(0, currency)
+    }
+
+    /// Whole units of the currency: dollars, euros, yen.
+    pub fn from_major(units: i64, currency: Currency) -> Self {
+        Money::new(units * currency.scale(), currency)
+    }
+
+    pub fn from_cents(cents: i64) -> Self {
+        Money::new(cents, Currency::USD)
+    }
+
+    pub fn from_dollars(dollars: i64) -> Self {
+        Money::from_major(dollars, Currency::USD)
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.minor
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor < 0
+    }
+
+    pub fn abs(&self) -> Money {
+        Money::new(self.minor.abs(), self.currency)
+    }
+
+    /// Adds up amounts in `currency`. Adding up nothing gives zero in that currency.
+    pub fn sum_in<I: IntoIterator<Item = Money>>(currency: Currency, amounts: I) -> Result<Money, MoneyError> {
+        amounts.into_iter().try_fold(Money::zero(currency), Money::checked_add)
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        let minor = self.minor.checked_add(other.minor).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor, self.currency))
+    }
+
+    pub fn checked_cmp(self, other: Money) -> Result<Ordering, MoneyError> {
+        self.same_currency(other)?;
+        Ok(self.minor.cmp(&other.minor))
+    }
+
+    /// The smaller of two amounts in the same currency.
+    pub fn checked_min(self, other: Money) -> Result<Money, MoneyError> {
+        Ok(match self.checked_cmp(other)? {
+            Ordering::Greater => other,
+            _ => self,
+        })
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        let minor = self.minor.checked_sub(other.minor).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor, self.currency))
+    }
+
+    pub fn checked_mul(self, quantity: i64) -> Result<Money, MoneyError> {
+        let minor = self.minor.checked_mul(quantity).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor, self.currency))
+    }
+
+    /// This amount times `numerator / denominator`, rounded to a whole minor unit.
+    pub fn checked_mul_ratio(self, numerator: i128, denominator: i128, rounding: Rounding) -> Result<Money, MoneyError> {
+        let scaled = (self.minor as i128).checked_mul(numerator).ok_or(MoneyError::Overflow)?;
+        let minor = rounding.divide(scaled, denominator);
+        Ok(Money::new(i64::try_from(minor).map_err(|_| MoneyError::Overflow)?, self.currency))
+    }
+
+    /// `basis_points` hundredths of a percent of this amount, rounded half up.
+    pub fn percent(&self, basis_points: i64) -> Money {
+        self.percent_rounded(basis_points, Rounding::HalfUp)
+    }
+
+    pub fn percent_rounded(&self, basis_points: i64, rounding: Rounding) -> Money {
+        self.checked_mul_ratio(basis_points as i128, 10_000, rounding).expect("percentage overflowed")
+    }
+
+    pub fn times(&self, quantity: i64) -> Money {
+        self.checked_mul(quantity).expect("amount overflowed")
+    }
+
+    /// Splits the amount into `parts` nearly equal amounts that add up to it exactly. The first
+    /// parts get one minor unit more when it doesn't divide evenly.
+    pub fn allocate(&self, parts: usize) -> Result<Vec<Money>, MoneyError> {
+        self.allocate_by(&vec![1; parts])
+    }
+
+    /// Splits the amount in proportion to `weights`. The parts add up to the amount exactly; the
+    /// minor units left over after rounding down go one each to the parts that lost the most to
+    /// rounding, earlier parts first among equals.
+    pub fn allocate_by(&self, weights: &[u64]) -> Result<Vec<Money>, MoneyError> {
+        let total: u128 = weights.iter().map(|weight| *weight as u128).sum();
+        if total == 0 {
+            return Err(MoneyError::NoParts);
+        }
+
+        let amount = self.minor.unsigned_abs() as u128;
+        let mut shares: Vec<(u128, u128)> = weights
+            .iter()
+            .map(|weight| {
+                let exact = amount * *weight as u128;
+                (exact / total, exact % total)
+            })
+            .collect();
+
+        let handed_out: u128 = shares.iter().map(|(share, _)| share).sum();
+        let mut by_remainder: Vec<usize> = (0..shares.len()).collect();
+        by_remainder.sort_by(|a, b| shares[*b].1.cmp(&shares[*a].1).then(a.cmp(b)));
+        for index in by_remainder.into_iter().take((amount - handed_out) as usize) {
+            shares[index].0 += 1;
+        }
+
+        let sign = self.minor.signum();
+        Ok(shares.into_iter().map(|(share, _)| Money::new(sign * share as i64, self.currency)).collect())
+    }
+
+    fn same_currency(&self, other: Money) -> Result<(), MoneyError> {
+        if self.currency == other.currency {
+            Ok(())
+        } else {
+            Err(MoneyError::CurrencyMismatch { expected: self.currency, found: other.currency })
+        }
+    }
+
+    // The number without symbol or code: "-1234.56", "500" for yen
+    fn decimal(&self) -> String {
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let digits = self.currency.minor_units() as usize;
+        let minor = self.minor.unsigned_abs();
+        let scale = self.currency.scale() as u64;
+        if digits == 0 {
+            format!("{}{}", sign, minor)
+        } else {
+            format!("{}{}.{:0width$}", sign, minor / scale, minor % scale, width = digits)
+        }
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, other: Money) -> Money {
+        self.checked_add(other).unwrap_or_else(|error| panic!("{}", error))
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, other: Money) -> Money {
+        self.checked_sub(other).unwrap_or_else(|error| panic!("{}", error))
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, other: Money) {
+        *self = *self - other;
+    }
+}
+
+impl PartialOrd for Money {
+    fn partial_cmp(&self, other: &Money) -> Option<Ordering> {
+        self.checked_cmp(*other).ok()
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+    fn neg(self) -> Money {
+        Money::new(-self.minor, self.currency)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decimal = self.decimal();
+        let (sign, digits) = match decimal.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", decimal.as_str()),
+        };
+        let text = match self.currency.symbol() {
+            Some(symbol) => format!("{}{}{}", sign, symbol, digits),
+            None => format!("{}{} {}", sign, self.currency.code(), digits),
+        };
+        // Let callers pad money in tables with {:>12} and the like
+        f.pad(&text)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseMoneyError(pub String);
+
+impl fmt::Display for ParseMoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' is not an amount of money, expected something like 1234.56 or EUR 1234.56", self.0)
+    }
+}
+
+impl FromStr for Money {
+    type Err = ParseMoneyError;
+
+    // Accepts "1234.56" (US dollars), "EUR 1234.56", "1234.56 EUR", "-0.99", "$12.5" and "€12.50"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseMoneyError(s.to_string());
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (currency, text) = match words[..] {
+            [text] => (None, text),
+            [first, second] => match (first.parse::<Currency>(), second.parse::<Currency>()) {
+                (Ok(currency), _) => (Some(currency), second),
+                (_, Ok(currency)) => (Some(currency), first),
+                _ => return Err(error()),
+            },
+            _ => return Err(error()),
+        };
+
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let symbol = Currency::ALL
+            .iter()
+            .find(|candidate| candidate.symbol().is_some_and(|symbol| text.starts_with(symbol)));
+        let (currency, text) = match (currency, symbol) {
+            (Some(code), Some(symbol)) if code != *symbol => return Err(error()),
+            (code, Some(symbol)) => (code.unwrap_or(*symbol), &text[symbol.symbol().unwrap_or_default().len()..]),
+            (code, None) => (code.unwrap_or_default(), text),
+        };
+
+        let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+        let digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+        let places = currency.minor_units() as usize;
+        if !digits(whole) || (!fraction.is_empty() && !digits(fraction)) || fraction.len() > places {
+            return Err(error());
+        }
+
+        let whole: i64 = whole.parse().map_err(|_| error())?;
+        let fraction: i64 = if places == 0 { 0 } else { format!("{:0<places$}", fraction).parse().map_err(|_| error())? };
+        let minor = whole.checked_mul(currency.scale()).and_then(|m| m.checked_add(fraction)).ok_or_else(error)?;
+        Ok(Money::new(if negative { -minor } else { minor }, currency))
+    }
+}
+
+// Stored as currency code and decimal, such as "USD 99.99", so files never hold a float.
+// Plain decimals from before amounts had a currency are read as US dollars.
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{} {}", self.currency.code(), self.decimal()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eur(cents: i64) -> Money {
+        Money::new(cents, Currency::EUR)
+    }
+
+    #[test]
+    fn adding_cents_is_exact() {
+        let total = Money::sum_in(Currency::USD, std::iter::repeat_n(Money::from_cents(10), 3)).unwrap();
+        assert_eq!(total, "0.30".parse().unwrap());
+        assert_eq!(Money::from_dollars(100) - Money::from_cents(1), Money::from_cents(9_999));
+        assert_eq!(Money::from_major(5, Currency::JPY).minor_units(), 5);
+        assert_eq!(Money::from_major(5, Currency::KWD).minor_units(), 5_000);
+    }
+
+    #[test]
+    fn sums_are_in_the_currency_asked_for() {
+        assert_eq!(Money::sum_in(Currency::EUR, []), Ok(eur(0)));
+        assert_eq!(Money::sum_in(Currency::EUR, [eur(150), eur(-50)]), Ok(eur(100)));
+        assert_eq!(
+            Money::sum_in(Currency::EUR, [eur(150), Money::from_cents(50)]),
+            Err(MoneyError::CurrencyMismatch { expected: Currency::EUR, found: Currency::USD })
+        );
+        assert_eq!(Money::sum_in(Currency::EUR, [eur(i64::MAX), eur(1)]), Err(MoneyError::Overflow));
+    }
+
+    #[test]
+    fn percent_rounds_half_up_by_default() {
+        // 8.25% of $10.10 is 83.325 cents
+        assert_eq!(Money::from_cents(1010).percent(825), Money::from_cents(83));
+        // 50% of 1 cent is half a cent
+        assert_eq!(Money::from_cents(1).percent(5_000), Money::from_cents(1));
+        assert_eq!(Money::from_cents(-1).percent(5_000), Money::from_cents(-1));
+        assert_eq!(Money::from_cents(3).percent(3_333), Money::from_cents(1));
+    }
+
+    #[test]
+    fn half_even_rounds_halves_to_the_even_neighbour() {
+        let cases = [
+            // cents, basis points, half up, half even
+            (1, 5_000, 1, 0),
+            (3, 5_000, 2, 2),
+            (5, 5_000, 3, 2),
+            (-5, 5_000, -3, -2),
+            (-7, 5_000, -4, -4),
+            (1010, 825, 83, 83),
+            (1_000, 125, 13, 12),
+        ];
+        for (cents, basis_points, half_up, half_even) in cases {
+            let amount = Money::from_cents(cents);
+            assert_eq!(amount.percent_rounded(basis_points, Rounding::HalfUp), Money::from_cents(half_up), "{}", cents);
+            assert_eq!(amount.percent_rounded(basis_points, Rounding::HalfEven), Money::from_cents(half_even), "{}", cents);
+        }
+    }
+
+    #[test]
+    fn checked_arithmetic_reports_problems() {
+        assert_eq!(
+            Money::from_cents(1).checked_add(eur(1)),
+            Err(MoneyError::CurrencyMismatch { expected: Currency::USD, found: Currency::EUR })
+        );
+        assert_eq!(Money::from_cents(i64::MAX).checked_add(Money::from_cents(1)), Err(MoneyError::Overflow));
+        assert_eq!(Money::from_cents(i64::MAX).checked_mul(2), Err(MoneyError::Overflow));
+        assert_eq!(eur(100).checked_sub(eur(250)), Ok(eur(-150)));
+        assert_eq!(eur(1_000).checked_mul_ratio(1, 3, Rounding::HalfEven), Ok(eur(333)));
+    }
+
+    #[test]
+    fn only_amounts_in_the_same_currency_compare() {
+        assert!(eur(100) < eur(250));
+        assert_eq!(eur(100).checked_cmp(eur(250)), Ok(Ordering::Less));
+        assert_eq!(eur(250).checked_min(eur(100)), Ok(eur(100)));
+
+        // A dollar is neither more nor less than a euro
+        assert_eq!(eur(100).partial_cmp(&Money::from_cents(250)), None);
+        assert_eq!(
+            eur(100).checked_min(Money::from_cents(250)),
+            Err(MoneyError::CurrencyMismatch { expected: Currency::EUR, found: Currency::USD })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "can't combine EUR with USD")]
+    fn operators_refuse_to_mix_currencies() {
+        let _ = Money::from_cents(1) + eur(1);
+    }
+
+    #[test]
+    fn allocation_never_loses_a_cent() {
+        assert_eq!(Money::from_cents(100).allocate(3), Ok(vec![Money::from_cents(34), Money::from_cents(33), Money::from_cents(33)]));
+        assert_eq!(eur(-5).allocate(2), Ok(vec![eur(-3), eur(-2)]));
+        assert_eq!(Money::from_cents(100).allocate(0), Err(MoneyError::NoParts));
+
+        // 70/20/10 of 0.05: exact shares are 3.5, 1 and 0.5 cents
+        assert_eq!(eur(5).allocate_by(&[70, 20, 10]), Ok(vec![eur(4), eur(1), eur(0)]));
+
+        for cents in [0, 1, 99, 1_000_001, -7] {
+            for parts in 1..8 {
+                let split = Money::from_cents(cents).allocate(parts).unwrap();
+                assert_eq!(split.len(), parts);
+                assert_eq!(Money::sum_in(Currency::USD, split.iter().copied()).unwrap().minor_units(), cents);
+                let spread = split.iter().map(Money::minor_units).max().unwrap() - split.iter().map(Money::minor_units).min().unwrap();
+                assert!(spread <= 1, "{} in {} parts", cents, parts);
+            }
+        }
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        assert_eq!("99.99".parse(), Ok(Money::from_cents(9_999)));
+        assert_eq!("$12.5".parse(), Ok(Money::from_cents(1_250)));
+        assert_eq!("-0.05".parse(), Ok(Money::from_cents(-5)));
+        assert_eq!("EUR 12.50".parse(), Ok(eur(1_250)));
+        assert_eq!("12.5 eur".parse(), Ok(eur(1_250)));
+        assert_eq!("-€0.01".parse(), Ok(eur(-1)));
+        assert!("€-0.01".parse::<Money>().is_err());
+        assert_eq!("JPY 1500".parse(), Ok(Money::new(1_500, Currency::JPY)));
+        assert!("JPY 15.5".parse::<Money>().is_err());
+        assert!("USD €5".parse::<Money>().is_err());
+        assert!("12.345".parse::<Money>().is_err());
+        assert!("1e3".parse::<Money>().is_err());
+
+        assert_eq!(Money::from_cents(-123_456).to_string(), "-$1234.56");
+        assert_eq!(Money::new(1_234_567, Currency::KWD).to_string(), "KWD 1234.567");
+        assert_eq!(Money::new(500, Currency::JPY).to_string(), "¥500");
+        assert_eq!(format!("{:>9}", Money::from_cents(5)), "    $0.05");
+    }
+
+    #[test]
+    fn serializes_with_its_currency() {
+        let amount = eur(9_999);
+        assert_eq!(serde_json::to_string(&amount).unwrap(), "\"EUR 99.99\"");
+        assert_eq!(serde_json::from_str::<Money>("\"EUR 99.99\"").unwrap(), amount);
+        assert_eq!(serde_json::from_str::<Money>("\"99.99\"").unwrap(), Money::from_cents(9_999));
+    }
+}
@@ -0,0 +1,289 @@
+// Prices a Car order line by line, starting from the catalog's base price for the model
+
+/*
+    The price is built up as an itemized breakdown rather than a single number, so a customer (or a
+    test) can see where every cent came from:
+
+        base price (from the catalog)
+      + transmission surcharge       Automatic and Semi-Auto cost extra over the Manual
+      + roof surcharge               a Convertible costs extra over the Hardtop
+      - mileage depreciation         Used cars only, a fixed amount per mile up to a cap
+      = subtotal
+      - discounts                    percentages of the subtotal or fixed amounts, never below zero
+      + tax                          on the discounted subtotal
+      = total
+
+    All amounts are Money (whole cents) and all rates are basis points, so there are no floats.
+ */
+
+use crate::car::{Age, Car, Roof, Transmission};
+use crate::catalog::Catalog;
+use crate::money::{Money, MoneyError};
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PricingRules {
+    pub semi_auto_surcharge: Money,
+    pub automatic_surcharge: Money,
+    pub convertible_surcharge: Money,
+    pub depreciation_per_mile: Money,
+    /// Depreciation never takes more than this share of the base price, in basis points.
+    pub max_depreciation_bp: i64,
+    /// Sales tax on the discounted subtotal, in basis points.
+    pub tax_rate_bp: i64,
+}
+
+impl Default for PricingRules {
+    fn default() -> Self {
+        PricingRules {
+            semi_auto_surcharge: Money::from_dollars(1_200),
+            automatic_surcharge: Money::from_dollars(2_500),
+            convertible_surcharge: Money::from_dollars(6_000),
+            depreciation_per_mile: Money::from_cents(15),
+            max_depreciation_bp: 6_000,
+            tax_rate_bp: 825,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Discount {
+    /// A share of the subtotal, in basis points.
+    Percent { label: String, basis_points: i64 },
+    Fixed { label: String, amount: Money },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PriceLine {
+    pub label: String,
+    pub amount: Money,
+}
+
+impl PriceLine {
+    pub fn new(label: impl Into<String>, amount: Money) -> Self {
+        PriceLine { label: label.into(), amount }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PriceBreakdown {
+    /// Base price, surcharges and depreciation; these add up to the subtotal.
+    pub lines: Vec<PriceLine>,
+    pub subtotal: Money,
+    /// Negative amounts; they add up to at most the subtotal.
+    pub discounts: Vec<PriceLine>,
+    pub tax: Money,
+    pub total: Money,
+}
+
+impl PriceBreakdown {
+    pub fn taxable(&self) -> Money {
+        self.discounts.iter().fold(self.subtotal, |sum, line| sum + line.amount)
+    }
+}
+
+impl fmt::Display for PriceBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = |f: &mut fmt::Formatter<'_>, label: &str, amount: Money| writeln!(f, "{:<32} {:>14}", label, amount);
+        for item in &self.lines {
+            line(f, &item.label, item.amount)?;
+        }
+        line(f, "Subtotal", self.subtotal)?;
+        for item in &self.discounts {
+            line(f, &item.label, item.amount)?;
+        }
+        line(f, "Tax", self.tax)?;
+        write!(f, "{:<32} {:>14}", "Total", self.total)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PricingError {
+    UnknownModel { make: String, model: String },
+    NegativeDiscount(String),
+    Money(MoneyError),
+}
+
+impl fmt::Display for PricingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PricingError::UnknownModel { make, model } => write!(f, "{} {} has no price in the catalog", make, model),
+            PricingError::NegativeDiscount(label) => write!(f, "discount '{}' would raise the price", label),
+            PricingError::Money(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<MoneyError> for PricingError {
+    fn from(error: MoneyError) -> Self {
+        PricingError::Money(error)
+    }
+}
+
+pub struct PricingEngine<'c> {
+    catalog: &'c Catalog,
+    rules: PricingRules,
+}
+
+impl<'c> PricingEngine<'c> {
+    pub fn new(catalog: &'c Catalog, rules: PricingRules) -> Self {
+        PricingEngine { catalog, rules }
+    }
+
+    pub fn rules(&self) -> &PricingRules {
+        &self.rules
+    }
+
+    pub fn price(&self, car: &Car, discounts: &[Discount]) -> Result<PriceBreakdown, PricingError> {
+        let (make, model) = self.catalog.find(car.make(), car.model()).ok_or_else(|| PricingError::UnknownModel {
+            make: car.make().to_string(),
+            model: car.model().to_string(),
+        })?;
+
+        let base = Money::from_dollars(model.base_price as i64);
+        let mut lines = vec![PriceLine::new(format!("{} {} base price", make.name, model.name), base)];
+
+        match car.motor {
+            Transmission::Manual => {}
+            Transmission::SemiAuto => lines.push(PriceLine::new("Semi-Auto transmission", self.rules.semi_auto_surcharge)),
+            Transmission::Automatic => lines.push(PriceLine::new("Automatic transmission", self.rules.automatic_surcharge)),
+        }
+
+        if Roof::from(car.roof) == Roof::Convertible {
+            lines.push(PriceLine::new("Convertible roof", self.rules.convertible_surcharge));
+        }
+
+        if let (Age::Used, miles) = car.age {
+            let cap = base.percent(self.rules.max_depreciation_bp);
+            let depreciation = cap.checked_min(self.rules.depreciation_per_mile.times(miles as i64))?;
+            lines.push(PriceLine::new(format!("Depreciation ({} miles)", miles), -depreciation));
+        }
+
+        let subtotal = lines.iter().fold(Money::zero(base.currency()), |sum, line| sum + line.amount);
+
+        // Each discount is taken off what's left, so stacked discounts can't push the price below zero
+        let mut remaining = subtotal;
+        let mut applied = Vec::new();
+        for discount in discounts {
+            let (label, amount) = match discount {
+                Discount::Percent { label, basis_points } => (label, subtotal.percent(*basis_points)),
+                Discount::Fixed { label, amount } => (label, *amount),
+            };
+            if amount.is_negative() {
+                return Err(PricingError::NegativeDiscount(label.clone()));
+            }
+            let amount = remaining.checked_min(amount)?;
+            remaining -= amount;
+            applied.push(PriceLine::new(label.clone(), -amount));
+        }
+
+        let tax = remaining.percent(self.rules.tax_rate_bp);
+        Ok(PriceBreakdown { lines, subtotal, discounts: applied, tax, total: remaining + tax })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::{CarOrder, Color};
+    use crate::money::Currency;
+
+    fn catalog() -> Catalog {
+        serde_json::from_str(
+            r#"{ "makes": [
+                { "name": "Maserati", "wmi": "ZAM", "models": [
+                    { "name": "Folgore", "years": [2024], "colors": ["Blue"],
+                      "transmissions": ["Manual", "Automatic"], "roofs": ["Hardtop", "Convertible"], "base_price": 100000 }
+                ] }
+            ] }"#,
+        )
+        .unwrap()
+    }
+
+    fn car(motor: Transmission, hardtop: bool, miles: u32) -> Car {
+        CarOrder::new()
+            .make("Maserati")
+            .model("Folgore")
+            .year(2024)
+            .color(Color::Blue)
+            .transmission(motor)
+            .roof(hardtop)
+            .miles(miles)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn new_manual_hardtop_pays_base_price_plus_tax() {
+        let catalog = catalog();
+        let engine = PricingEngine::new(&catalog, PricingRules::default());
+        let price = engine.price(&car(Transmission::Manual, true, 0), &[]).unwrap();
+
+        assert_eq!(price.lines, vec![PriceLine::new("Maserati Folgore base price", Money::from_dollars(100_000))]);
+        assert_eq!(price.tax, Money::from_dollars(8_250));
+        assert_eq!(price.total, Money::from_dollars(108_250));
+    }
+
+    #[test]
+    fn surcharges_and_depreciation_are_itemized() {
+        let catalog = catalog();
+        let engine = PricingEngine::new(&catalog, PricingRules::default());
+        let price = engine.price(&car(Transmission::Automatic, false, 20_000), &[]).unwrap();
+
+        let labels: Vec<&str> = price.lines.iter().map(|line| line.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec!["Maserati Folgore base price", "Automatic transmission", "Convertible roof", "Depreciation (20000 miles)"]
+        );
+        // 100,000 + 2,500 + 6,000 - 20,000 * 0.15
+        assert_eq!(price.subtotal, Money::from_dollars(105_500));
+        assert_eq!(Money::sum_in(Currency::USD, price.lines.iter().map(|line| line.amount)), Ok(price.subtotal));
+    }
+
+    #[test]
+    fn depreciation_is_capped() {
+        let catalog = catalog();
+        let engine = PricingEngine::new(&catalog, PricingRules::default());
+        let price = engine.price(&car(Transmission::Manual, true, 2_000_000), &[]).unwrap();
+        assert_eq!(price.subtotal, Money::from_dollars(40_000));
+    }
+
+    #[test]
+    fn discounts_apply_before_tax_and_never_go_below_zero() {
+        let catalog = catalog();
+        let engine = PricingEngine::new(&catalog, PricingRules::default());
+        let discounts = [
+            Discount::Percent { label: "Loyalty 10%".to_string(), basis_points: 1_000 },
+            Discount::Fixed { label: "Trade-in".to_string(), amount: Money::from_dollars(5_000) },
+        ];
+        let price = engine.price(&car(Transmission::Manual, true, 0), &discounts).unwrap();
+        assert_eq!(price.taxable(), Money::from_dollars(85_000));
+        assert_eq!(price.tax, Money::from_cents(701_250));
+        assert_eq!(price.total, Money::from_cents(9_201_250));
+
+        let giveaway = [Discount::Fixed { label: "Prize".to_string(), amount: Money::from_dollars(1_000_000) }];
+        let price = engine.price(&car(Transmission::Manual, true, 0), &giveaway).unwrap();
+        assert_eq!(price.total, Money::ZERO);
+
+        let backwards = [Discount::Fixed { label: "Oops".to_string(), amount: Money::from_dollars(-1) }];
+        assert_eq!(
+            engine.price(&car(Transmission::Manual, true, 0), &backwards),
+            Err(PricingError::NegativeDiscount("Oops".to_string()))
+        );
+
+        let euros = [Discount::Fixed { label: "Trade-in".to_string(), amount: Money::new(500_000, Currency::EUR) }];
+        assert_eq!(
+            engine.price(&car(Transmission::Manual, true, 0), &euros),
+            Err(PricingError::Money(MoneyError::CurrencyMismatch { expected: Currency::USD, found: Currency::EUR }))
+        );
+    }
+
+    #[test]
+    fn models_missing_from_the_catalog_have_no_price() {
+        let catalog = Catalog { makes: Vec::new() };
+        let engine = PricingEngine::new(&catalog, PricingRules::default());
+        assert!(matches!(engine.price(&car(Transmission::Manual, true, 0), &[]), Err(PricingError::UnknownModel { .. })));
+    }
+}
@@ -1,3 +1,10 @@
+use crate::exchange::{ConvertedTotals, ExchangeError, ExchangeRate};
+use crate::money::{Currency, Money};
+use crate::pricing::Discount;
+use crate::totals::{LineItem, Totals, TotalsError};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use uuid::Uuid;
 /*
     v1 - Version 1 UUIDs using a timestamp and monotonic counter.
@@ -20,15 +27,17 @@ use uuid::Uuid;
     The debug representation is intended to be easily readable by developers and provides a way
     to quickly check the contents of a struct or enum.
  */
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PostRequest {
-    pub customer_id: i32
+    pub customer_id: i32,
+    pub items: Vec<LineItem>,
+    pub discounts: Vec<Discount>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PostResponse {
     pub quote_uuid: String,
-    pub amount: f32
+    pub amount: Money
 }
 
 /*
@@ -53,27 +62,407 @@ pub enum Pricing {
     However, it is still possible to create structs that hold references using the lifetime parameter syntax.
     This allows the struct to hold a reference to some data, but the data must live at least as long as the struct itself.
     This ensures that the data is always valid while the struct holds a reference to it.
-    Quote holds a reference to a string slice using the lifetime parameter 's.
-    The lifetime 's ensures that the string slice lives at least as long as the struct itself, so that the reference remains valid.
+    QuoteView holds references to a quote's note and line items using the lifetime parameter 's.
+    The lifetime 's ensures that the quote lives at least as long as the view, so that the references remain valid.
+
+    Quote itself owns all of its data. A quote that borrowed its note couldn't be stored, sent to
+    another thread or deserialized, so the owned Quote is what gets kept and written to files, and
+    quote.view() hands out a QuoteView for hot paths that only read a quote and shouldn't clone it.
  */
-#[derive(Debug)]
-pub struct Quote<'s> {
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "QuoteData")]
+pub struct Quote {
+    pub uuid: Uuid,
+    items: Vec<LineItem>,
+    discounts: Vec<Discount>,
+    index: u8,
+    status: QuoteStatus,
+    /// A free-form note. None when there isn't one, never Some("").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entangled: Option<String>,
+    history: Vec<StatusChange>,
+    valid_until: Option<DateTime<Utc>>,
+    /// The rate the totals were last converted to another currency with, kept for auditing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    conversion: Option<ExchangeRate>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuoteView<'s> {
     pub uuid: Uuid,
-    pub amount: f32,
+    pub index: u8,
+    pub status: QuoteStatus,
+    pub entangled: Option<&'s str>,
+    pub items: &'s [LineItem],
+    pub discounts: &'s [Discount],
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl QuoteView<'_> {
+    pub fn totals(&self) -> Totals {
+        Totals::compute(self.items, self.discounts).expect("The totals were checked when the quote was drafted")
+    }
+
+    pub fn amount(&self) -> Money {
+        self.totals().total
+    }
+}
+
+// What a quote file holds; turned into a Quote only if its totals can be worked out and still
+// convert at the recorded rate
+#[derive(Deserialize)]
+struct QuoteData {
+    uuid: Uuid,
+    items: Vec<LineItem>,
+    discounts: Vec<Discount>,
     index: u8,
-    accepted: bool,
-    pub entangled: Option<&'s str>
+    status: QuoteStatus,
+    #[serde(default)]
+    entangled: Option<String>,
+    history: Vec<StatusChange>,
+    valid_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    conversion: Option<ExchangeRate>,
+}
+
+impl TryFrom<QuoteData> for Quote {
+    type Error = String;
+
+    fn try_from(data: QuoteData) -> Result<Self, Self::Error> {
+        Totals::compute(&data.items, &data.discounts).map_err(|error| format!("quote {}: {}", data.uuid, error))?;
+        if data.history.is_empty() {
+            return Err(format!("quote {} has no history", data.uuid));
+        }
+        let quote = Quote {
+            uuid: data.uuid,
+            items: data.items,
+            discounts: data.discounts,
+            index: data.index,
+            status: data.status,
+            entangled: non_blank(data.entangled),
+            history: data.history,
+            valid_until: data.valid_until,
+            conversion: data.conversion,
+        };
+        if let Some(rate) = &quote.conversion {
+            if rate.to == rate.from {
+                return Err(format!("quote {} is converted from {} to itself", quote.uuid, rate.from));
+            }
+            quote.check_conversion(rate).map_err(|error| format!("quote {}: {}", quote.uuid, error))?;
+        }
+        Ok(quote)
+    }
+}
+
+/*
+    A quote starts as a Draft and only ever moves forwards:
+
+        Draft -> Issued -> Accepted
+          |        |-----> Rejected
+          |        |-----> Expired     once its validity window has passed
+          |        '-----> Superseded  a newer revision replaced it
+          '--------------> Superseded
+
+    Accepted, Rejected, Expired and Superseded are final. Issuing a quote starts its validity window;
+    a quote can't be accepted once the window has closed, even if nobody has marked it Expired yet.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuoteStatus {
+    Draft,
+    Issued,
+    Accepted,
+    Rejected,
+    Expired,
+    Superseded,
+}
+
+impl QuoteStatus {
+    pub fn can_become(self, next: QuoteStatus) -> bool {
+        use QuoteStatus::*;
+        matches!(
+            (self, next),
+            (Draft, Issued) | (Draft, Superseded) | (Issued, Accepted) | (Issued, Rejected) | (Issued, Expired) | (Issued, Superseded)
+        )
+    }
+
+    pub fn is_final(self) -> bool {
+        matches!(self, QuoteStatus::Accepted | QuoteStatus::Rejected | QuoteStatus::Expired | QuoteStatus::Superseded)
+    }
+}
+
+impl fmt::Display for QuoteStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            QuoteStatus::Draft => "draft",
+            QuoteStatus::Issued => "issued",
+            QuoteStatus::Accepted => "accepted",
+            QuoteStatus::Rejected => "rejected",
+            QuoteStatus::Expired => "expired",
+            QuoteStatus::Superseded => "superseded",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusChange {
+    pub status: QuoteStatus,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum QuoteError {
+    InvalidTransition { from: QuoteStatus, to: QuoteStatus },
+    // The validity window closed before the quote was accepted
+    Expired { valid_until: DateTime<Utc> },
+    // A quote can only be marked Expired once its window has closed
+    StillValid { valid_until: DateTime<Utc> },
+    EmptyValidity,
+    // A revision's items don't add up
+    Totals(TotalsError),
+    // The revision number would overflow
+    TooManyRevisions,
+    // Every revision of a quote is priced in the same currency
+    CurrencyChanged { from: Currency, to: Currency },
+    Exchange(ExchangeError),
+    // Accepted, rejected, expired and superseded quotes can't be changed any more
+    Closed { status: QuoteStatus },
+}
+
+impl fmt::Display for QuoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuoteError::InvalidTransition { from, to } => write!(f, "a quote that is {} can't become {}", from, to),
+            QuoteError::Expired { valid_until } => write!(f, "the quote expired at {}", valid_until.format("%F %H:%M")),
+            QuoteError::StillValid { valid_until } => {
+                write!(f, "the quote is valid until {}", valid_until.format("%F %H:%M"))
+            }
+            QuoteError::EmptyValidity => write!(f, "a quote must be valid for some time after it's issued"),
+            QuoteError::Totals(error) => write!(f, "{}", error),
+            QuoteError::TooManyRevisions => write!(f, "a quote can have at most {} revisions", u8::MAX),
+            QuoteError::CurrencyChanged { from, to } => {
+                write!(f, "a quote priced in {} can't be revised to {}", from, to)
+            }
+            QuoteError::Exchange(error) => write!(f, "{}", error),
+            QuoteError::Closed { status } => write!(f, "a quote that is {} can't be changed", status),
+        }
+    }
+}
+
+impl Quote {
+    /// A new draft for the items, after checking that their totals can be worked out.
+    pub fn draft(
+        items: Vec<LineItem>,
+        discounts: Vec<Discount>,
+        entangled: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Result<Quote, TotalsError> {
+        Totals::compute(&items, &discounts)?;
+        Ok(Quote {
+            uuid: Uuid::new_v4(),
+            items,
+            discounts,
+            index: 1,
+            status: QuoteStatus::Draft,
+            entangled: non_blank(entangled),
+            history: vec![StatusChange { status: QuoteStatus::Draft, at: now }],
+            valid_until: None,
+            conversion: None,
+        })
+    }
+
+    /// Borrows the quote's contents without copying them.
+    pub fn view(&self) -> QuoteView<'_> {
+        QuoteView {
+            uuid: self.uuid,
+            index: self.index,
+            status: self.status,
+            entangled: self.entangled.as_deref(),
+            items: &self.items,
+            discounts: &self.discounts,
+            valid_until: self.valid_until,
+        }
+    }
+
+    pub fn items(&self) -> &[LineItem] {
+        &self.items
+    }
+
+    pub fn discounts(&self) -> &[Discount] {
+        &self.discounts
+    }
+
+    /// Subtotal, discounts, taxes and grand total, worked out from the items every time.
+    pub fn totals(&self) -> Totals {
+        self.view().totals()
+    }
+
+    /// The grand total.
+    pub fn amount(&self) -> Money {
+        self.view().amount()
+    }
+
+    /// The currency the items are priced in.
+    pub fn currency(&self) -> Currency {
+        self.items[0].unit_price.currency()
+    }
+
+    pub fn entangled(&self) -> Option<&str> {
+        self.entangled.as_deref()
+    }
+
+    /// Replaces the note; a blank one removes it.
+    pub fn set_entangled(&mut self, note: Option<String>) {
+        self.entangled = non_blank(note);
+    }
+
+    pub fn conversion(&self) -> Option<&ExchangeRate> {
+        self.conversion.as_ref()
+    }
+
+    /// Records the rate to show the totals in another currency with. The items keep their own
+    /// prices, so converting back to the quote's currency simply drops the conversion.
+    pub fn convert(&mut self, rate: ExchangeRate) -> Result<(), QuoteError> {
+        if self.status.is_final() {
+            return Err(QuoteError::Closed { status: self.status });
+        }
+        // Make sure the figures convert before recording the rate
+        self.check_conversion(&rate).map_err(QuoteError::Exchange)?;
+        self.conversion = (rate.to != rate.from).then_some(rate);
+        Ok(())
+    }
+
+    fn check_conversion(&self, rate: &ExchangeRate) -> Result<ConvertedTotals, ExchangeError> {
+        if rate.from != self.currency() {
+            return Err(ExchangeError::WrongCurrency { expected: rate.from, found: self.currency() });
+        }
+        ConvertedTotals::convert(&self.totals(), rate)
+    }
+
+    /// The totals in the currency the quote was converted to, if it was.
+    pub fn converted_totals(&self) -> Option<ConvertedTotals> {
+        let rate = self.conversion.as_ref()?;
+        Some(self.check_conversion(rate).expect("The conversion was checked when it was recorded or loaded"))
+    }
+
+    pub fn status(&self) -> QuoteStatus {
+        self.status
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    pub fn history(&self) -> &[StatusChange] {
+        &self.history
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.history[0].at
+    }
+
+    /// When the current status was reached.
+    pub fn updated_at(&self) -> DateTime<Utc> {
+        self.history[self.history.len() - 1].at
+    }
+
+    /// The end of the validity window, once the quote has been issued.
+    pub fn valid_until(&self) -> Option<DateTime<Utc>> {
+        self.valid_until
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        self.status == QuoteStatus::Accepted
+    }
+
+    /// Sends the quote to the customer. It can be accepted until `validity` has passed.
+    pub fn issue(&mut self, validity: Duration, now: DateTime<Utc>) -> Result<(), QuoteError> {
+        if validity <= Duration::zero() {
+            return Err(QuoteError::EmptyValidity);
+        }
+        self.change(QuoteStatus::Issued, now)?;
+        self.valid_until = Some(now + validity);
+        Ok(())
+    }
+
+    pub fn accept(&mut self, now: DateTime<Utc>) -> Result<(), QuoteError> {
+        self.check(QuoteStatus::Accepted)?;
+        match self.valid_until {
+            Some(valid_until) if now >= valid_until => Err(QuoteError::Expired { valid_until }),
+            _ => self.change(QuoteStatus::Accepted, now),
+        }
+    }
+
+    pub fn reject(&mut self, now: DateTime<Utc>) -> Result<(), QuoteError> {
+        self.change(QuoteStatus::Rejected, now)
+    }
+
+    /// Marks an issued quote whose validity window has closed as Expired.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Result<(), QuoteError> {
+        self.check(QuoteStatus::Expired)?;
+        match self.valid_until {
+            Some(valid_until) if now < valid_until => Err(QuoteError::StillValid { valid_until }),
+            _ => self.change(QuoteStatus::Expired, now),
+        }
+    }
+
+    pub fn supersede(&mut self, now: DateTime<Utc>) -> Result<(), QuoteError> {
+        self.change(QuoteStatus::Superseded, now)
+    }
+
+    /// Drafts the next revision of this quote with new contents: same UUID, the next index, and
+    /// the same note and conversion. This quote becomes Superseded, so only a Draft or Issued quote can be revised.
+    /// The new items must be priced in the same currency as this quote's, and their totals must
+    /// still convert at the conversion's rate.
+    pub fn revise(&mut self, items: Vec<LineItem>, discounts: Vec<Discount>, now: DateTime<Utc>) -> Result<Quote, QuoteError> {
+        self.check(QuoteStatus::Superseded)?;
+        let index = self.index.checked_add(1).ok_or(QuoteError::TooManyRevisions)?;
+        let mut next = Quote::draft(items, discounts, self.entangled.clone(), now).map_err(QuoteError::Totals)?;
+        if next.currency() != self.currency() {
+            return Err(QuoteError::CurrencyChanged { from: self.currency(), to: next.currency() });
+        }
+        next.uuid = self.uuid;
+        next.index = index;
+        if let Some(rate) = self.conversion.clone() {
+            next.convert(rate)?;
+        }
+        self.change(QuoteStatus::Superseded, now)?;
+        Ok(next)
+    }
+
+    fn check(&self, to: QuoteStatus) -> Result<(), QuoteError> {
+        if self.status.can_become(to) {
+            Ok(())
+        } else {
+            Err(QuoteError::InvalidTransition { from: self.status, to })
+        }
+    }
+
+    fn change(&mut self, to: QuoteStatus, now: DateTime<Utc>) -> Result<(), QuoteError> {
+        self.check(to)?;
+        self.status = to;
+        self.history.push(StatusChange { status: to, at: now });
+        Ok(())
+    }
 }
 
-pub fn from_quote_factory(amount: f32, accepted: bool, entangled: Option<&str>) -> Quote {
+// Builds a single-line quote for a non-negative amount that's either still a draft or already issued and accepted on the spot
+// A note that's only whitespace is no note at all
+fn non_blank(note: Option<String>) -> Option<String> {
+    note.filter(|note| !note.trim().is_empty())
+}
+
+pub fn from_quote_factory(amount: Money, accepted: bool, entangled: Option<&str>) -> Quote {
     // Arguments can be passed in any other because struct parameter names are unique
-    Quote {
-        amount,
-        accepted,
-        entangled: Option::from(entangled.unwrap_or_default()),
-        uuid: Uuid::new_v4(),
-        index: 1
+    let now = Utc::now();
+    let items = vec![LineItem::new("QUOTE", "Quoted amount", 1, amount)];
+    let mut quote = Quote::draft(items, Vec::new(), entangled.map(str::to_string), now)
+        .expect("A single line with a non-negative amount has totals");
+    if accepted {
+        quote.issue(Duration::days(30), now).and_then(|()| quote.accept(now)).expect("A fresh draft can be accepted");
     }
+    quote
 }
 
 /*
@@ -99,3 +488,267 @@ pub fn from_quote_factory(amount: f32, accepted: bool, entangled: Option<&str>)
 
     Overall, macros are an important tool in Rust for reducing code duplication, increasing expressiveness, and enabling advanced metaprogramming techniques.
  */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::Rate;
+    use crate::totals::TaxRate;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 6, 1, 9, 0, 0).unwrap()
+    }
+
+    fn issued() -> Quote {
+        let mut quote = Quote::draft(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_cents(9_999))], Vec::new(), None, now()).unwrap();
+        quote.issue(Duration::days(14), now()).unwrap();
+        quote
+    }
+
+    #[test]
+    fn issued_quotes_can_be_accepted_within_their_validity() {
+        let mut quote = issued();
+        assert_eq!(quote.valid_until(), Some(now() + Duration::days(14)));
+
+        quote.accept(now() + Duration::days(13)).unwrap();
+        assert!(quote.is_accepted());
+        let statuses: Vec<QuoteStatus> = quote.history().iter().map(|change| change.status).collect();
+        assert_eq!(statuses, vec![QuoteStatus::Draft, QuoteStatus::Issued, QuoteStatus::Accepted]);
+        assert_eq!(quote.updated_at(), now() + Duration::days(13));
+    }
+
+    #[test]
+    fn expired_quotes_cannot_be_accepted() {
+        let mut quote = issued();
+        let valid_until = now() + Duration::days(14);
+
+        assert_eq!(quote.accept(valid_until), Err(QuoteError::Expired { valid_until }));
+        assert_eq!(quote.status(), QuoteStatus::Issued);
+
+        assert_eq!(quote.expire(now()), Err(QuoteError::StillValid { valid_until }));
+        quote.expire(valid_until).unwrap();
+        assert_eq!(
+            quote.accept(valid_until),
+            Err(QuoteError::InvalidTransition { from: QuoteStatus::Expired, to: QuoteStatus::Accepted })
+        );
+    }
+
+    #[test]
+    fn only_allowed_transitions_happen() {
+        let mut draft = Quote::draft(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(10))], Vec::new(), None, now()).unwrap();
+        assert_eq!(
+            draft.accept(now()),
+            Err(QuoteError::InvalidTransition { from: QuoteStatus::Draft, to: QuoteStatus::Accepted })
+        );
+        assert_eq!(draft.issue(Duration::zero(), now()), Err(QuoteError::EmptyValidity));
+
+        let mut rejected = issued();
+        rejected.reject(now()).unwrap();
+        assert!(rejected.status().is_final());
+        assert!(rejected.supersede(now()).is_err());
+
+        let mut superseded = issued();
+        superseded.supersede(now()).unwrap();
+        assert_eq!(superseded.history().len(), 3);
+    }
+
+    #[test]
+    fn revisions_keep_the_uuid_and_count_up() {
+        let mut first = issued();
+        let items = vec![LineItem::new("MAT-01", "Floor mats", 2, Money::from_cents(9_999))];
+
+        let mut second = first.revise(items.clone(), Vec::new(), now()).unwrap();
+        assert_eq!((second.uuid, second.index()), (first.uuid, 2));
+        assert_eq!(second.status(), QuoteStatus::Draft);
+        assert_eq!(first.status(), QuoteStatus::Superseded);
+        assert_eq!(
+            first.revise(items.clone(), Vec::new(), now()),
+            Err(QuoteError::InvalidTransition { from: QuoteStatus::Superseded, to: QuoteStatus::Superseded })
+        );
+
+        assert_eq!(second.revise(Vec::new(), Vec::new(), now()), Err(QuoteError::Totals(TotalsError::NoItems)));
+        let euros = vec![LineItem::new("MAT-01", "Floor mats", 2, Money::new(9_199, Currency::EUR))];
+        assert_eq!(
+            second.revise(euros, Vec::new(), now()),
+            Err(QuoteError::CurrencyChanged { from: Currency::USD, to: Currency::EUR })
+        );
+        assert_eq!(second.status(), QuoteStatus::Draft);
+
+        second.index = u8::MAX;
+        assert_eq!(second.revise(items, Vec::new(), now()), Err(QuoteError::TooManyRevisions));
+    }
+
+    #[test]
+    fn factory_quotes_are_accepted_or_draft() {
+        let accepted = from_quote_factory(Money::from_dollars(1), true, None);
+        assert!(accepted.is_accepted());
+        assert_eq!(accepted.amount(), Money::from_dollars(1));
+        assert_eq!(from_quote_factory(Money::from_dollars(1), false, None).status(), QuoteStatus::Draft);
+    }
+
+    #[test]
+    fn a_missing_note_stays_missing() {
+        assert_eq!(from_quote_factory(Money::from_dollars(1), false, None).entangled(), None);
+        assert_eq!(from_quote_factory(Money::from_dollars(1), false, Some("Call first")).entangled(), Some("Call first"));
+
+        let json = serde_json::to_string(&issued()).unwrap();
+        assert!(!json.contains("entangled"));
+        assert_eq!(serde_json::from_str::<Quote>(&json).unwrap().entangled(), None);
+    }
+
+    #[test]
+    fn a_blank_note_is_no_note() {
+        assert_eq!(from_quote_factory(Money::from_dollars(1), false, Some("")).entangled(), None);
+        assert_eq!(from_quote_factory(Money::from_dollars(1), false, Some(" \t")).entangled(), None);
+
+        let mut quote = issued();
+        quote.set_entangled(Some("Call first".to_string()));
+        assert_eq!(quote.entangled(), Some("Call first"));
+        let json = serde_json::to_string(&quote).unwrap().replace("Call first", "  ");
+        assert!(json.contains("entangled"));
+        assert_eq!(serde_json::from_str::<Quote>(&json).unwrap().entangled(), None);
+
+        quote.set_entangled(Some(" \n".to_string()));
+        assert_eq!(quote.entangled(), None);
+        quote.set_entangled(Some("Call first".to_string()));
+        quote.set_entangled(None);
+        assert_eq!(quote.entangled(), None);
+    }
+
+    #[test]
+    fn quotes_round_trip_through_json() {
+        let items = vec![
+            LineItem::new("FOLGORE-24", "Maserati Folgore", 1, Money::from_dollars(100_000)).taxed(TaxRate::new("Sales tax", 825)),
+            LineItem::new("MAT-01", "Floor mats \"deluxe\"", 4, Money::from_cents(4_999))
+                .discounted(Discount::Percent { label: "Bundle".to_string(), basis_points: 1_000 }),
+        ];
+        let discounts = vec![Discount::Fixed { label: "Trade-in".to_string(), amount: Money::from_dollars(5_000) }];
+        let mut quote = Quote::draft(items, discounts, Some("Blue, not black".to_string()), now()).unwrap();
+        quote.issue(Duration::days(14), now()).unwrap();
+        quote.accept(now() + Duration::hours(1)).unwrap();
+
+        let json = serde_json::to_string(&quote).unwrap();
+        let read: Quote = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(read, quote);
+        assert_eq!(read.totals(), quote.totals());
+        assert_eq!(serde_json::to_string(&read).unwrap(), json);
+    }
+
+    #[test]
+    fn quotes_with_bad_items_are_not_read() {
+        let json = serde_json::to_string(&issued()).unwrap().replace("\"quantity\":1", "\"quantity\":0");
+        let error = serde_json::from_str::<Quote>(&json).unwrap_err();
+        assert!(error.to_string().contains("quantity of zero"), "{}", error);
+    }
+
+    #[test]
+    fn converting_there_and_back_is_reproducible() {
+        let items = vec![LineItem::new("MAT-01", "Floor mats", 3, Money::new(3_333, Currency::EUR)).taxed(TaxRate::new("VAT", 2_000))];
+        let mut quote = Quote::draft(items, Vec::new(), None, now()).unwrap();
+        let original = quote.totals();
+        let rate = ExchangeRate {
+            from: Currency::EUR,
+            to: Currency::USD,
+            base: Currency::USD,
+            effective: now().date_naive(),
+            from_rate: "0.9214".parse().unwrap(),
+            to_rate: Rate::ONE,
+        };
+
+        quote.convert(rate.clone()).unwrap();
+        let converted = quote.converted_totals().unwrap();
+        // 99.99 EUR and 20% VAT of 20.00 EUR, at 0.9214 EUR to the dollar
+        assert_eq!((converted.subtotal, converted.tax, converted.total), (Money::from_cents(10_852), Money::from_cents(2_171), Money::from_cents(13_023)));
+        assert_eq!(quote.conversion(), Some(&rate));
+
+        // Reading it back from JSON keeps the rate, and with it the same figures
+        let read: Quote = serde_json::from_str(&serde_json::to_string(&quote).unwrap()).unwrap();
+        assert_eq!(read.converted_totals(), Some(converted.clone()));
+
+        quote.convert(ExchangeRate::identity(Currency::EUR, now().date_naive())).unwrap();
+        assert_eq!(quote.conversion(), None);
+        assert_eq!(quote.totals(), original);
+        quote.convert(rate.clone()).unwrap();
+        assert_eq!(quote.converted_totals(), Some(converted));
+
+        assert_eq!(
+            quote.convert(rate.inverse()),
+            Err(QuoteError::Exchange(ExchangeError::WrongCurrency { expected: Currency::USD, found: Currency::EUR }))
+        );
+        quote.issue(Duration::days(1), now()).unwrap();
+        quote.reject(now()).unwrap();
+        assert_eq!(quote.convert(rate), Err(QuoteError::Closed { status: QuoteStatus::Rejected }));
+    }
+
+    #[test]
+    fn tampered_conversions_are_refused_when_loading() {
+        let items = vec![LineItem::new("FLEET", "A fleet of cars", 1, Money::from_dollars(1_000_000_000))];
+        let mut quote = Quote::draft(items, Vec::new(), None, now()).unwrap();
+        let rate = ExchangeRate {
+            from: Currency::USD,
+            to: Currency::JPY,
+            base: Currency::USD,
+            effective: now().date_naive(),
+            from_rate: Rate::ONE,
+            to_rate: "139.48".parse().unwrap(),
+        };
+        quote.convert(rate).unwrap();
+        let json = serde_json::to_value(&quote).unwrap();
+        assert!(serde_json::from_value::<Quote>(json.clone()).is_ok());
+
+        let tampered = |field: &str, value: &str| {
+            let mut json = json.clone();
+            json["conversion"][field] = serde_json::Value::from(value);
+            serde_json::from_value::<Quote>(json)
+        };
+        assert!(tampered("from", "EUR").is_err());
+        assert!(tampered("to", "USD").is_err());
+        // Ten trillion yen to the dollar makes a billion dollars more yen than an amount can hold
+        assert!(tampered("to_rate", "10000000000000").is_err());
+    }
+
+    #[test]
+    fn revisions_keep_a_conversion_that_still_works() {
+        let rate = ExchangeRate {
+            from: Currency::USD,
+            to: Currency::JPY,
+            base: Currency::USD,
+            effective: now().date_naive(),
+            from_rate: Rate::ONE,
+            // A million yen to the dollar
+            to_rate: Rate::from_millionths(1_000_000_000_000).unwrap(),
+        };
+        let mut first = issued();
+        first.convert(rate.clone()).unwrap();
+
+        let mats = vec![LineItem::new("MAT-01", "Floor mats", 2, Money::from_cents(9_999))];
+        let second = first.clone().revise(mats, Vec::new(), now()).unwrap();
+        assert_eq!(second.conversion(), Some(&rate));
+        assert_eq!(second.converted_totals().unwrap().total, Money::new(199_980_000, Currency::JPY));
+
+        // Ten trillion dollars is more yen than an amount can hold
+        let fleet = vec![LineItem::new("FLEET", "Every car there is", 1, Money::from_dollars(10_000_000_000_000))];
+        assert!(matches!(first.revise(fleet, Vec::new(), now()), Err(QuoteError::Exchange(_))));
+        assert_eq!(first.status(), QuoteStatus::Issued);
+    }
+
+    #[test]
+    fn views_borrow_instead_of_copying() {
+        let quote = from_quote_factory(Money::from_dollars(1), true, Some("Call first"));
+        let view = quote.view();
+
+        assert_eq!(view.entangled.map(|note| note.as_ptr()), quote.entangled().map(|note| note.as_ptr()));
+        assert!(std::ptr::eq(view.items, quote.items()));
+        assert_eq!(view.amount(), quote.amount());
+        assert_eq!(view.status, QuoteStatus::Accepted);
+    }
+
+    #[test]
+    fn quotes_can_move_between_threads() {
+        let quote = issued();
+        let amount = std::thread::spawn(move || quote.amount()).join().unwrap();
+        assert_eq!(amount, Money::from_cents(9_999));
+    }
+}
@@ -0,0 +1,820 @@
+// Turns a customer's PostRequest into a priced, issued Quote and answers with a PostResponse
+
+/*
+    A request lists what the customer wants: line items with a SKU, a quantity and a unit price,
+    and any discounts. The service expects the lines to be priced already and doesn't price them
+    itself: the caller prices a car with the PricingEngine and puts its subtotal on the line. All
+    the service adds from the PricingRules is the sales tax: a line that doesn't name its own taxes
+    pays rules.tax_rate_bp (a tax-exempt line carries a zero rate instead). It then drafts a Quote
+    with a fresh UUID, issues it for the service's validity window, and keeps it. The response
+    carries that UUID and the grand total, so anything holding a response can look the quote up
+    again with get().
+
+    A vehicle line, one that carries a car, needs the vehicle catalog given with with_catalog().
+    The car is built again against the catalog, so it must be one the catalog offers, and it
+    takes the catalog's spelling and manufacturer code and drops any VIN. Its price is only
+    checked, not set: the line's unit price must be the PricingEngine's subtotal for the car under
+    the service's rules, or the request is refused with a PriceMismatch.
+
+    Revising a quote keeps its UUID: revise() drafts and issues the next revision (index 2, 3, ...)
+    with the new items, and marks the one before it Superseded. A customer who pays in another
+    currency gets the new revision converted at the rate in effect on the day it's made. Every
+    revision is kept, so any two can be compared with QuoteRecord::diff().
+
+    Once the customer accepts the latest revision, place_orders() orders the cars on its vehicle
+    lines (one per unit of quantity) from the inventory. Every order links back to the quote's
+    UUID, and the quote record keeps the order ids, so a quote that has been ordered can't be
+    ordered again, from any inventory. The inventory refuses a quote it already has orders for
+    as well.
+
+    convert() shows the latest revision's totals in another currency, at the rate in effect on
+    the service clock's current day, and records that rate on the quote.
+
+    Every line must be in the same currency, and a revision must keep the quote's currency.
+
+    Requests are checked against the customer registry: quotes are only made out to customers it
+    knows. A request without discounts gets the customer's standing discount, and a customer who
+    pays in another currency gets their quote converted at today's rate, which needs the rate
+    tables given with with_rates().
+
+    A service opened on a file saves every quote record to it, replacing the file atomically after
+    each change. A change only takes effect in memory once it has been saved, so a failed save
+    leaves the service as it was.
+ */
+
+use crate::car::{Car, CarOrder, CarOrderError};
+use crate::catalog::Catalog;
+use crate::clock::Clock;
+use crate::customer::CustomerRegistry;
+use crate::exchange::{ConvertedTotals, ExchangeRate, RateTables};
+use crate::inventory::{Inventory, InventoryError};
+use crate::money::{Currency, Money};
+use crate::pricing::{Discount, PricingEngine, PricingRules};
+use crate::quote::{PostRequest, PostResponse, Quote, QuoteError, QuoteStatus};
+use crate::revision::QuoteDiff;
+use crate::storage::write_json_atomically;
+use crate::totals::{LineItem, TaxRate, TotalsError};
+use chrono::{Datelike, Duration};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::iter;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+#[derive(Debug, PartialEq)]
+pub enum QuotingError {
+    Totals(TotalsError),
+    Quote(QuoteError),
+    UnknownQuote(Uuid),
+    UnknownCustomer(i32),
+    /// A quote has to be converted, but the service has no exchange rates.
+    NoRates { currency: Currency },
+    /// A line carries a car, but the service has no vehicle catalog to check it against.
+    NoCatalog { sku: String },
+    /// The car on a vehicle line can't be built against the catalog.
+    Vehicle { sku: String, error: CarOrderError },
+    /// A vehicle line's unit price isn't what the car costs.
+    PriceMismatch { sku: String, expected: Money, found: Money },
+    /// Only accepted quotes can be ordered.
+    NotAccepted { status: QuoteStatus },
+    NoVehicles(Uuid),
+    AlreadyOrdered { orders: Vec<u32> },
+    Inventory(InventoryError),
+    Storage(String),
+}
+
+impl fmt::Display for QuotingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotingError::Totals(error) => write!(f, "can't price the request: {}", error),
+            QuotingError::Quote(error) => write!(f, "{}", error),
+            QuotingError::UnknownQuote(uuid) => write!(f, "there is no quote {}", uuid),
+            QuotingError::UnknownCustomer(id) => write!(f, "there is no customer #{}", id),
+            QuotingError::NoRates { currency } => {
+                write!(f, "can't convert to {}: no exchange rates are loaded", currency)
+            }
+            QuotingError::NoCatalog { sku } => write!(f, "line {} is a car, but no vehicle catalog is loaded", sku),
+            QuotingError::Vehicle { sku, error } => write!(f, "line {}: {}", sku, error),
+            QuotingError::PriceMismatch { sku, expected, found } => {
+                write!(f, "line {} is priced at {}, but the car costs {}", sku, found, expected)
+            }
+            QuotingError::NotAccepted { status } => write!(f, "the quote is {}, not accepted", status),
+            QuotingError::NoVehicles(uuid) => write!(f, "quote {} has no vehicles to order", uuid),
+            QuotingError::AlreadyOrdered { orders } => {
+                let orders: Vec<String> = orders.iter().map(|id| format!("#{}", id)).collect();
+                write!(f, "the quote was already ordered as {}", orders.join(", "))
+            }
+            QuotingError::Inventory(error) => write!(f, "{}", error),
+            QuotingError::Storage(error) => write!(f, "quote file: {}", error),
+        }
+    }
+}
+
+impl From<TotalsError> for QuotingError {
+    fn from(error: TotalsError) -> Self {
+        QuotingError::Totals(error)
+    }
+}
+
+impl From<QuoteError> for QuotingError {
+    fn from(error: QuoteError) -> Self {
+        QuotingError::Quote(error)
+    }
+}
+
+impl From<InventoryError> for QuotingError {
+    fn from(error: InventoryError) -> Self {
+        QuotingError::Inventory(error)
+    }
+}
+
+impl From<std::io::Error> for QuotingError {
+    fn from(error: std::io::Error) -> Self {
+        QuotingError::Storage(error.to_string())
+    }
+}
+
+/// Every revision of a quote, oldest first, together with the customer who asked for it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "QuoteRecordData")]
+pub struct QuoteRecord {
+    pub customer_id: i32,
+    revisions: Vec<Quote>,
+    /// The car orders placed for the accepted quote.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    orders: Vec<u32>,
+}
+
+// What a quote file holds for each quote; turned into a QuoteRecord only if its revisions belong together
+#[derive(Deserialize)]
+struct QuoteRecordData {
+    customer_id: i32,
+    revisions: Vec<Quote>,
+    #[serde(default)]
+    orders: Vec<u32>,
+}
+
+impl TryFrom<QuoteRecordData> for QuoteRecord {
+    type Error = String;
+
+    fn try_from(data: QuoteRecordData) -> Result<Self, Self::Error> {
+        let first = data.revisions.first().ok_or("a quote record has no revisions")?;
+        for pair in data.revisions.windows(2) {
+            let (before, after) = (&pair[0], &pair[1]);
+            if after.uuid != first.uuid {
+                return Err(format!("quote {} has a revision of quote {}", first.uuid, after.uuid));
+            }
+            if after.index() <= before.index() {
+                return Err(format!("quote {} has revision {} after revision {}", first.uuid, after.index(), before.index()));
+            }
+            if after.currency() != first.currency() {
+                return Err(format!("quote {} has revisions in {} and {}", first.uuid, first.currency(), after.currency()));
+            }
+        }
+        let latest = data.revisions.last().expect("checked above");
+        if !data.orders.is_empty() && !latest.is_accepted() {
+            return Err(format!("quote {} has orders but is {}", latest.uuid, latest.status()));
+        }
+        Ok(QuoteRecord { customer_id: data.customer_id, revisions: data.revisions, orders: data.orders })
+    }
+}
+
+impl QuoteRecord {
+    pub fn latest(&self) -> &Quote {
+        self.revisions.last().expect("A record always has its first revision")
+    }
+
+    pub fn revisions(&self) -> &[Quote] {
+        &self.revisions
+    }
+
+    /// The ids of the car orders placed for the quote, if it has been ordered.
+    pub fn orders(&self) -> &[u32] {
+        &self.orders
+    }
+
+    pub fn revision(&self, index: u8) -> Option<&Quote> {
+        self.revisions.iter().find(|quote| quote.index() == index)
+    }
+
+    /// What changed from revision `from` to revision `to`, if both exist.
+    pub fn diff(&self, from: u8, to: u8) -> Option<QuoteDiff> {
+        Some(QuoteDiff::between(self.revision(from)?, self.revision(to)?))
+    }
+}
+
+pub struct QuoteService<C: Clock> {
+    rules: PricingRules,
+    validity: Duration,
+    clock: C,
+    customers: CustomerRegistry,
+    rates: Option<RateTables>,
+    catalog: Option<Catalog>,
+    quotes: HashMap<Uuid, QuoteRecord>,
+    path: Option<PathBuf>,
+}
+
+impl<C: Clock> QuoteService<C> {
+    /// A service that keeps its quotes in memory.
+    pub fn new(rules: PricingRules, customers: CustomerRegistry, clock: C) -> Self {
+        QuoteService {
+            rules,
+            validity: Duration::days(30),
+            clock,
+            customers,
+            rates: None,
+            catalog: None,
+            quotes: HashMap::new(),
+            path: None,
+        }
+    }
+
+    /// A service backed by a JSON file of quote records. Records already in the file are loaded.
+    pub fn open(path: PathBuf, rules: PricingRules, customers: CustomerRegistry, clock: C) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let records: Vec<QuoteRecord> = match serde_json::from_reader(file) {
+            Ok(records) => records,
+            Err(e) if e.is_eof() => Vec::new(),
+            Err(e) => Err(e)?,
+        };
+
+        let mut service = QuoteService::new(rules, customers, clock);
+        service.quotes = records.into_iter().map(|record| (record.latest().uuid, record)).collect();
+        service.path = Some(path);
+        Ok(service)
+    }
+
+    /// How long issued quotes can be accepted for. 30 days unless changed.
+    pub fn with_validity(mut self, validity: Duration) -> Self {
+        self.validity = validity;
+        self
+    }
+
+    /// Exchange rates for customers who pay in another currency than the one they're quoted in.
+    pub fn with_rates(mut self, rates: RateTables) -> Self {
+        self.rates = Some(rates);
+        self
+    }
+
+    /// The vehicle catalog that cars on vehicle lines are checked and priced against.
+    pub fn with_catalog(mut self, catalog: Catalog) -> Self {
+        self.catalog = Some(catalog);
+        self
+    }
+
+    pub fn customers(&self) -> &CustomerRegistry {
+        &self.customers
+    }
+
+    pub fn customers_mut(&mut self) -> &mut CustomerRegistry {
+        &mut self.customers
+    }
+
+    pub fn quote(&mut self, request: &PostRequest) -> Result<PostResponse, QuotingError> {
+        let customer =
+            self.customers.get(request.customer_id).ok_or(QuotingError::UnknownCustomer(request.customer_id))?;
+        let discounts = match &request.discounts[..] {
+            [] => customer.default_discount.iter().cloned().collect(),
+            discounts => discounts.to_vec(),
+        };
+        let items = self.prepare(request.items.clone())?;
+        let now = self.clock.now();
+        let mut quote = Quote::draft(items, discounts, None, now)?;
+
+        if customer.currency != quote.currency() {
+            quote.convert(self.rate_today(quote.currency(), customer.currency)?)?;
+        }
+        quote.issue(self.validity, now)?;
+
+        let response = PostResponse { quote_uuid: quote.uuid.to_string(), amount: quote.amount() };
+        self.commit(QuoteRecord { customer_id: request.customer_id, revisions: vec![quote], orders: Vec::new() })?;
+        Ok(response)
+    }
+
+    /// Issues a new revision of a quote with new items and discounts, superseding the latest one.
+    pub fn revise(&mut self, uuid: &Uuid, items: Vec<LineItem>, discounts: Vec<Discount>) -> Result<PostResponse, QuotingError> {
+        let items = self.prepare(items)?;
+        let now = self.clock.now();
+        let mut record = self.get(uuid).ok_or(QuotingError::UnknownQuote(*uuid))?.clone();
+
+        let customer = self.customers.get(record.customer_id).ok_or(QuotingError::UnknownCustomer(record.customer_id))?;
+
+        let latest = record.revisions.last_mut().expect("A record always has its first revision");
+        let mut next = latest.revise(items, discounts, now)?;
+        if customer.currency != next.currency() {
+            next.convert(self.rate_today(next.currency(), customer.currency)?)?;
+        }
+        next.issue(self.validity, now)?;
+
+        let response = PostResponse { quote_uuid: next.uuid.to_string(), amount: next.amount() };
+        record.revisions.push(next);
+        self.commit(record)?;
+        Ok(response)
+    }
+
+    /// Records that the customer accepted the latest revision. It must still be valid.
+    pub fn accept(&mut self, uuid: &Uuid) -> Result<&Quote, QuotingError> {
+        let now = self.clock.now();
+        let mut record = self.get(uuid).ok_or(QuotingError::UnknownQuote(*uuid))?.clone();
+        record.revisions.last_mut().expect("A record always has its first revision").accept(now)?;
+        self.commit(record)?;
+        Ok(self.quotes[uuid].latest())
+    }
+
+    /// Orders the cars on an accepted quote and returns the new order ids.
+    pub fn place_orders(&mut self, uuid: &Uuid, inventory: &mut Inventory) -> Result<Vec<u32>, QuotingError> {
+        let mut record = self.get(uuid).ok_or(QuotingError::UnknownQuote(*uuid))?.clone();
+        if !record.orders.is_empty() {
+            return Err(QuotingError::AlreadyOrdered { orders: record.orders });
+        }
+        let quote = record.latest();
+        if !quote.is_accepted() {
+            return Err(QuotingError::NotAccepted { status: quote.status() });
+        }
+        let cars: Vec<_> = quote
+            .items()
+            .iter()
+            .filter_map(|item| Some(iter::repeat_n(Car::clone(item.vehicle.as_ref()?), item.quantity as usize)))
+            .flatten()
+            .collect();
+        if cars.is_empty() {
+            return Err(QuotingError::NoVehicles(*uuid));
+        }
+        // If the record can't be saved the orders stay placed, and the inventory still refuses
+        // to order the quote again
+        record.orders = inventory.place_for_quote(*uuid, cars, self.clock.now())?;
+        self.commit(record.clone())?;
+        Ok(record.orders)
+    }
+
+    pub fn get(&self, uuid: &Uuid) -> Option<&QuoteRecord> {
+        self.quotes.get(uuid)
+    }
+
+    /// The quote a response links back to, if its UUID is one of ours.
+    pub fn for_response(&self, response: &PostResponse) -> Option<&QuoteRecord> {
+        Uuid::parse_str(&response.quote_uuid).ok().and_then(|uuid| self.get(&uuid))
+    }
+
+    /// A customer's quotes, oldest first.
+    pub fn for_customer(&self, customer_id: i32) -> Vec<&QuoteRecord> {
+        let mut records: Vec<&QuoteRecord> =
+            self.quotes.values().filter(|record| record.customer_id == customer_id).collect();
+        records.sort_by_key(|record| (record.revisions[0].created_at(), record.latest().uuid));
+        records
+    }
+
+    pub fn len(&self) -> usize {
+        self.quotes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quotes.is_empty()
+    }
+
+    /// Converts the latest revision's totals into `to` with today's rate from the service's rate tables.
+    pub fn convert(&mut self, uuid: &Uuid, to: Currency) -> Result<ConvertedTotals, QuotingError> {
+        let mut record = self.get(uuid).ok_or(QuotingError::UnknownQuote(*uuid))?.clone();
+        let latest = record.revisions.last_mut().expect("A record always has its first revision");
+        let rate = self.rate_today(latest.currency(), to)?;
+        let converted = ConvertedTotals::convert(&latest.totals(), &rate).map_err(QuoteError::Exchange)?;
+        latest.convert(rate)?;
+        self.commit(record)?;
+        Ok(converted)
+    }
+
+    // Today's rate from the rate tables given with with_rates()
+    fn rate_today(&self, from: Currency, to: Currency) -> Result<ExchangeRate, QuotingError> {
+        let rates = self.rates.as_ref().ok_or(QuotingError::NoRates { currency: to })?;
+        Ok(rates.rate(from, to, self.clock.now().date_naive()).map_err(QuoteError::Exchange)?)
+    }
+
+    fn prepare(&self, items: Vec<LineItem>) -> Result<Vec<LineItem>, QuotingError> {
+        items.into_iter().map(|item| self.check_vehicle(self.apply_rules(item))).collect()
+    }
+
+    // Adds the sales tax to a line that doesn't name its taxes; the unit price is left alone
+    fn apply_rules(&self, mut item: LineItem) -> LineItem {
+        if item.taxes.is_empty() {
+            item.taxes.push(TaxRate::new("Sales tax", self.rules.tax_rate_bp));
+        }
+        item
+    }
+
+    // Builds a vehicle line's car again against the catalog and checks what it's sold for
+    fn check_vehicle(&self, mut item: LineItem) -> Result<LineItem, QuotingError> {
+        let Some(vehicle) = &item.vehicle else {
+            return Ok(item);
+        };
+        let catalog = self.catalog.as_ref().ok_or_else(|| QuotingError::NoCatalog { sku: item.sku.clone() })?;
+        let car = CarOrder::from(vehicle.as_ref())
+            .build_against_at(catalog, self.clock.now().year() as u16)
+            .map_err(|error| QuotingError::Vehicle { sku: item.sku.clone(), error })?;
+
+        let price = PricingEngine::new(catalog, self.rules.clone())
+            .price(&car, &[])
+            .expect("Cars built against the catalog have a price");
+        if item.unit_price != price.subtotal {
+            return Err(QuotingError::PriceMismatch { sku: item.sku, expected: price.subtotal, found: item.unit_price });
+        }
+        item.vehicle = Some(Box::new(car));
+        Ok(item)
+    }
+
+    // Stores the changed record and saves; if the save fails the record is put back as it was
+    fn commit(&mut self, record: QuoteRecord) -> Result<(), QuotingError> {
+        let uuid = record.latest().uuid;
+        let previous = self.quotes.insert(uuid, record);
+        if let Err(error) = self.save() {
+            match previous {
+                Some(previous) => self.quotes.insert(uuid, previous),
+                None => self.quotes.remove(&uuid),
+            };
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    // Oldest first, so the file doesn't reshuffle on every save
+    fn save(&self) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            let mut records: Vec<&QuoteRecord> = self.quotes.values().collect();
+            records.sort_by_key(|record| (record.revisions[0].created_at(), record.latest().uuid));
+            write_json_atomically(path, &records)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::{Color, Transmission};
+    use crate::clock::FakeClock;
+    use crate::customer::Customer;
+    use crate::money::{Money, MoneyError};
+    use std::path::Path;
+    use crate::quote::QuoteStatus;
+    use chrono::{TimeZone, Utc};
+
+    fn clock() -> FakeClock {
+        FakeClock::new(Utc.with_ymd_and_hms(2023, 6, 1, 9, 0, 0).unwrap())
+    }
+
+    fn customers() -> CustomerRegistry {
+        let mut customers = CustomerRegistry::new();
+        customers.add(Customer::new("Acme Rentals", "1 Main St, Springfield", "US123456789")).unwrap();
+        customers
+    }
+
+    fn request(items: Vec<LineItem>) -> PostRequest {
+        PostRequest { customer_id: 1, items, discounts: Vec::new() }
+    }
+
+    #[test]
+    fn responses_link_back_to_stored_quotes() {
+        let clock = clock();
+        let mut service = QuoteService::new(PricingRules::default(), customers(), &clock);
+        let request = request(vec![
+            LineItem::new("FOLGORE-24", "Maserati Folgore", 1, Money::from_dollars(100_000)),
+            LineItem::new("MAT-01", "Floor mats", 4, Money::from_cents(4_999)),
+        ]);
+
+        let response = service.quote(&request).unwrap();
+
+        // (100,000.00 + 4 x 49.99) + 8.25% tax = 100,199.96 + 8,266.50
+        assert_eq!(response.amount, Money::from_cents(10_846_646));
+        let record = service.for_response(&response).unwrap();
+        assert_eq!(record.latest().uuid.to_string(), response.quote_uuid);
+        assert_eq!(record.latest().amount(), response.amount);
+        assert_eq!(record.customer_id, 1);
+        assert_eq!(record.latest().items()[1].taxes, vec![TaxRate::new("Sales tax", 825)]);
+        assert_eq!(record.latest().status(), QuoteStatus::Issued);
+        assert_eq!(record.latest().valid_until(), Some(clock.now() + Duration::days(30)));
+    }
+
+    #[test]
+    fn lines_keep_their_own_taxes_and_the_request_its_discounts() {
+        let clock = clock();
+        let mut service = QuoteService::new(PricingRules::default(), customers(), &clock);
+        let mut request = request(vec![
+            LineItem::new("MAT-01", "Floor mats", 2, Money::from_dollars(50)),
+            LineItem::new("WARRANTY", "Extended warranty", 1, Money::from_dollars(100)).taxed(TaxRate::new("Exempt", 0)),
+        ]);
+        request.discounts.push(Discount::Percent { label: "Loyalty".to_string(), basis_points: 1_000 });
+
+        let response = service.quote(&request).unwrap();
+
+        // 200.00 - 10% = 180.00, of which the mats' 90.00 pays 8.25% sales tax
+        assert_eq!(response.amount, Money::from_cents(18_743));
+        let totals = service.for_response(&response).unwrap().latest().totals();
+        assert_eq!(totals.discount(), Money::from_dollars(20));
+        assert_eq!(totals.taxes.iter().map(|tax| tax.name.as_str()).collect::<Vec<_>>(), vec!["Sales tax", "Exempt"]);
+    }
+
+    #[test]
+    fn every_quote_gets_a_fresh_uuid() {
+        let clock = clock();
+        let mut service = QuoteService::new(PricingRules::default(), customers(), &clock).with_validity(Duration::days(7));
+        let request = request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_cents(4_999))]);
+
+        let first = service.quote(&request).unwrap();
+        let second = service.quote(&request).unwrap();
+
+        assert_ne!(first.quote_uuid, second.quote_uuid);
+        assert_eq!(service.len(), 2);
+        assert_eq!(service.for_response(&second).unwrap().latest().valid_until(), Some(clock.now() + Duration::days(7)));
+        assert!(service.get(&Uuid::new_v4()).is_none());
+    }
+
+    #[test]
+    fn bad_requests_are_refused_and_not_stored() {
+        let clock = clock();
+        let mut service = QuoteService::new(PricingRules::default(), customers(), &clock);
+
+        assert_eq!(service.quote(&request(vec![])), Err(QuotingError::Totals(TotalsError::NoItems)));
+        assert_eq!(
+            service.quote(&request(vec![LineItem::new("MAT-01", "Floor mats", 0, Money::from_cents(4_999))])),
+            Err(QuotingError::Totals(TotalsError::ZeroQuantity { sku: "MAT-01".to_string() }))
+        );
+        let mixed = request(vec![
+            LineItem::new("MAT-01", "Floor mats", 1, Money::from_cents(4_999)),
+            LineItem::new("MAT-02", "Floor mats", 1, Money::new(4_599, Currency::EUR)),
+        ]);
+        assert_eq!(
+            service.quote(&mixed),
+            Err(QuotingError::Totals(TotalsError::Money(MoneyError::CurrencyMismatch {
+                expected: Currency::USD,
+                found: Currency::EUR
+            })))
+        );
+        assert!(service.is_empty());
+    }
+
+    #[test]
+    fn revisions_keep_the_uuid_and_every_earlier_revision() {
+        let clock = clock();
+        let mut service = QuoteService::new(PricingRules::default(), customers(), &clock);
+        let first = service.quote(&request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(50))])).unwrap();
+        let uuid = Uuid::parse_str(&first.quote_uuid).unwrap();
+
+        clock.advance(Duration::days(1));
+        let items = vec![LineItem::new("MAT-01", "Floor mats", 2, Money::from_dollars(50))];
+        let second = service.revise(&uuid, items.clone(), Vec::new()).unwrap();
+        let third = service.revise(&uuid, items, vec![Discount::Fixed { label: "Goodwill".to_string(), amount: Money::from_dollars(10) }]).unwrap();
+
+        assert_eq!(second.quote_uuid, first.quote_uuid);
+        assert_eq!(third.quote_uuid, first.quote_uuid);
+        let record = service.get(&uuid).unwrap();
+        let statuses: Vec<(u8, QuoteStatus)> = record.revisions().iter().map(|quote| (quote.index(), quote.status())).collect();
+        assert_eq!(statuses, vec![(1, QuoteStatus::Superseded), (2, QuoteStatus::Superseded), (3, QuoteStatus::Issued)]);
+        assert_eq!(record.latest().amount(), third.amount);
+
+        let diff = record.diff(1, 3).unwrap();
+        assert_eq!(diff.total.before, first.amount);
+        assert_eq!(diff.total.after, third.amount);
+        assert!(diff.discounts_changed);
+        assert!(record.diff(1, 4).is_none());
+    }
+
+    #[test]
+    fn failed_revisions_change_nothing() {
+        let clock = clock();
+        let mut service = QuoteService::new(PricingRules::default(), customers(), &clock);
+        let first = service.quote(&request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(50))])).unwrap();
+        let uuid = Uuid::parse_str(&first.quote_uuid).unwrap();
+
+        assert_eq!(service.revise(&uuid, Vec::new(), Vec::new()), Err(QuotingError::Quote(QuoteError::Totals(TotalsError::NoItems))));
+        assert_eq!(service.get(&uuid).unwrap().latest().status(), QuoteStatus::Issued);
+        let stranger = Uuid::new_v4();
+        assert_eq!(service.revise(&stranger, Vec::new(), Vec::new()), Err(QuotingError::UnknownQuote(stranger)));
+    }
+
+    #[test]
+    fn converts_with_the_rates_in_effect_today() {
+        let clock = clock();
+        let mut service = QuoteService::new(PricingRules::default(), customers(), &clock);
+        let response = service.quote(&request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(100))])).unwrap();
+        let uuid = Uuid::parse_str(&response.quote_uuid).unwrap();
+        assert_eq!(service.convert(&uuid, Currency::EUR), Err(QuotingError::NoRates { currency: Currency::EUR }));
+
+        let mut service = service.with_rates(RateTables::load(Path::new("rates.json")).unwrap());
+        let converted = service.convert(&uuid, Currency::EUR).unwrap();
+
+        // 100.00 + 8.25 tax at 0.9214 euros to the dollar
+        assert_eq!(converted.total, Money::new(9_974, Currency::EUR));
+        let rate = service.get(&uuid).unwrap().latest().conversion().unwrap();
+        assert_eq!(rate.effective.to_string(), "2023-06-01");
+        assert_eq!(rate.to_rate.to_string(), "0.921400");
+    }
+
+    #[test]
+    fn quotes_are_only_made_out_to_known_customers() {
+        let clock = clock();
+        let mut service = QuoteService::new(PricingRules::default(), customers(), &clock);
+        let mut stranger = request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(50))]);
+        stranger.customer_id = 42;
+
+        assert_eq!(service.quote(&stranger), Err(QuotingError::UnknownCustomer(42)));
+        assert!(service.is_empty());
+    }
+
+    #[test]
+    fn requests_without_discounts_get_the_customers_standing_discount() {
+        let clock = clock();
+        let mut customers = customers();
+        let fleet = Discount::Percent { label: "Fleet".to_string(), basis_points: 1_000 };
+        let id = customers.add(Customer::new("Bauer GmbH", "Hauptstr. 5, Berlin", "DE123456789").with_discount(fleet.clone())).unwrap();
+        let mut service = QuoteService::new(PricingRules::default(), customers, &clock);
+        let mut request = request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(100)).taxed(TaxRate::new("Exempt", 0))]);
+        request.customer_id = id;
+
+        let standing = service.quote(&request).unwrap();
+        request.discounts.push(Discount::Fixed { label: "Goodwill".to_string(), amount: Money::from_dollars(5) });
+        let own = service.quote(&request).unwrap();
+
+        assert_eq!(service.for_response(&standing).unwrap().latest().discounts(), &[fleet][..]);
+        assert_eq!(standing.amount, Money::from_dollars(90));
+        assert_eq!(own.amount, Money::from_dollars(95));
+    }
+
+    #[test]
+    fn customers_paying_in_another_currency_get_converted_quotes() {
+        let clock = clock();
+        let mut customers = customers();
+        let id = customers.add(Customer::new("Bauer GmbH", "Hauptstr. 5, Berlin", "DE123456789").paying_in(Currency::EUR)).unwrap();
+        let mut request = request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(100))]);
+        request.customer_id = id;
+
+        let mut without_rates = QuoteService::new(PricingRules::default(), customers, &clock);
+        assert_eq!(without_rates.quote(&request), Err(QuotingError::NoRates { currency: Currency::EUR }));
+
+        let customers = std::mem::take(without_rates.customers_mut());
+        let rates = RateTables::load(Path::new("rates.json")).unwrap();
+        let mut service = QuoteService::new(PricingRules::default(), customers, &clock).with_rates(rates);
+        let response = service.quote(&request).unwrap();
+
+        // Quoted in dollars, shown in euros: 108.25 at 0.9214
+        assert_eq!(response.amount, Money::from_cents(10_825));
+        let quote = service.for_response(&response).unwrap().latest();
+        assert_eq!(quote.converted_totals().unwrap().total, Money::new(9_974, Currency::EUR));
+
+        // A revision a month later gets July's rate of 0.9165
+        clock.advance(Duration::days(31));
+        let uuid = Uuid::parse_str(&response.quote_uuid).unwrap();
+        service.revise(&uuid, request.items.clone(), Vec::new()).unwrap();
+        let revised = service.get(&uuid).unwrap().latest();
+        assert_eq!(revised.conversion().unwrap().effective, chrono::NaiveDate::from_ymd_opt(2023, 7, 1).unwrap());
+        assert_eq!(revised.converted_totals().unwrap().total, Money::new(9_921, Currency::EUR));
+    }
+
+    #[test]
+    fn quotes_survive_restart_and_are_listed_per_customer() {
+        let path = std::env::temp_dir().join(format!("quotes-{}.json", uuid::Uuid::new_v4()));
+        let clock = clock();
+        let mut customers = customers();
+        let other = customers.add(Customer::new("Bauer GmbH", "Hauptstr. 5, Berlin", "DE123456789")).unwrap();
+
+        let mut service = QuoteService::open(path.clone(), PricingRules::default(), customers, &clock).unwrap();
+        let first = service.quote(&request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(50))])).unwrap();
+        clock.advance(Duration::hours(1));
+        let second = service.quote(&request(vec![LineItem::new("WAX", "Wax", 1, Money::from_dollars(20))])).unwrap();
+        service.revise(&Uuid::parse_str(&first.quote_uuid).unwrap(), vec![LineItem::new("MAT-01", "Floor mats", 2, Money::from_dollars(50))], Vec::new()).unwrap();
+
+        let customers = std::mem::take(service.customers_mut());
+        let reopened = QuoteService::open(path.clone(), PricingRules::default(), customers, &clock).unwrap();
+        let listed: Vec<String> = reopened.for_customer(1).iter().map(|record| record.latest().uuid.to_string()).collect();
+        assert_eq!(listed, vec![first.quote_uuid.clone(), second.quote_uuid]);
+        assert_eq!(reopened.for_response(&first).unwrap().revisions().len(), 2);
+        assert!(reopened.for_customer(other).is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn broken_quote_files_are_refused() {
+        let path = std::env::temp_dir().join(format!("quotes-{}.json", uuid::Uuid::new_v4()));
+        let clock = clock();
+        let mut service = QuoteService::open(path.clone(), PricingRules::default(), customers(), &clock).unwrap();
+        let mats = request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(50))]);
+        let first = Uuid::parse_str(&service.quote(&mats).unwrap().quote_uuid).unwrap();
+        let second = Uuid::parse_str(&service.quote(&mats).unwrap().quote_uuid).unwrap();
+        service.revise(&first, mats.items.clone(), Vec::new()).unwrap();
+        let records: Vec<QuoteRecord> = [first, second].iter().map(|uuid| service.get(uuid).unwrap().clone()).collect();
+
+        let reopen = |records: serde_json::Value| {
+            std::fs::write(&path, records.to_string()).unwrap();
+            QuoteService::open(path.clone(), PricingRules::default(), customers(), &clock).map(|service| service.len())
+        };
+        assert_eq!(reopen(serde_json::to_value(&records).unwrap()).unwrap(), 2);
+
+        let empty = serde_json::json!([{ "customer_id": 1, "revisions": [] }]);
+        let mut mixed = serde_json::to_value(&records[0]).unwrap();
+        mixed["revisions"][1] = serde_json::to_value(records[1].latest()).unwrap();
+        let mut backwards = serde_json::to_value(&records[0]).unwrap();
+        backwards["revisions"].as_array_mut().unwrap().reverse();
+        let mut unaccepted = serde_json::to_value(&records[1]).unwrap();
+        unaccepted["orders"] = serde_json::json!([1, 2]);
+        for broken in [empty, serde_json::json!([mixed]), serde_json::json!([backwards]), serde_json::json!([unaccepted])] {
+            assert_eq!(reopen(broken).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failed_saves_change_nothing() {
+        let dir = std::env::temp_dir().join(format!("quotes-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let clock = clock();
+        let mut service = QuoteService::open(dir.join("quotes.json"), PricingRules::default(), customers(), &clock).unwrap();
+        let mats = service.quote(&request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(50))])).unwrap();
+        let uuid = Uuid::parse_str(&mats.quote_uuid).unwrap();
+        let before = service.get(&uuid).unwrap().clone();
+
+        // With the directory gone nothing can be saved
+        std::fs::remove_dir_all(&dir).unwrap();
+        let wax = request(vec![LineItem::new("WAX", "Wax", 1, Money::from_dollars(20))]);
+        assert!(matches!(service.quote(&wax), Err(QuotingError::Storage(_))));
+        assert!(matches!(service.revise(&uuid, wax.items.clone(), Vec::new()), Err(QuotingError::Storage(_))));
+        assert!(matches!(service.accept(&uuid), Err(QuotingError::Storage(_))));
+
+        assert_eq!(service.len(), 1);
+        assert_eq!(service.get(&uuid), Some(&before));
+    }
+
+    #[test]
+    fn vehicle_lines_are_checked_against_the_catalog() {
+        let clock = clock();
+        let catalog = Catalog::load(PathBuf::from("catalog.json")).unwrap();
+        let folgore = CarOrder::new()
+            .make("maserati")
+            .model("folgore")
+            .year(2024)
+            .color(Color::Blue)
+            .transmission(Transmission::Automatic)
+            .roof(true);
+        let car = folgore.clone().build_against(&catalog).unwrap();
+        let price = PricingEngine::new(&catalog, PricingRules::default()).price(&car, &[]).unwrap();
+        let line = |order: CarOrder, unit_price: Money| {
+            LineItem::new("FOLGORE", "Maserati Folgore", 1, unit_price).for_vehicle(order.build().unwrap())
+        };
+
+        let mut without_catalog = QuoteService::new(PricingRules::default(), customers(), &clock);
+        assert_eq!(
+            without_catalog.quote(&request(vec![line(folgore.clone(), price.subtotal)])),
+            Err(QuotingError::NoCatalog { sku: "FOLGORE".to_string() })
+        );
+
+        let mut service = QuoteService::new(PricingRules::default(), customers(), &clock).with_catalog(catalog.clone());
+        assert_eq!(
+            service.quote(&request(vec![line(folgore.clone().year(2022), price.subtotal)])),
+            Err(QuotingError::Vehicle {
+                sku: "FOLGORE".to_string(),
+                error: CarOrderError::YearNotOffered { model: "Maserati Folgore".to_string(), year: 2022, offered: vec![2023, 2024] },
+            })
+        );
+        assert_eq!(
+            service.quote(&request(vec![line(folgore.clone(), Money::from_dollars(1))])),
+            Err(QuotingError::PriceMismatch { sku: "FOLGORE".to_string(), expected: price.subtotal, found: Money::from_dollars(1) })
+        );
+        assert!(service.is_empty());
+
+        // The stored car is the catalog's, ready to be given a VIN when it's ordered
+        let response = service.quote(&request(vec![line(folgore.clone(), price.subtotal)])).unwrap();
+        let uuid = Uuid::parse_str(&response.quote_uuid).unwrap();
+        let car = service.get(&uuid).unwrap().latest().items()[0].vehicle.clone().unwrap();
+        assert_eq!((car.make(), car.model(), car.wmi.as_deref()), ("Maserati", "Folgore", Some("ZAM")));
+
+        let cheaper = vec![line(folgore, price.subtotal - Money::from_dollars(500))];
+        assert!(matches!(service.revise(&uuid, cheaper, Vec::new()), Err(QuotingError::PriceMismatch { .. })));
+    }
+
+    #[test]
+    fn only_accepted_quotes_with_vehicles_are_ordered() {
+        let clock = clock();
+        let mut service = QuoteService::new(PricingRules::default(), customers(), &clock);
+        let mut inventory = Inventory::new();
+        let mats = service.quote(&request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(50))])).unwrap();
+        let uuid = Uuid::parse_str(&mats.quote_uuid).unwrap();
+
+        assert_eq!(service.place_orders(&uuid, &mut inventory), Err(QuotingError::NotAccepted { status: QuoteStatus::Issued }));
+        service.accept(&uuid).unwrap();
+        assert_eq!(service.place_orders(&uuid, &mut inventory), Err(QuotingError::NoVehicles(uuid)));
+
+        clock.advance(Duration::days(31));
+        let late = service.quote(&request(vec![LineItem::new("MAT-01", "Floor mats", 1, Money::from_dollars(50))])).unwrap();
+        clock.advance(Duration::days(31));
+        let late = Uuid::parse_str(&late.quote_uuid).unwrap();
+        assert!(matches!(service.accept(&late), Err(QuotingError::Quote(QuoteError::Expired { .. }))));
+        assert!(inventory.orders().is_empty());
+    }
+}
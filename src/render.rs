@@ -0,0 +1,420 @@
+// Renders quotes into customer-facing documents, as HTML or as fixed-width plain text
+
+/*
+    Documents come from templates, so changing the wording or the layout doesn't mean changing
+    code. The template language is deliberately small:
+
+        {{name}}                   the value of a field
+        {{name:<30}} {{name:>12}}  padded to a width, left or right aligned (for plain text)
+        {{name:<30.30}}            padded, and cut off at 30 characters
+        {{#name}} ... {{/name}}    a section: repeated once per entry of a list, shown once for a
+                                   field with a value, and left out for an empty or missing one
+
+    Inside a list section the entry's own fields are available along with all the outer ones.
+    Templates are checked when they're parsed, and a field the document doesn't have is an error
+    when rendering rather than a blank in a customer's quote. HTML templates escape every value.
+
+    The built-in templates live in templates/ and are compiled into the program. The fields a
+    quote document offers are listed in quote_fields().
+ */
+
+use crate::quote::{Quote, QuoteStatus};
+use std::fmt;
+
+pub const HTML_TEMPLATE: &str = include_str!("../templates/quote.html");
+pub const TEXT_TEMPLATE: &str = include_str!("../templates/quote.txt");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escape {
+    Html,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateError {
+    Unclosed(String),
+    Unexpected(String),
+    BadFormat(String),
+    UnknownField(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Unclosed(tag) => write!(f, "template: '{}' is never closed", tag),
+            TemplateError::Unexpected(tag) => write!(f, "template: '{}' doesn't close anything", tag),
+            TemplateError::BadFormat(tag) => write!(f, "template: can't read the format of '{}'", tag),
+            TemplateError::UnknownField(name) => write!(f, "template: there is no field '{}'", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Align {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Format {
+    align: Align,
+    width: usize,
+    max: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field { name: String, format: Option<Format> },
+    Section { name: String, body: Vec<Part> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+    escape: Escape,
+}
+
+impl Template {
+    pub fn parse(text: &str, escape: Escape) -> Result<Template, TemplateError> {
+        let mut rest = text;
+        let parts = parse_parts(&mut rest, None)?;
+        Ok(Template { parts, escape })
+    }
+
+    pub fn html() -> Template {
+        Template::parse(HTML_TEMPLATE, Escape::Html).expect("The built-in HTML template is valid")
+    }
+
+    pub fn text() -> Template {
+        Template::parse(TEXT_TEMPLATE, Escape::None).expect("The built-in text template is valid")
+    }
+
+    pub fn render(&self, fields: &Fields) -> Result<String, TemplateError> {
+        let mut out = String::new();
+        render_parts(&self.parts, &[fields], self.escape, &mut out)?;
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    List(Vec<Fields>),
+}
+
+/// The named values a template can use.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fields(Vec<(String, Value)>);
+
+impl Fields {
+    pub fn new() -> Self {
+        Fields::default()
+    }
+
+    pub fn text(mut self, name: &str, value: impl ToString) -> Self {
+        self.0.push((name.to_string(), Value::Text(value.to_string())));
+        self
+    }
+
+    pub fn list(mut self, name: &str, entries: Vec<Fields>) -> Self {
+        self.0.push((name.to_string(), Value::List(entries)));
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.0.iter().find(|(field, _)| field == name).map(|(_, value)| value)
+    }
+}
+
+// Parses until the end of the text, or until the {{/name}} that closes the section `closing`
+fn parse_parts(rest: &mut &str, closing: Option<&str>) -> Result<Vec<Part>, TemplateError> {
+    let mut parts = Vec::new();
+    loop {
+        let Some(start) = rest.find("{{") else {
+            if let Some(name) = closing {
+                return Err(TemplateError::Unclosed(format!("{{{{#{}}}}}", name)));
+            }
+            if !rest.is_empty() {
+                parts.push(Part::Text(rest.to_string()));
+            }
+            *rest = "";
+            return Ok(parts);
+        };
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        let end = rest[start..].find("}}").ok_or_else(|| TemplateError::Unclosed(rest[start..].lines().next().unwrap_or("").to_string()))?;
+        let tag = rest[start + 2..start + end].trim().to_string();
+        *rest = &rest[start + end + 2..];
+
+        if let Some(name) = tag.strip_prefix('#') {
+            let body = parse_parts(rest, Some(name))?;
+            parts.push(Part::Section { name: name.to_string(), body });
+        } else if let Some(name) = tag.strip_prefix('/') {
+            return match closing {
+                Some(open) if open == name => Ok(parts),
+                _ => Err(TemplateError::Unexpected(format!("{{{{{}}}}}", tag))),
+            };
+        } else {
+            let (name, format) = match tag.split_once(':') {
+                Some((name, format)) => (name, Some(parse_format(format).ok_or_else(|| TemplateError::BadFormat(tag.clone()))?)),
+                None => (tag.as_str(), None),
+            };
+            parts.push(Part::Field { name: name.trim().to_string(), format });
+        }
+    }
+}
+
+// "<30", ">12" or "<30.30"
+fn parse_format(format: &str) -> Option<Format> {
+    let align = match format.chars().next()? {
+        '<' => Align::Left,
+        '>' => Align::Right,
+        _ => return None,
+    };
+    let (width, max) = match format[1..].split_once('.') {
+        Some((width, max)) => (width.parse().ok()?, Some(max.parse().ok()?)),
+        None => (format[1..].parse().ok()?, None),
+    };
+    Some(Format { align, width, max })
+}
+
+fn lookup<'f>(scopes: &[&'f Fields], name: &str) -> Option<&'f Value> {
+    scopes.iter().rev().find_map(|fields| fields.get(name))
+}
+
+fn render_parts(parts: &[Part], scopes: &[&Fields], escape: Escape, out: &mut String) -> Result<(), TemplateError> {
+    for part in parts {
+        match part {
+            Part::Text(text) => out.push_str(text),
+            Part::Field { name, format } => match lookup(scopes, name) {
+                Some(Value::Text(value)) => {
+                    let value = format.as_ref().map_or_else(|| value.clone(), |format| pad(value, format));
+                    out.push_str(&match escape {
+                        Escape::Html => escape_html(&value),
+                        Escape::None => value,
+                    });
+                }
+                _ => return Err(TemplateError::UnknownField(name.clone())),
+            },
+            Part::Section { name, body } => match lookup(scopes, name) {
+                Some(Value::List(entries)) => {
+                    for entry in entries {
+                        let mut inner = scopes.to_vec();
+                        inner.push(entry);
+                        render_parts(body, &inner, escape, out)?;
+                    }
+                }
+                Some(Value::Text(value)) if !value.is_empty() => render_parts(body, scopes, escape, out)?,
+                _ => {}
+            },
+        }
+    }
+    Ok(())
+}
+
+fn pad(value: &str, format: &Format) -> String {
+    let value: String = match format.max {
+        Some(max) => value.chars().take(max).collect(),
+        None => value.to_string(),
+    };
+    match format.align {
+        Align::Left => format!("{:<width$}", value, width = format.width),
+        Align::Right => format!("{:>width$}", value, width = format.width),
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Everything a quote document can show:
+///
+/// customer, uuid, revision, status, note, created, issued, valid_until, currency,
+/// items (sku, description, quantity, unit_price, gross, line_discount, amount),
+/// subtotal, discounts (label, amount), discount, taxes (name, rate, base, amount), tax, total,
+/// and converted (currency, subtotal, discount, tax, total, rate) when the quote has been converted.
+///
+/// Dates are shown as YYYY-MM-DD. Optional values are empty when absent. Discount lines and
+/// line_discount are negative amounts; the total discount is positive.
+pub fn quote_fields(customer: &str, quote: &Quote) -> Fields {
+    let date = |at: chrono::DateTime<chrono::Utc>| at.format("%Y-%m-%d").to_string();
+    let unless_zero = |amount: crate::money::Money| if amount.is_zero() { String::new() } else { amount.to_string() };
+    let totals = quote.totals();
+
+    let items = quote
+        .items()
+        .iter()
+        .zip(&totals.lines)
+        .map(|(item, line)| {
+            Fields::new()
+                .text("sku", &item.sku)
+                .text("description", &item.description)
+                .text("quantity", item.quantity)
+                .text("unit_price", item.unit_price)
+                .text("gross", line.gross)
+                .text("line_discount", unless_zero(-line.discount))
+                .text("amount", line.net)
+        })
+        .collect();
+    let discounts = totals.discounts.iter().map(|line| Fields::new().text("label", &line.label).text("amount", line.amount)).collect();
+    let taxes = totals
+        .taxes
+        .iter()
+        .map(|tax| {
+            Fields::new()
+                .text("name", &tax.name)
+                .text("rate", format!("{}.{:02}%", tax.basis_points / 100, tax.basis_points % 100))
+                .text("base", tax.base)
+                .text("amount", tax.amount)
+        })
+        .collect();
+    let converted = quote
+        .converted_totals()
+        .map(|converted| {
+            Fields::new()
+                .text("currency", converted.rate.to)
+                .text("subtotal", converted.subtotal)
+                .text("discount", unless_zero(converted.discount))
+                .text("tax", converted.tax)
+                .text("total", converted.total)
+                .text("rate", &converted.rate)
+        })
+        .into_iter()
+        .collect();
+    let issued = quote.history().iter().find(|change| change.status == QuoteStatus::Issued).map(|change| date(change.at));
+
+    Fields::new()
+        .text("customer", customer)
+        .text("uuid", quote.uuid)
+        .text("revision", quote.index())
+        .text("status", quote.status())
+        .text("note", quote.entangled().unwrap_or_default())
+        .text("created", date(quote.created_at()))
+        .text("issued", issued.unwrap_or_default())
+        .text("valid_until", quote.valid_until().map(date).unwrap_or_default())
+        .text("currency", quote.currency())
+        .list("items", items)
+        .text("subtotal", totals.subtotal)
+        .list("discounts", discounts)
+        .text("discount", unless_zero(totals.discount()))
+        .list("taxes", taxes)
+        .text("tax", totals.tax)
+        .text("total", totals.total)
+        .list("converted", converted)
+}
+
+pub fn render_html(customer: &str, quote: &Quote) -> String {
+    Template::html().render(&quote_fields(customer, quote)).expect("The built-in HTML template only uses quote fields")
+}
+
+pub fn render_text(customer: &str, quote: &Quote) -> String {
+    Template::text().render(&quote_fields(customer, quote)).expect("The built-in text template only uses quote fields")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::{ExchangeRate, Rate};
+    use crate::money::{Currency, Money};
+    use crate::pricing::Discount;
+    use crate::totals::{LineItem, TaxRate};
+    use chrono::{Duration, TimeZone, Utc};
+    use std::path::Path;
+
+    fn quote() -> Quote {
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 9, 0, 0).unwrap();
+        let sales_tax = TaxRate::new("Sales tax", 825);
+        let items = vec![
+            LineItem::new("FOLGORE-24", "Maserati Folgore, Blue, Automatic", 1, Money::from_dollars(137_500)).taxed(sales_tax.clone()),
+            LineItem::new("MAT-01", "All-weather floor mats <deluxe> & trunk liner set", 4, Money::from_cents(4_999))
+                .discounted(Discount::Percent { label: "Bundle".to_string(), basis_points: 1_000 })
+                .taxed(sales_tax),
+            LineItem::new("WARRANTY", "Extended warranty, 5 years", 1, Money::from_dollars(2_400)).taxed(TaxRate::new("Exempt", 0)),
+        ];
+        let discounts = vec![Discount::Fixed { label: "Trade-in".to_string(), amount: Money::from_dollars(20_000) }];
+        let mut quote = Quote::draft(items, discounts, Some("Delivery to the Dallas showroom".to_string()), now).unwrap();
+        quote.uuid = uuid::Uuid::parse_str("cabc33f5-5edf-4ef0-b698-d3a33dfc15fe").unwrap();
+        quote.issue(Duration::days(30), now + Duration::hours(2)).unwrap();
+        quote
+            .convert(ExchangeRate {
+                from: Currency::USD,
+                to: Currency::EUR,
+                base: Currency::USD,
+                effective: now.date_naive(),
+                from_rate: Rate::ONE,
+                to_rate: "0.9214".parse().unwrap(),
+            })
+            .unwrap();
+        quote
+    }
+
+    // Compares with the file in tests/snapshots, or rewrites it when UPDATE_SNAPSHOTS is set
+    fn assert_snapshot(name: &str, actual: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots").join(name);
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(&path, actual).unwrap();
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(actual == expected, "{} doesn't match; rerun with UPDATE_SNAPSHOTS=1 to accept:\n{}", name, actual);
+    }
+
+    #[test]
+    fn text_snapshot() {
+        assert_snapshot("quote.txt", &render_text("Ada Lovelace", &quote()));
+    }
+
+    #[test]
+    fn html_snapshot() {
+        assert_snapshot("quote.html", &render_html("Ada <Countess> Lovelace", &quote()));
+    }
+
+    #[test]
+    fn sections_repeat_show_and_hide() {
+        let template = Template::parse("{{#rows}}[{{name:<4}}|{{n:>3}}]{{/rows}}{{#note}} note: {{note}}{{/note}}{{#gone}}!{{/gone}}", Escape::None).unwrap();
+        let rows = vec![Fields::new().text("name", "a").text("n", 1), Fields::new().text("name", "abcdef").text("n", 22)];
+
+        let fields = Fields::new().list("rows", rows.clone()).text("note", "");
+        assert_eq!(template.render(&fields).unwrap(), "[a   |  1][abcdef| 22]");
+        let fields = Fields::new().list("rows", rows).text("note", "hi");
+        assert_eq!(template.render(&fields).unwrap(), "[a   |  1][abcdef| 22] note: hi");
+    }
+
+    #[test]
+    fn formats_pad_truncate_and_escape() {
+        let fields = Fields::new().text("name", "Fish & <Chips>");
+        assert_eq!(Template::parse("{{name:<6.6}}|", Escape::None).unwrap().render(&fields).unwrap(), "Fish &|");
+        assert_eq!(Template::parse("{{ name }}", Escape::Html).unwrap().render(&fields).unwrap(), "Fish &amp; &lt;Chips&gt;");
+    }
+
+    #[test]
+    fn bad_templates_are_caught() {
+        assert_eq!(Template::parse("{{#items}}x", Escape::None), Err(TemplateError::Unclosed("{{#items}}".to_string())));
+        assert_eq!(Template::parse("x{{/items}}", Escape::None), Err(TemplateError::Unexpected("{{/items}}".to_string())));
+        assert_eq!(Template::parse("{{#a}}{{/b}}", Escape::None), Err(TemplateError::Unexpected("{{/b}}".to_string())));
+        assert_eq!(Template::parse("{{total:^9}}", Escape::None), Err(TemplateError::BadFormat("total:^9".to_string())));
+        assert_eq!(Template::parse("{{total", Escape::None), Err(TemplateError::Unclosed("{{total".to_string())));
+        let template = Template::parse("{{customer}} {{totl}}", Escape::None).unwrap();
+        assert_eq!(template.render(&quote_fields("Ada", &quote())), Err(TemplateError::UnknownField("totl".to_string())));
+    }
+
+    #[test]
+    fn drafts_have_no_validity_dates() {
+        let draft = Quote::draft(vec![LineItem::new("A", "A", 1, Money::from_dollars(1))], Vec::new(), None, Utc::now()).unwrap();
+        let text = render_text("Ada", &draft);
+        assert!(!text.contains("Valid until"), "{}", text);
+        assert!(!text.contains("Converted"), "{}", text);
+    }
+}
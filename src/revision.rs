@@ -0,0 +1,194 @@
+// What changed between two revisions of a quote
+
+/*
+    Line items are matched up by SKU. A SKU only in the later revision was added, one only in the
+    earlier revision was removed, and one in both whose quantity, price, discount, taxes or
+    description differ was changed. The totals are recomputed for both revisions and compared
+    figure by figure. Revisions can be compared in either order, and not only neighbouring ones.
+ */
+
+use crate::money::Money;
+use crate::quote::Quote;
+use crate::totals::LineItem;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineChange {
+    Added(LineItem),
+    Removed(LineItem),
+    Changed { before: LineItem, after: LineItem },
+}
+
+impl LineChange {
+    pub fn sku(&self) -> &str {
+        match self {
+            LineChange::Added(item) | LineChange::Removed(item) => &item.sku,
+            LineChange::Changed { after, .. } => &after.sku,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmountChange {
+    pub before: Money,
+    pub after: Money,
+}
+
+impl AmountChange {
+    pub fn is_changed(&self) -> bool {
+        self.before != self.after
+    }
+
+    pub fn difference(&self) -> Money {
+        self.after - self.before
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteDiff {
+    pub from: u8,
+    pub to: u8,
+    /// Changed and removed lines in the earlier revision's order, then added lines in the later one's.
+    pub lines: Vec<LineChange>,
+    pub discounts_changed: bool,
+    pub subtotal: AmountChange,
+    pub discount: AmountChange,
+    pub tax: AmountChange,
+    pub total: AmountChange,
+}
+
+impl QuoteDiff {
+    pub fn between(before: &Quote, after: &Quote) -> QuoteDiff {
+        let find = |items: &[LineItem], sku: &str| items.iter().find(|item| item.sku == sku).cloned();
+
+        let mut lines = Vec::new();
+        for item in before.items() {
+            match find(after.items(), &item.sku) {
+                None => lines.push(LineChange::Removed(item.clone())),
+                Some(later) if later != *item => lines.push(LineChange::Changed { before: item.clone(), after: later }),
+                Some(_) => {}
+            }
+        }
+        for item in after.items() {
+            if find(before.items(), &item.sku).is_none() {
+                lines.push(LineChange::Added(item.clone()));
+            }
+        }
+
+        let (old, new) = (before.totals(), after.totals());
+        let change = |before, after| AmountChange { before, after };
+        QuoteDiff {
+            from: before.index(),
+            to: after.index(),
+            lines,
+            discounts_changed: before.discounts() != after.discounts(),
+            subtotal: change(old.subtotal, new.subtotal),
+            discount: change(old.discount(), new.discount()),
+            tax: change(old.tax, new.tax),
+            total: change(old.total, new.total),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && !self.discounts_changed && !self.total.is_changed()
+    }
+}
+
+impl fmt::Display for QuoteDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let item = |item: &LineItem| format!("{} x {} @ {}", item.description, item.quantity, item.unit_price);
+
+        writeln!(f, "Revision {} -> {}", self.from, self.to)?;
+        for change in &self.lines {
+            match change {
+                LineChange::Added(added) => writeln!(f, "+ {:<12} {}", added.sku, item(added))?,
+                LineChange::Removed(removed) => writeln!(f, "- {:<12} {}", removed.sku, item(removed))?,
+                LineChange::Changed { before, after } => {
+                    writeln!(f, "~ {:<12} {}  ->  {}", after.sku, item(before), item(after))?
+                }
+            }
+        }
+        if self.discounts_changed {
+            writeln!(f, "~ discounts")?;
+        }
+
+        let amounts = [("Subtotal", &self.subtotal), ("Discount", &self.discount), ("Tax", &self.tax), ("Total", &self.total)];
+        for (index, (label, amount)) in amounts.iter().enumerate() {
+            let difference = amount.difference();
+            let sign = if difference.is_negative() { "" } else { "+" };
+            write!(f, "{:<10} {:>14} -> {:>14} ({}{})", label, amount.before, amount.after, sign, difference)?;
+            if index + 1 < amounts.len() {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pricing::Discount;
+    use crate::totals::TaxRate;
+    use chrono::{TimeZone, Utc};
+
+    fn item(sku: &str, quantity: u32, dollars: i64) -> LineItem {
+        LineItem::new(sku, sku, quantity, Money::from_dollars(dollars)).taxed(TaxRate::new("Sales tax", 1_000))
+    }
+
+    fn revisions() -> (Quote, Quote) {
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 9, 0, 0).unwrap();
+        let mut first = Quote::draft(vec![item("CAR", 1, 1_000), item("MAT", 1, 50), item("WAX", 1, 20)], Vec::new(), None, now).unwrap();
+        let loyalty = vec![Discount::Fixed { label: "Loyalty".to_string(), amount: Money::from_dollars(100) }];
+        let second = first.revise(vec![item("CAR", 1, 1_000), item("MAT", 2, 50), item("TINT", 1, 300)], loyalty, now).unwrap();
+        (first, second)
+    }
+
+    #[test]
+    fn lists_added_removed_and_changed_lines() {
+        let (first, second) = revisions();
+        let diff = QuoteDiff::between(&first, &second);
+
+        assert_eq!((diff.from, diff.to), (1, 2));
+        assert_eq!(
+            diff.lines,
+            vec![
+                LineChange::Changed { before: item("MAT", 1, 50), after: item("MAT", 2, 50) },
+                LineChange::Removed(item("WAX", 1, 20)),
+                LineChange::Added(item("TINT", 1, 300)),
+            ]
+        );
+        assert!(diff.discounts_changed);
+        assert_eq!(diff.subtotal, AmountChange { before: Money::from_dollars(1_070), after: Money::from_dollars(1_400) });
+        assert_eq!(diff.discount.difference(), Money::from_dollars(100));
+        assert_eq!(diff.total, AmountChange { before: Money::from_dollars(1_177), after: Money::from_dollars(1_430) });
+    }
+
+    #[test]
+    fn works_in_either_direction_and_is_empty_for_the_same_revision() {
+        let (first, second) = revisions();
+        let backwards = QuoteDiff::between(&second, &first);
+
+        assert_eq!(backwards.lines.iter().map(LineChange::sku).collect::<Vec<_>>(), vec!["MAT", "TINT", "WAX"]);
+        assert_eq!(backwards.total.difference(), -QuoteDiff::between(&first, &second).total.difference());
+        assert!(QuoteDiff::between(&second, &second).is_empty());
+    }
+
+    #[test]
+    fn displays_the_changes() {
+        let (first, second) = revisions();
+        let text = QuoteDiff::between(&first, &second).to_string();
+
+        let expected = "\
+Revision 1 -> 2
+~ MAT          MAT x 1 @ $50.00  ->  MAT x 2 @ $50.00
+- WAX          WAX x 1 @ $20.00
++ TINT         TINT x 1 @ $300.00
+~ discounts
+Subtotal         $1070.00 ->       $1400.00 (+$330.00)
+Discount            $0.00 ->        $100.00 (+$100.00)
+Tax               $107.00 ->        $130.00 (+$23.00)
+Total            $1177.00 ->       $1430.00 (+$253.00)";
+        assert_eq!(text, expected);
+    }
+}
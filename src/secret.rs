@@ -0,0 +1,132 @@
+// A wrapper for credentials that keeps them out of logs and scrubs them from memory
+
+/*
+    Wrapping a value in Secret does three things:
+    - {:?} and {} print [REDACTED] instead of the value, so a stray println! or a derived Debug on
+      a struct holding the secret can't leak it.
+    - When the Secret is dropped its memory is overwritten with zeroes (through the zeroize crate,
+      which stops the compiler from optimising the "useless" final write away).
+    - Getting at the value takes an explicit call to expose_secret(), which is easy to grep for.
+
+    Serialization is transparent, since the stores that persist hashes need the real value on disk.
+ */
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use zeroize::Zeroize;
+
+const REDACTED: &str = "[REDACTED]";
+
+pub struct Secret<T: Zeroize>(T);
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Secret(value)
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Secret(self.0.clone())
+    }
+}
+
+// Secrets are compared in constant time, like every other credential comparison in the crate
+impl<T: Zeroize + AsRef<[u8]>> PartialEq for Secret<T> {
+    fn eq(&self, other: &Self) -> bool {
+        crate::auth::constant_time_eq(self.0.as_ref(), other.0.as_ref())
+    }
+}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T: Zeroize> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T: Zeroize + Serialize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Zeroize + Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_key::{ApiKeyStore, Scope};
+    use crate::auth::User;
+    use crate::totp::TotpConfig;
+    use chrono::Utc;
+
+    #[test]
+    fn secret_is_redacted_but_serializes_transparently() {
+        let secret = Secret::new(String::from("hunter2"));
+
+        assert_eq!(format!("{}", secret), "[REDACTED]");
+        assert_eq!(format!("{:?}", secret), "[REDACTED]");
+        assert_eq!(format!("{:#?}", Some(&secret)), "Some(\n    [REDACTED],\n)");
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"hunter2\"");
+        assert_eq!(secret.expose_secret(), "hunter2");
+    }
+
+    #[test]
+    fn user_output_has_no_password_hash_or_totp_material() {
+        let mut user = User::new("Charles", "super-secret");
+        let recovery_codes = user.enable_two_factor(TotpConfig::default());
+        let two_factor = user.two_factor().unwrap();
+        let base32 = two_factor.secret_base32();
+
+        let outputs = [user.to_string(), format!("{:?}", user), format!("{:#?}", user)];
+        for output in outputs.iter() {
+            assert!(output.contains("Charles"));
+            assert!(!output.contains("super-secret"));
+            assert!(!output.contains(base32.expose_secret().as_str()));
+            assert!(!output.contains('$'), "password hash leaked: {}", output);
+            for code in recovery_codes.iter() {
+                assert!(!output.contains(code.expose_secret().as_str()));
+            }
+        }
+    }
+
+    #[test]
+    fn api_key_output_has_no_key_or_hash() {
+        let mut store = ApiKeyStore::new();
+        let (plain, key) = store.create("ci-bot", vec![Scope::JournalRead], None, Utc::now()).unwrap();
+        let secret_part = plain.expose_secret().rsplit('_').next().unwrap().to_string();
+        let stored_hash = serde_json::to_value(&key).unwrap()["key_hash"].as_str().unwrap().to_string();
+
+        let outputs = [format!("{}", key), format!("{:?}", key), format!("{:?}", plain), format!("{}", plain)];
+        for output in outputs.iter() {
+            assert!(!output.contains(&secret_part));
+            assert!(!output.contains(&stored_hash));
+        }
+    }
+}
@@ -0,0 +1,299 @@
+// Simulates large batches of car orders in parallel, to stress-test ordering, pricing and the inventory
+
+/*
+    The orders are split into fixed-size chunks, and each chunk gets its own random number generator
+    seeded from the run's seed and the chunk's index, and its own in-memory inventory. Every order
+    the catalog and the pricing engine accept is placed in the chunk's inventory and taken some way
+    down its lifecycle: built, shipped, delivered, or cancelled before it ships. rayon runs the chunks
+    on every core and adds their tallies together. Because the chunks don't depend on how many threads there are or on the
+    order they finish in, and because the tallies are plain sums (money included, being whole cents),
+    the same seed always gives the same report, on one core or on sixty-four.
+
+    StdRng's algorithm may change between versions of the rand crate, so a seed is only guaranteed
+    to reproduce a run with the same Cargo.lock.
+ */
+
+use crate::car::{Age, CarOrder, Color, Transmission};
+use crate::catalog::Catalog;
+use crate::inventory::{Inventory, OrderState};
+use crate::money::Money;
+use crate::pricing::{PricingEngine, PricingRules};
+use chrono::{TimeZone, Utc};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const CHUNK_SIZE: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationConfig {
+    pub orders: u64,
+    pub seed: u64,
+    /// Share of orders for new cars, in percent. The rest are used cars with up to 150,000 miles.
+    pub new_percent: u32,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig { orders: 1_000_000, seed: 42, new_percent: 60 }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SimulationError {
+    NewPercent(u32),
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulationError::NewPercent(percent) => {
+                write!(f, "the share of new cars must be between 0% and 100%, not {}%", percent)
+            }
+        }
+    }
+}
+
+/// What a batch of orders added up to. Counts are in the order of Color::ALL, Transmission::ALL
+/// and OrderState::ALL.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Tallies {
+    pub orders: u64,
+    /// Orders the catalog, the pricing engine or the inventory turned down.
+    pub rejected: u64,
+    pub colors: [u64; 4],
+    pub transmissions: [u64; 3],
+    pub new: u64,
+    pub used: u64,
+    /// Where the placed orders ended up, as counted by the inventories.
+    pub states: [u64; 5],
+    /// What the orders that weren't cancelled are worth.
+    pub revenue: Money,
+}
+
+impl Tallies {
+    fn merge(mut self, other: Tallies) -> Tallies {
+        self.orders += other.orders;
+        self.rejected += other.rejected;
+        self.colors.iter_mut().zip(other.colors).for_each(|(count, more)| *count += more);
+        self.transmissions.iter_mut().zip(other.transmissions).for_each(|(count, more)| *count += more);
+        self.new += other.new;
+        self.used += other.used;
+        self.states.iter_mut().zip(other.states).for_each(|(count, more)| *count += more);
+        self.revenue += other.revenue;
+        self
+    }
+
+    pub fn cancelled(&self) -> u64 {
+        self.states[OrderState::ALL.iter().position(|state| *state == OrderState::Cancelled).expect("cancelled is in ALL")]
+    }
+
+    pub fn average_price(&self) -> Money {
+        let sold = self.orders - self.rejected - self.cancelled();
+        if sold == 0 {
+            Money::ZERO
+        } else {
+            Money::from_cents(self.revenue.minor_units() / sold as i64)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationReport {
+    pub tallies: Tallies,
+    pub elapsed: Duration,
+    pub threads: usize,
+}
+
+impl SimulationReport {
+    pub fn orders_per_second(&self) -> f64 {
+        self.tallies.orders as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tallies = &self.tallies;
+        let share = |count: u64| 100.0 * count as f64 / tallies.orders.max(1) as f64;
+
+        writeln!(f, "{} orders ({} rejected)", tallies.orders, tallies.rejected)?;
+        writeln!(f, "\nColor")?;
+        for (color, count) in Color::ALL.iter().zip(tallies.colors) {
+            writeln!(f, "  {:<12} {:>10} {:>6.2}%", color, count, share(count))?;
+        }
+        writeln!(f, "\nTransmission")?;
+        for (motor, count) in Transmission::ALL.iter().zip(tallies.transmissions) {
+            writeln!(f, "  {:<12} {:>10} {:>6.2}%", motor, count, share(count))?;
+        }
+        writeln!(f, "\nAge")?;
+        writeln!(f, "  {:<12} {:>10} {:>6.2}%", Age::New, tallies.new, share(tallies.new))?;
+        writeln!(f, "  {:<12} {:>10} {:>6.2}%", Age::Used, tallies.used, share(tallies.used))?;
+        writeln!(f, "\nState")?;
+        for (state, count) in OrderState::ALL.iter().zip(tallies.states) {
+            writeln!(f, "  {:<12} {:>10} {:>6.2}%", state, count, share(count))?;
+        }
+        writeln!(f, "\nRevenue        {}", tallies.revenue)?;
+        writeln!(f, "Average price  {}", tallies.average_price())?;
+        write!(
+            f,
+            "\n{:.2}s, {:.0} orders/s (threads: {})",
+            self.elapsed.as_secs_f64(),
+            self.orders_per_second(),
+            self.threads
+        )
+    }
+}
+
+pub fn simulate(
+    catalog: &Catalog,
+    rules: &PricingRules,
+    config: &SimulationConfig,
+) -> Result<SimulationReport, SimulationError> {
+    if config.new_percent > 100 {
+        return Err(SimulationError::NewPercent(config.new_percent));
+    }
+    let started = Instant::now();
+    let engine = PricingEngine::new(catalog, rules.clone());
+    let chunks = config.orders.div_ceil(CHUNK_SIZE);
+
+    let tallies = (0..chunks)
+        .into_par_iter()
+        .map(|chunk| {
+            let first = chunk * CHUNK_SIZE;
+            let size = CHUNK_SIZE.min(config.orders - first);
+            simulate_chunk(catalog, &engine, config, chunk, size)
+        })
+        .reduce(Tallies::default, Tallies::merge);
+
+    Ok(SimulationReport { tallies, elapsed: started.elapsed(), threads: rayon::current_num_threads() })
+}
+
+fn simulate_chunk(catalog: &Catalog, engine: &PricingEngine, config: &SimulationConfig, chunk: u64, size: u64) -> Tallies {
+    // Spread the chunk index over the seed's bits so neighbouring chunks get unrelated streams
+    let mut rng = StdRng::seed_from_u64(config.seed ^ chunk.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    let models: Vec<_> = catalog.makes.iter().flat_map(|make| make.models.iter().map(move |model| (make, model))).collect();
+    let mut inventory = Inventory::new();
+    let mut tallies = Tallies::default();
+    // A fixed clock, so the order histories are as reproducible as everything else
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap();
+
+    for i in 0..size {
+        tallies.orders += 1;
+        let Some((make, model)) = models.choose(&mut rng) else {
+            tallies.rejected += 1;
+            continue;
+        };
+
+        let new = rng.gen_range(0..100) < config.new_percent;
+        let miles = if new { rng.gen_range(0..10) } else { rng.gen_range(10..150_000) };
+        let order = CarOrder::new()
+            .make(&make.name)
+            .model(&model.name)
+            .year(*model.years.choose(&mut rng).expect("catalog models have years"))
+            .color(*model.colors.choose(&mut rng).expect("catalog models have colors"))
+            .transmission(*model.transmissions.choose(&mut rng).expect("catalog models have transmissions"))
+            .roof((*model.roofs.choose(&mut rng).expect("catalog models have roofs")).into())
+            .miles(miles)
+            .accidents(if new { 0 } else { rng.gen_range(0..3) })
+            .service_records(miles / 10_000);
+
+        let priced = order
+            .build_against(catalog)
+            .ok()
+            .and_then(|car| engine.price(&car, &[]).ok().map(|price| (car, price)));
+        let Some((car, price)) = priced else {
+            tallies.rejected += 1;
+            continue;
+        };
+        let color = Color::ALL.iter().position(|color| *color == car.color).expect("every color is in ALL");
+        let motor = Transmission::ALL.iter().position(|motor| *motor == car.motor).expect("every transmission is in ALL");
+        let age = car.age.0;
+        let placed_at = start + chrono::Duration::minutes(i as i64);
+        let Ok(id) = inventory.place(car, placed_at) else {
+            tallies.rejected += 1;
+            continue;
+        };
+
+        // Most orders get some way down the line, a week per step; a few are cancelled before they ship
+        let steps = rng.gen_range(0..=3);
+        let lifecycle = [OrderState::Built, OrderState::Shipped, OrderState::Delivered];
+        for (week, state) in (1..).zip(lifecycle.into_iter().take(steps)) {
+            let at = placed_at + chrono::Duration::weeks(week);
+            inventory.advance(id, state, at).expect("placed orders can be built, shipped and delivered");
+        }
+        let cancelled = steps < 2 && rng.gen_range(0..10) == 0;
+        if cancelled {
+            inventory.cancel(id, placed_at + chrono::Duration::weeks(4)).expect("unshipped orders can be cancelled");
+        }
+
+        tallies.colors[color] += 1;
+        tallies.transmissions[motor] += 1;
+        match age {
+            Age::New => tallies.new += 1,
+            Age::Used => tallies.used += 1,
+        }
+        if !cancelled {
+            tallies.revenue += price.total;
+        }
+    }
+
+    for (count, state) in tallies.states.iter_mut().zip(OrderState::ALL) {
+        *count = inventory.by_state(state).len() as u64;
+    }
+    tallies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn catalog() -> Catalog {
+        Catalog::load(PathBuf::from("catalog.json")).unwrap()
+    }
+
+    fn run(orders: u64, seed: u64) -> Tallies {
+        let config = SimulationConfig { orders, seed, ..SimulationConfig::default() };
+        simulate(&catalog(), &PricingRules::default(), &config).unwrap().tallies
+    }
+
+    #[test]
+    fn every_order_is_counted_once() {
+        let tallies = run(25_001, 7);
+        assert_eq!(tallies.orders, 25_001);
+        assert_eq!(tallies.rejected, 0);
+        assert_eq!(tallies.colors.iter().sum::<u64>(), tallies.orders);
+        assert_eq!(tallies.transmissions.iter().sum::<u64>(), tallies.orders);
+        assert_eq!(tallies.new + tallies.used, tallies.orders);
+        assert!(tallies.revenue > Money::ZERO);
+    }
+
+    #[test]
+    fn placed_orders_go_through_the_inventory() {
+        let tallies = run(25_001, 7);
+        assert_eq!(tallies.states.iter().sum::<u64>(), tallies.orders - tallies.rejected);
+        assert!(tallies.states.iter().all(|count| *count > 0));
+        assert!(tallies.cancelled() < tallies.orders / 10);
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_results_on_any_number_of_threads() {
+        let single = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap().install(|| run(30_000, 99));
+        let many = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap().install(|| run(30_000, 99));
+        assert_eq!(single, many);
+        assert_ne!(run(30_000, 100), single);
+    }
+
+    #[test]
+    fn new_share_follows_the_config() {
+        let config = SimulationConfig { orders: 20_000, seed: 1, new_percent: 100 };
+        let tallies = simulate(&catalog(), &PricingRules::default(), &config).unwrap().tallies;
+        assert_eq!(tallies.used, 0);
+
+        let config = SimulationConfig { new_percent: 101, ..config };
+        assert_eq!(simulate(&catalog(), &PricingRules::default(), &config).unwrap_err(), SimulationError::NewPercent(101));
+    }
+}
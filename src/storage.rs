@@ -0,0 +1,73 @@
+// Writing data files so that a crash or a full disk can't leave them half-written
+
+/*
+    Writing a file in place truncates it first, so if the program dies halfway through (or the disk
+    fills up) the old contents are gone and the new ones are incomplete. Instead we write the new
+    contents to a temporary file next to the real one, flush it to disk, and rename it over the real
+    file. A rename within one directory is atomic, so readers see either the old file or the new
+    one, never a mix.
+ */
+
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let temporary = temporary_path(path);
+    let written = File::create(&temporary).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    match written.and_then(|()| fs::rename(&temporary, path)) {
+        Ok(()) => Ok(()),
+        Err(error) => {
+            // Don't leave the half-written file lying around; the original error is the one that matters
+            let _ = fs::remove_file(&temporary);
+            Err(error)
+        }
+    }
+}
+
+pub fn write_json_atomically<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<()> {
+    write_atomically(path, &serde_json::to_vec(value)?)
+}
+
+// Same directory as the target, so the rename never crosses file systems
+fn temporary_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map_or("data".into(), |name| name.to_string_lossy());
+    path.with_file_name(format!(".{}.{}.tmp", name, uuid::Uuid::new_v4()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_the_file_and_cleans_up() {
+        let dir = std::env::temp_dir().join(format!("storage-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("orders.json");
+        fs::write(&path, "old contents that are longer than the new ones").unwrap();
+
+        write_json_atomically(&path, &vec![1, 2, 3]).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[1,2,3]");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_write_leaves_the_old_file_alone() {
+        let dir = std::env::temp_dir().join(format!("storage-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        // Renaming a file over a directory fails, after the temporary file has been written
+        let target = dir.join("busy");
+        fs::create_dir(&target).unwrap();
+
+        assert!(write_atomically(&target, b"new").is_err());
+        assert!(target.is_dir());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
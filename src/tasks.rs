@@ -1,6 +1,8 @@
 // chrono is a good crate to use if you need to handle date and time data in Rust.
 // It provides an easy API for representing a moment in time.
 
+use crate::format::{csv_row, OutputFormat};
+use crate::storage::write_json_atomically;
 use chrono::{serde::ts_seconds, DateTime, Local, Utc};
 use serde::Deserialize;
 use serde::Serialize;
@@ -83,7 +85,7 @@ pub fn add_task(journal_path: PathBuf, task: Task) -> Result<()> {
         .read(true)
         .write(true)
         .create(true)
-        .open(journal_path)?;
+        .open(&journal_path)?;
     /*
         Without the ? the variable type is Result<Vec<Task>>
         With the ? the variable type is Vec<Task>
@@ -95,20 +97,23 @@ pub fn add_task(journal_path: PathBuf, task: Task) -> Result<()> {
         Finally, we push the Task value received as a function parameter to the task list and use
         serde_json to write the task vector into the file. We then return the empty tuple value
         inside an Ok to indicate that everything went according to our plans.
+        The new list goes to a temporary file that then replaces the journal, so a crash halfway
+        through the write can't leave a corrupted journal behind (see storage.rs).
      */
     tasks.push(task);
 
-    serde_json::to_writer(file, &tasks)?;
+    write_json_atomically(&journal_path, &tasks)?;
 
     Ok(())
 }
 
-pub fn complete_task(journal_path: PathBuf, task_position: usize) -> Result<()> {
+// Returns the removed task, so the caller can say which task was completed.
+pub fn complete_task(journal_path: PathBuf, task_position: usize) -> Result<Task> {
     // Open the file.
     let file = OpenOptions::new()
         .read(true)
         .write(true)
-        .open(journal_path)?;
+        .open(&journal_path)?;
 
     // Consume the file's contents as a vector of tasks.
     let mut tasks = collect_tasks(&file)?;
@@ -118,25 +123,22 @@ pub fn complete_task(journal_path: PathBuf, task_position: usize) -> Result<()>
         return Err(Error::new(ErrorKind::InvalidInput, "Invalid Task ID"));
     }
 
-    tasks.remove(task_position - 1);
+    let task = tasks.remove(task_position - 1);
 
     /*
-        Truncate the file.
-        We're truncating the file before writing to it because we're performing a removal operation.
-        So the file will be smaller than the original. If we ignored this step, the rewound cursor
-        would stop behind the previously written bytes of the file, resulting in a malformed JSON
-        file. When we truncate the file by using the file.set_len(0) operation, we ensure that
-        we're writing the bytes in a blank page.
+        Replace the file.
+        Writing over the file in place would need it truncated first, because after a removal the
+        list is smaller than the original, and the rewound cursor would stop behind the previously
+        written bytes, resulting in a malformed JSON file. Writing the list to a temporary file and
+        renaming it over the journal avoids that, and also means a crash mid-write leaves the old
+        journal intact instead of a truncated one.
      */
-    file.set_len(0)?;
-
-    // Write the modified task list back into the file.
-    serde_json::to_writer(file, &tasks)?;
+    write_json_atomically(&journal_path, &tasks)?;
 
-    Ok(())
+    Ok(task)
 }
 
-pub fn list_tasks(journal_path: PathBuf) -> Result<()> {
+pub fn list_tasks(journal_path: PathBuf, format: OutputFormat) -> Result<()> {
     // Open the file.
     let file = OpenOptions::new()
         .read(true)
@@ -145,14 +147,23 @@ pub fn list_tasks(journal_path: PathBuf) -> Result<()> {
     // Parse the file and collect the tasks.
     let tasks = collect_tasks(&file)?;
 
-    // Enumerate and display tasks, if any.
-    if tasks.is_empty() {
-        println!("Task list is empty!");
-    } else {
-        let mut order: u32 = 1;
-        for task in tasks {
-            println!("{}: {:?}", order, task);
-            order += 1;
+    // Enumerate and display tasks, if any, in the format asked for.
+    match format {
+        OutputFormat::Text if tasks.is_empty() => println!("Task list is empty!"),
+        OutputFormat::Text => {
+            let mut order: u32 = 1;
+            for task in tasks {
+                println!("{}: {:?}", order, task);
+                order += 1;
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&tasks)?),
+        OutputFormat::Csv => {
+            println!("{}", csv_row(&["position", "text", "created_at"]));
+            for (index, task) in tasks.iter().enumerate() {
+                let position = (index + 1).to_string();
+                println!("{}", csv_row(&[position, task.text.clone(), task.created_at.to_rfc3339()]));
+            }
         }
     }
 
@@ -0,0 +1,407 @@
+// Line items on a quote, and the subtotal, discounts, taxes and grand total they add up to
+
+/*
+    Nothing here is stored: the totals are worked out from the items and discounts every time, so
+    they can always be reproduced (and checked) from a quote's contents. The steps, all in whole
+    minor units of the quote's single currency:
+
+        gross         unit price x quantity, per line
+      - line discount a percentage of the line's gross or a fixed amount, at most the gross
+      = net           per line; the nets add up to the subtotal
+      - quote discounts  percentages of the subtotal or fixed amounts, taken in order off what's
+                    left, so together they never exceed the subtotal
+      + taxes         per tax rate, on the discounted amounts of the lines that carry that rate
+      = grand total
+
+    Where the rounding happens:
+
+      - Percentage discounts round half up (half a cent off rounds to a cent off).
+      - The quote discounts are spread over the lines in proportion to their nets with
+        Money::allocate_by, so the lines' shares add up to the discount exactly.
+      - Each tax rate is rounded once, half up, on the sum of its lines' taxable amounts. Rounding
+        line by line instead would let the half cents pile up: ten lines of 10 cents at 5% would
+        owe 10 cents of tax rather than 5.
+
+    A line can carry several taxes (a state and a county sales tax, say), and different lines can
+    carry different ones; a line with no taxes is tax-exempt.
+ */
+
+use crate::car::Car;
+use crate::money::{Money, MoneyError, Rounding};
+use crate::pricing::{Discount, PriceLine};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TaxRate {
+    pub name: String,
+    pub basis_points: i64,
+}
+
+impl TaxRate {
+    pub fn new(name: &str, basis_points: i64) -> Self {
+        TaxRate { name: name.to_string(), basis_points }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineItem {
+    pub sku: String,
+    pub description: String,
+    pub quantity: u32,
+    pub unit_price: Money,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discount: Option<Discount>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub taxes: Vec<TaxRate>,
+    /// The car this line sells, one per unit of quantity. Accepted quotes turn these into orders.
+    /// Boxed, as a Car is several times the size of the rest of the line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vehicle: Option<Box<Car>>,
+}
+
+impl LineItem {
+    pub fn new(sku: &str, description: &str, quantity: u32, unit_price: Money) -> Self {
+        LineItem {
+            sku: sku.to_string(),
+            description: description.to_string(),
+            quantity,
+            unit_price,
+            discount: None,
+            taxes: Vec::new(),
+            vehicle: None,
+        }
+    }
+
+    pub fn discounted(mut self, discount: Discount) -> Self {
+        self.discount = Some(discount);
+        self
+    }
+
+    pub fn taxed(mut self, tax: TaxRate) -> Self {
+        self.taxes.push(tax);
+        self
+    }
+
+    pub fn for_vehicle(mut self, car: Car) -> Self {
+        self.vehicle = Some(Box::new(car));
+        self
+    }
+
+    /// Unit price times quantity, before any discount.
+    pub fn gross(&self) -> Result<Money, MoneyError> {
+        self.unit_price.checked_mul(self.quantity as i64)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TotalsError {
+    NoItems,
+    ZeroQuantity { sku: String },
+    NegativePrice { sku: String },
+    NegativeDiscount(String),
+    NegativeTax(String),
+    Money(MoneyError),
+}
+
+impl fmt::Display for TotalsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TotalsError::NoItems => write!(f, "a quote needs at least one line item"),
+            TotalsError::ZeroQuantity { sku } => write!(f, "line item {} has a quantity of zero", sku),
+            TotalsError::NegativePrice { sku } => write!(f, "line item {} has a negative unit price", sku),
+            TotalsError::NegativeDiscount(label) => write!(f, "discount '{}' would raise the price", label),
+            TotalsError::NegativeTax(name) => write!(f, "tax '{}' has a negative rate", name),
+            TotalsError::Money(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<MoneyError> for TotalsError {
+    fn from(error: MoneyError) -> Self {
+        TotalsError::Money(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineTotals {
+    pub sku: String,
+    pub gross: Money,
+    /// The line's own discount, as a positive amount.
+    pub discount: Money,
+    pub net: Money,
+    /// The line's part of the quote discounts, as a positive amount.
+    pub quote_discount: Money,
+    pub taxable: Money,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaxLine {
+    pub name: String,
+    pub basis_points: i64,
+    pub base: Money,
+    pub amount: Money,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Totals {
+    /// In the order of the items.
+    pub lines: Vec<LineTotals>,
+    pub subtotal: Money,
+    /// Negative amounts; they add up to at most the subtotal.
+    pub discounts: Vec<PriceLine>,
+    /// In the order each rate first appears on the items.
+    pub taxes: Vec<TaxLine>,
+    pub tax: Money,
+    pub total: Money,
+}
+
+impl Totals {
+    pub fn compute(items: &[LineItem], discounts: &[Discount]) -> Result<Totals, TotalsError> {
+        let first = items.first().ok_or(TotalsError::NoItems)?;
+        let zero = Money::zero(first.unit_price.currency());
+
+        let mut lines = Vec::with_capacity(items.len());
+        let mut subtotal = zero;
+        for item in items {
+            if item.quantity == 0 {
+                return Err(TotalsError::ZeroQuantity { sku: item.sku.clone() });
+            }
+            if item.unit_price.is_negative() {
+                return Err(TotalsError::NegativePrice { sku: item.sku.clone() });
+            }
+            // Adding to zero checks the line is in the quote's currency
+            let gross = zero.checked_add(item.gross()?)?;
+            let discount = match &item.discount {
+                Some(discount) => gross.checked_min(discount_amount(discount, gross)?)?,
+                None => zero,
+            };
+            let net = gross.checked_sub(discount)?;
+            subtotal = subtotal.checked_add(net)?;
+            lines.push(LineTotals { sku: item.sku.clone(), gross, discount, net, quote_discount: zero, taxable: net });
+        }
+
+        let mut remaining = subtotal;
+        let mut applied = Vec::with_capacity(discounts.len());
+        for discount in discounts {
+            let amount = remaining.checked_min(discount_amount(discount, subtotal)?)?;
+            remaining -= amount;
+            applied.push(PriceLine::new(label(discount), -amount));
+        }
+
+        let discounted = subtotal - remaining;
+        if !discounted.is_zero() {
+            let weights: Vec<u64> = lines.iter().map(|line| line.net.minor_units() as u64).collect();
+            for (line, share) in lines.iter_mut().zip(discounted.allocate_by(&weights)?) {
+                line.quote_discount = share;
+                line.taxable = line.net - share;
+            }
+        }
+
+        let mut taxes: Vec<TaxLine> = Vec::new();
+        for (item, line) in items.iter().zip(&lines) {
+            for rate in &item.taxes {
+                if rate.basis_points < 0 {
+                    return Err(TotalsError::NegativeTax(rate.name.clone()));
+                }
+                let position = taxes.iter().position(|tax| tax.name == rate.name && tax.basis_points == rate.basis_points);
+                let tax = match position {
+                    Some(position) => &mut taxes[position],
+                    None => {
+                        taxes.push(TaxLine { name: rate.name.clone(), basis_points: rate.basis_points, base: zero, amount: zero });
+                        taxes.last_mut().expect("just pushed")
+                    }
+                };
+                tax.base = tax.base.checked_add(line.taxable)?;
+            }
+        }
+        for tax in &mut taxes {
+            tax.amount = tax.base.checked_mul_ratio(tax.basis_points as i128, 10_000, Rounding::HalfUp)?;
+        }
+
+        let tax = taxes.iter().try_fold(zero, |sum, line| sum.checked_add(line.amount))?;
+        Ok(Totals { lines, subtotal, discounts: applied, taxes, tax, total: remaining.checked_add(tax)? })
+    }
+
+    pub fn discount(&self) -> Money {
+        // Folded from a zero in the quote's currency, so no discounts is still zero in that currency
+        self.discounts.iter().fold(Money::zero(self.subtotal.currency()), |sum, line| sum - line.amount)
+    }
+}
+
+impl fmt::Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = |f: &mut fmt::Formatter<'_>, label: &str, amount: Money| writeln!(f, "{:<32} {:>14}", label, amount);
+        line(f, "Subtotal", self.subtotal)?;
+        for discount in &self.discounts {
+            line(f, &discount.label, discount.amount)?;
+        }
+        for tax in &self.taxes {
+            line(f, &format!("{} ({}.{:02}%)", tax.name, tax.basis_points / 100, tax.basis_points % 100), tax.amount)?;
+        }
+        write!(f, "{:<32} {:>14}", "Total", self.total)
+    }
+}
+
+fn discount_amount(discount: &Discount, base: Money) -> Result<Money, TotalsError> {
+    let amount = match discount {
+        Discount::Percent { basis_points, .. } => base.checked_mul_ratio(*basis_points as i128, 10_000, Rounding::HalfUp)?,
+        Discount::Fixed { amount, .. } => *amount,
+    };
+    if amount.is_negative() {
+        return Err(TotalsError::NegativeDiscount(label(discount).to_string()));
+    }
+    Ok(amount)
+}
+
+fn label(discount: &Discount) -> &str {
+    match discount {
+        Discount::Percent { label, .. } | Discount::Fixed { label, .. } => label,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+
+    fn item(sku: &str, quantity: u32, cents: i64) -> LineItem {
+        LineItem::new(sku, sku, quantity, Money::from_cents(cents))
+    }
+
+    fn percent(basis_points: i64) -> Discount {
+        Discount::Percent { label: format!("{} bp off", basis_points), basis_points }
+    }
+
+    fn fixed(cents: i64) -> Discount {
+        Discount::Fixed { label: "Fixed".to_string(), amount: Money::from_cents(cents) }
+    }
+
+    fn sales_tax() -> TaxRate {
+        TaxRate::new("Sales tax", 825)
+    }
+
+    #[test]
+    fn totals_add_up() {
+        let items = [
+            item("CAR", 1, 10_000_000).taxed(sales_tax()),
+            item("MAT", 4, 4_999).discounted(percent(1_000)).taxed(sales_tax()),
+            item("SVC", 1, 50_000),
+        ];
+        let totals = Totals::compute(&items, &[fixed(100_000)]).unwrap();
+
+        assert_eq!(totals.lines[1].gross, Money::from_cents(19_996));
+        // 10% of 199.96 is 19.996
+        assert_eq!(totals.lines[1].discount, Money::from_cents(2_000));
+        assert_eq!(totals.subtotal, Money::from_cents(10_067_996));
+        assert_eq!(totals.discount(), Money::from_cents(100_000));
+        assert_eq!(Money::sum_in(Currency::USD, totals.lines.iter().map(|line| line.quote_discount)), Ok(totals.discount()));
+        assert_eq!(
+            Money::sum_in(Currency::USD, totals.lines.iter().map(|line| line.taxable)),
+            Ok(totals.subtotal - totals.discount())
+        );
+        assert_eq!(totals.taxes.len(), 1);
+        assert_eq!(totals.taxes[0].base, totals.lines[0].taxable + totals.lines[1].taxable);
+        assert_eq!(totals.total, totals.subtotal - totals.discount() + totals.tax);
+        // Computing again from the same items gives the same totals
+        assert_eq!(Totals::compute(&items, &[fixed(100_000)]).unwrap(), totals);
+    }
+
+    // Golden cases: the exact cents each tricky rounding case must come to
+    #[test]
+    fn golden_rounding() {
+        struct Case {
+            name: &'static str,
+            items: Vec<LineItem>,
+            discounts: Vec<Discount>,
+            tax: i64,
+            total: i64,
+        }
+
+        let cases = vec![
+            Case {
+                // 0.99 at 8.25% is 8.1675 cents
+                name: "tax rounds down below half",
+                items: vec![item("A", 3, 33).taxed(sales_tax())],
+                discounts: vec![],
+                tax: 8,
+                total: 107,
+            },
+            Case {
+                // Half a cent of tax on each line; rounding per line would give 2 cents
+                name: "taxes round once per rate, not per line",
+                items: vec![item("A", 1, 10).taxed(TaxRate::new("VAT", 500)), item("B", 1, 10).taxed(TaxRate::new("VAT", 500))],
+                discounts: vec![],
+                tax: 1,
+                total: 21,
+            },
+            Case {
+                // 50% off 15 cents is 7.5 cents, which rounds half up to 8
+                name: "line discount rounds half up",
+                items: vec![item("A", 1, 15).discounted(percent(5_000))],
+                discounts: vec![],
+                tax: 0,
+                total: 7,
+            },
+            Case {
+                // 10 dollars off three equal lines is 3.34 + 3.33 + 3.33; the taxed first line owes 10% of 6.66
+                name: "quote discount is spread without losing a cent",
+                items: vec![item("A", 1, 1_000).taxed(TaxRate::new("Luxury", 1_000)), item("B", 1, 1_000), item("C", 1, 1_000)],
+                discounts: vec![fixed(1_000)],
+                tax: 67,
+                total: 2_067,
+            },
+            Case {
+                // 10% of 100.01 is 10.001; state and county taxes are rounded separately
+                name: "stacked taxes each round on their own",
+                items: vec![item("A", 1, 10_001).taxed(TaxRate::new("State", 625)).taxed(TaxRate::new("County", 200))],
+                discounts: vec![percent(1_000)],
+                tax: 563 + 180,
+                total: 9_001 + 743,
+            },
+            Case {
+                // Discounts stack on what's left and stop at zero
+                name: "discounts never go below zero",
+                items: vec![item("A", 2, 999).taxed(sales_tax())],
+                discounts: vec![percent(5_000), fixed(5_000)],
+                tax: 0,
+                total: 0,
+            },
+        ];
+
+        for case in cases {
+            let totals = Totals::compute(&case.items, &case.discounts).unwrap();
+            assert_eq!(totals.tax, Money::from_cents(case.tax), "{}", case.name);
+            assert_eq!(totals.total, Money::from_cents(case.total), "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn yen_have_no_cents_to_round_to() {
+        let items = [LineItem::new("A", "A", 3, Money::new(333, Currency::JPY)).taxed(TaxRate::new("Consumption", 1_000))];
+        let totals = Totals::compute(&items, &[]).unwrap();
+        // 10% of 999 yen is 99.9
+        assert_eq!(totals.tax, Money::new(100, Currency::JPY));
+        assert_eq!(totals.total, Money::new(1_099, Currency::JPY));
+    }
+
+    #[test]
+    fn bad_items_are_refused() {
+        assert_eq!(Totals::compute(&[], &[]), Err(TotalsError::NoItems));
+        assert_eq!(Totals::compute(&[item("A", 0, 1)], &[]), Err(TotalsError::ZeroQuantity { sku: "A".to_string() }));
+        assert_eq!(Totals::compute(&[item("A", 1, -1)], &[]), Err(TotalsError::NegativePrice { sku: "A".to_string() }));
+        assert_eq!(
+            Totals::compute(&[item("A", 1, 100)], &[fixed(-1)]),
+            Err(TotalsError::NegativeDiscount("Fixed".to_string()))
+        );
+        assert_eq!(
+            Totals::compute(&[item("A", 1, 100).taxed(TaxRate::new("Refund", -1))], &[]),
+            Err(TotalsError::NegativeTax("Refund".to_string()))
+        );
+        let mixed = [item("A", 1, 100), LineItem::new("B", "B", 1, Money::new(100, Currency::EUR))];
+        assert_eq!(
+            Totals::compute(&mixed, &[]),
+            Err(TotalsError::Money(MoneyError::CurrencyMismatch { expected: Currency::USD, found: Currency::EUR }))
+        );
+    }
+}
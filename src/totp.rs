@@ -0,0 +1,338 @@
+// Time-based one-time passwords (RFC 6238) for two-factor authentication
+
+/*
+    TOTP is HOTP (RFC 4226) with the counter replaced by the number of time steps since the Unix epoch.
+    HOTP takes the HMAC-SHA1 of the 8-byte big-endian counter, picks 4 bytes out of the digest at an
+    offset given by the digest's last nibble ("dynamic truncation"), and keeps the last `digits` decimal
+    digits of that 31-bit number.
+
+    Only HMAC-SHA1 is supported. RFC 6238 also allows SHA-256 and SHA-512, but authenticator apps
+    widely ignore the algorithm parameter, so provisioning URIs always say SHA1.
+
+    Codes have 6 to 8 digits and a time step of at least one second; TotpConfig::new() refuses
+    anything else.
+
+    The shared secret is shown to the user once, as an otpauth:// URI that authenticator apps turn into
+    an account (usually by scanning it as a QR code). Recovery codes are printed once as well, and only
+    their hashes are kept, so each can be used a single time if the phone is lost.
+ */
+
+use crate::auth::{constant_time_eq, to_hex};
+use crate::secret::Secret;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+const SECRET_LEN: usize = 20;
+const RECOVERY_CODES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TotpConfig {
+    digits: u32,
+    step_secs: i64,
+    // How many steps either side of the current one are still accepted, to allow for clock drift
+    skew: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TotpConfigError {
+    Digits(u32),
+    Step(i64),
+}
+
+impl fmt::Display for TotpConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TotpConfigError::Digits(digits) => write!(f, "codes must have 6 to 8 digits, not {}", digits),
+            TotpConfigError::Step(step) => write!(f, "the time step must be at least one second, not {}", step),
+        }
+    }
+}
+
+impl TotpConfig {
+    pub fn new(digits: u32, step_secs: i64, skew: u32) -> Result<Self, TotpConfigError> {
+        if !(6..=8).contains(&digits) {
+            return Err(TotpConfigError::Digits(digits));
+        }
+        if step_secs <= 0 {
+            return Err(TotpConfigError::Step(step_secs));
+        }
+        Ok(TotpConfig { digits, step_secs, skew })
+    }
+
+    pub fn digits(&self) -> u32 {
+        self.digits
+    }
+
+    pub fn step_secs(&self) -> i64 {
+        self.step_secs
+    }
+
+    pub fn skew(&self) -> u32 {
+        self.skew
+    }
+}
+
+impl Default for TotpConfig {
+    fn default() -> Self {
+        TotpConfig {
+            digits: 6,
+            step_secs: 30,
+            skew: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TwoFactor {
+    secret: Secret<Vec<u8>>,
+    config: TotpConfig,
+    recovery_code_hashes: Vec<Secret<String>>,
+    // The step of the last accepted code, so the same code can't be replayed within its window
+    last_used_step: Option<i64>,
+}
+
+impl TwoFactor {
+    /// Enrolls a fresh secret. The recovery codes are returned in plain text exactly once.
+    pub fn enroll(config: TotpConfig) -> (Self, Vec<Secret<String>>) {
+        let mut rng = rand::thread_rng();
+        let secret = Secret::new(rng.gen::<[u8; SECRET_LEN]>().to_vec());
+
+        let recovery_codes: Vec<Secret<String>> = (0..RECOVERY_CODES)
+            .map(|_| {
+                let hex = Secret::new(to_hex(&rng.gen::<[u8; 5]>()));
+                let (left, right) = hex.expose_secret().split_at(5);
+                Secret::new(format!("{}-{}", left, right))
+            })
+            .collect();
+
+        let two_factor = TwoFactor {
+            secret,
+            config,
+            recovery_code_hashes: recovery_codes.iter().map(|code| hash_code(code.expose_secret())).collect(),
+            last_used_step: None,
+        };
+
+        (two_factor, recovery_codes)
+    }
+
+    pub fn config(&self) -> &TotpConfig {
+        &self.config
+    }
+
+    pub fn secret_base32(&self) -> Secret<String> {
+        Secret::new(base32_encode(self.secret.expose_secret()))
+    }
+
+    /// The otpauth:// URI understood by authenticator apps. It embeds the secret.
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> Secret<String> {
+        Secret::new(format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            percent_encode(issuer),
+            percent_encode(account),
+            self.secret_base32().expose_secret(),
+            percent_encode(issuer),
+            self.config.digits,
+            self.config.step_secs
+        ))
+    }
+
+    /// Accepts a code from the authenticator app, once, within the configured skew.
+    pub fn verify(&mut self, code: &str, now: DateTime<Utc>) -> bool {
+        let current = now.timestamp().div_euclid(self.config.step_secs);
+        let skew = self.config.skew as i64;
+
+        for step in (current - skew)..=(current + skew) {
+            if step < 0 || self.last_used_step.is_some_and(|used| step <= used) {
+                continue;
+            }
+            let expected = hotp(self.secret.expose_secret(), step as u64, self.config.digits);
+            let expected = format_code(expected, self.config.digits);
+            if constant_time_eq(expected.as_bytes(), code.as_bytes()) {
+                self.last_used_step = Some(step);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Accepts a recovery code, which is then used up.
+    pub fn redeem_recovery_code(&mut self, code: &str) -> bool {
+        let hash = hash_code(&code.trim().to_lowercase());
+        match self
+            .recovery_code_hashes
+            .iter()
+            .position(|stored| stored == &hash)
+        {
+            Some(index) => {
+                self.recovery_code_hashes.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn recovery_codes_remaining(&self) -> usize {
+        self.recovery_code_hashes.len()
+    }
+}
+
+/// HOTP value (RFC 4226) for the given counter, before zero-padding.
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]])
+        & 0x7fff_ffff;
+
+    // A 31-bit number never has more than 10 digits, so there's nothing to cut off beyond 9
+    10u32.checked_pow(digits).map_or(binary, |modulus| binary % modulus)
+}
+
+/// TOTP code (RFC 6238) for the given moment, zero-padded to the configured number of digits.
+pub fn totp(secret: &[u8], now: DateTime<Utc>, config: &TotpConfig) -> String {
+    let step = now.timestamp().div_euclid(config.step_secs) as u64;
+    format_code(hotp(secret, step, config.digits), config.digits)
+}
+
+fn format_code(value: u32, digits: u32) -> String {
+    format!("{:0width$}", value, width = digits as usize)
+}
+
+fn hash_code(code: &str) -> Secret<String> {
+    Secret::new(to_hex(&Sha256::digest(code.as_bytes())))
+}
+
+/// RFC 4648 base32, without padding, as expected by otpauth:// URIs.
+pub fn base32_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut output = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        output.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    output
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn matches_rfc_6238_sha1_vectors() {
+        let config = TotpConfig::new(8, 30, 1).unwrap();
+        let vectors = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+
+        for (seconds, expected) in vectors {
+            let now = Utc.timestamp_opt(seconds, 0).unwrap();
+            assert_eq!(totp(RFC_SECRET, now, &config), expected, "T = {}", seconds);
+        }
+    }
+
+    #[test]
+    fn matches_rfc_4226_hotp_vectors() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, value) in expected.iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as u64, 6), *value);
+        }
+    }
+
+    #[test]
+    fn configs_outside_the_supported_range_are_refused() {
+        assert_eq!(TotpConfig::new(6, 30, 1), Ok(TotpConfig::default()));
+        assert_eq!(TotpConfig::new(5, 30, 1), Err(TotpConfigError::Digits(5)));
+        assert_eq!(TotpConfig::new(10, 30, 1), Err(TotpConfigError::Digits(10)));
+        assert_eq!(TotpConfig::new(8, 0, 1), Err(TotpConfigError::Step(0)));
+        assert_eq!(TotpConfig::new(8, -30, 1), Err(TotpConfigError::Step(-30)));
+        assert_eq!(hotp(RFC_SECRET, 0, 10), 1_284_755_224);
+    }
+
+    #[test]
+    fn base32_matches_rfc_4648() {
+        assert_eq!(base32_encode(b""), "");
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_encode(RFC_SECRET), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    }
+
+    #[test]
+    fn provisioning_uri_encodes_issuer_and_account() {
+        let (two_factor, _) = TwoFactor::enroll(TotpConfig::default());
+        let uri = two_factor.provisioning_uri("Rusty Journal", "charles@example.com");
+        let uri = uri.expose_secret();
+
+        assert!(uri.starts_with("otpauth://totp/Rusty%20Journal:charles%40example.com?secret="));
+        assert!(uri.contains(&format!("secret={}&", two_factor.secret_base32().expose_secret())));
+        assert!(uri.ends_with("&issuer=Rusty%20Journal&algorithm=SHA1&digits=6&period=30"));
+    }
+
+    #[test]
+    fn verify_accepts_codes_within_skew_only() {
+        let (mut two_factor, _) = TwoFactor::enroll(TotpConfig::default());
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap();
+        let config = *two_factor.config();
+
+        let previous = totp(two_factor.secret.expose_secret(), now - Duration::seconds(30), &config);
+        let too_old = totp(two_factor.secret.expose_secret(), now - Duration::seconds(60), &config);
+
+        assert!(!two_factor.verify(&too_old, now));
+        assert!(two_factor.verify(&previous, now));
+    }
+
+    #[test]
+    fn verify_rejects_replayed_code() {
+        let (mut two_factor, _) = TwoFactor::enroll(TotpConfig::default());
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap();
+        let code = totp(two_factor.secret.expose_secret(), now, two_factor.config());
+
+        assert!(two_factor.verify(&code, now));
+        assert!(!two_factor.verify(&code, now + Duration::seconds(10)));
+    }
+
+    #[test]
+    fn recovery_codes_are_single_use() {
+        let (mut two_factor, codes) = TwoFactor::enroll(TotpConfig::default());
+
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(two_factor.redeem_recovery_code(&codes[3].expose_secret().to_uppercase()));
+        assert!(!two_factor.redeem_recovery_code(codes[3].expose_secret()));
+        assert_eq!(two_factor.recovery_codes_remaining(), RECOVERY_CODES - 1);
+    }
+}
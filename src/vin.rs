@@ -0,0 +1,330 @@
+// Vehicle identification numbers: generating them for new cars and checking the ones we're given
+
+/*
+    A VIN is 17 characters from 0-9 and A-Z without I, O and Q (they look too much like 1 and 0):
+
+        Z A M  F O L G 0  7  R  M  0 0 0 0 4 2
+        \___/  \_______/  |  |  |  \_________/
+         WMI    model     |  |  plant  serial
+                code      | model year
+                          check digit
+
+    - WMI, the world manufacturer identifier, says who made the car. Each make in the catalog lists
+      its WMI, and cars built against the catalog carry it.
+    - The model code is the first five letters and digits of the model name, with I, O and Q swapped
+      for 1, 0 and 0 and padded with 0 if the name is shorter.
+    - The check digit is computed as ISO 3779 (and the North American standard) describe: every
+      character is transliterated to a number, multiplied by a weight for its position, and the sum
+      is taken modulo 11, with 10 written as X.
+    - The model year is one character from a cycle of 30 that starts at A for 2010, so only model
+      years 2010 to 2039 can be encoded and decoded without ambiguity.
+    - The plant is a single character and the serial is six digits.
+ */
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+pub const LENGTH: usize = 17;
+
+// Position 9 holds the check digit and has no weight of its own
+const WEIGHTS: [u32; LENGTH] = [8, 7, 6, 5, 4, 3, 2, 10, 0, 9, 8, 7, 6, 5, 4, 3, 2];
+
+const YEAR_CODES: &[u8; 30] = b"ABCDEFGHJKLMNPRSTVWXY123456789";
+const FIRST_YEAR: u16 = 2010;
+const LAST_YEAR: u16 = FIRST_YEAR + YEAR_CODES.len() as u16 - 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Vin(String);
+
+/// The parts of a VIN, as decode() reads them back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VinParts {
+    pub wmi: String,
+    pub model_code: String,
+    pub check_digit: char,
+    pub year: u16,
+    pub plant: char,
+    pub serial: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VinError {
+    WrongLength(usize),
+    /// `position` counts from 1, the way VIN positions are usually numbered.
+    IllegalCharacter { position: usize, character: char },
+    WrongCheckDigit { expected: char, found: char },
+    InvalidYearCode(char),
+    InvalidSerial(String),
+    // Only generating a VIN can fail with these
+    InvalidWmi(String),
+    MissingWmi,
+    MissingYear,
+    YearOutOfRange(u16),
+    InvalidPlant(char),
+    SerialOutOfRange(u32),
+}
+
+impl fmt::Display for VinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VinError::WrongLength(length) => write!(f, "a VIN has {} characters, not {}", LENGTH, length),
+            VinError::IllegalCharacter { position, character } => {
+                write!(f, "'{}' at position {} can't appear in a VIN", character, position)
+            }
+            VinError::WrongCheckDigit { expected, found } => {
+                write!(f, "check digit is {} but should be {}; the VIN has a typo", found, expected)
+            }
+            VinError::InvalidYearCode(code) => write!(f, "'{}' is not a model year code", code),
+            VinError::InvalidSerial(serial) => write!(f, "serial number '{}' should be six digits", serial),
+            VinError::InvalidWmi(wmi) => write!(f, "'{}' is not a manufacturer code, those are three VIN characters", wmi),
+            VinError::MissingWmi => write!(f, "a VIN can't be generated without a manufacturer code"),
+            VinError::MissingYear => write!(f, "a VIN can't be generated without a model year"),
+            VinError::YearOutOfRange(year) => {
+                write!(f, "model year {} can't be encoded, only {} to {} can", year, FIRST_YEAR, LAST_YEAR)
+            }
+            VinError::InvalidPlant(plant) => write!(f, "'{}' can't be used as a plant code", plant),
+            VinError::SerialOutOfRange(serial) => write!(f, "serial number {} has more than six digits", serial),
+        }
+    }
+}
+
+impl Vin {
+    pub fn generate(wmi: &str, model: &str, year: u16, plant: char, serial: u32) -> Result<Vin, VinError> {
+        let wmi = wmi.trim().to_ascii_uppercase();
+        if !is_wmi(&wmi) {
+            return Err(VinError::InvalidWmi(wmi));
+        }
+        let year_code = year
+            .checked_sub(FIRST_YEAR)
+            .and_then(|offset| YEAR_CODES.get(offset as usize))
+            .ok_or(VinError::YearOutOfRange(year))?;
+        let plant = plant.to_ascii_uppercase();
+        if !is_legal(plant) {
+            return Err(VinError::InvalidPlant(plant));
+        }
+        if serial > 999_999 {
+            return Err(VinError::SerialOutOfRange(serial));
+        }
+
+        let mut vin: Vec<char> = format!("{}{}0{}{}{:06}", wmi, model_code(model), *year_code as char, plant, serial)
+            .chars()
+            .collect();
+        vin[8] = check_digit(&vin);
+        Ok(Vin(vin.into_iter().collect()))
+    }
+
+    /// Validates a VIN: length, characters, check digit, model year and serial number.
+    pub fn parse(text: &str) -> Result<Vin, VinError> {
+        let vin: Vec<char> = text.trim().chars().map(|c| c.to_ascii_uppercase()).collect();
+        if vin.len() != LENGTH {
+            return Err(VinError::WrongLength(vin.len()));
+        }
+        if let Some((index, character)) = vin.iter().enumerate().find(|(_, c)| !is_legal(**c)) {
+            return Err(VinError::IllegalCharacter { position: index + 1, character: *character });
+        }
+        let expected = check_digit(&vin);
+        if vin[8] != expected {
+            return Err(VinError::WrongCheckDigit { expected, found: vin[8] });
+        }
+        year_of(vin[9])?;
+        let serial: String = vin[11..].iter().collect();
+        if !serial.chars().all(|c| c.is_ascii_digit()) {
+            return Err(VinError::InvalidSerial(serial));
+        }
+        Ok(Vin(vin.into_iter().collect()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn decode(&self) -> VinParts {
+        let chars: Vec<char> = self.0.chars().collect();
+        let wmi: String = chars[..3].iter().collect();
+        VinParts {
+            wmi,
+            model_code: chars[3..8].iter().collect(),
+            check_digit: chars[8],
+            // A Vin has been validated, so these can't fail
+            year: year_of(chars[9]).expect("validated model year"),
+            plant: chars[10],
+            serial: self.0[11..].parse().expect("validated serial"),
+        }
+    }
+}
+
+/// Whether the text can be a world manufacturer identifier: three characters allowed in a VIN.
+pub fn is_wmi(text: &str) -> bool {
+    text.chars().count() == 3 && text.chars().all(is_legal)
+}
+
+fn is_legal(c: char) -> bool {
+    (c.is_ascii_digit() || c.is_ascii_uppercase()) && !matches!(c, 'I' | 'O' | 'Q')
+}
+
+fn model_code(model: &str) -> String {
+    let code: String = model
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| match c.to_ascii_uppercase() {
+            'I' => '1',
+            'O' | 'Q' => '0',
+            c => c,
+        })
+        .take(5)
+        .collect();
+    format!("{:0<5}", code)
+}
+
+fn transliterate(c: char) -> u32 {
+    match c {
+        '0'..='9' => c as u32 - '0' as u32,
+        'A' | 'J' => 1,
+        'B' | 'K' | 'S' => 2,
+        'C' | 'L' | 'T' => 3,
+        'D' | 'M' | 'U' => 4,
+        'E' | 'N' | 'V' => 5,
+        'F' | 'W' => 6,
+        'G' | 'P' | 'X' => 7,
+        'H' | 'Y' => 8,
+        'R' | 'Z' => 9,
+        _ => 0,
+    }
+}
+
+fn check_digit(vin: &[char]) -> char {
+    let sum: u32 = vin.iter().zip(WEIGHTS).map(|(c, weight)| transliterate(*c) * weight).sum();
+    match sum % 11 {
+        10 => 'X',
+        digit => char::from_digit(digit, 10).unwrap(),
+    }
+}
+
+fn year_of(code: char) -> Result<u16, VinError> {
+    YEAR_CODES
+        .iter()
+        .position(|candidate| *candidate as char == code)
+        .map(|offset| FIRST_YEAR + offset as u16)
+        .ok_or(VinError::InvalidYearCode(code))
+}
+
+impl fmt::Display for Vin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+impl FromStr for Vin {
+    type Err = VinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Vin::parse(s)
+    }
+}
+
+// Stored as the plain 17 characters; a file with a bad VIN doesn't load
+impl Serialize for Vin {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Vin {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn check_digit_matches_a_published_vin() {
+        // The worked example from the North American VIN standard
+        assert_eq!(Vin::parse("1M8GDM9AXKP042788").map(|vin| vin.decode().check_digit), Ok('X'));
+        assert_eq!(
+            Vin::parse("1M8GDM9A1KP042788"),
+            Err(VinError::WrongCheckDigit { expected: 'X', found: '1' })
+        );
+    }
+
+    #[test]
+    fn generates_from_make_model_year_and_plant() {
+        let vin = Vin::generate("zam", "Folgore", 2024, 'm', 42).unwrap();
+        assert_eq!(vin.as_str().len(), LENGTH);
+        assert!(vin.as_str().starts_with("ZAMF0LG"));
+        assert_eq!(
+            vin.decode(),
+            VinParts {
+                wmi: "ZAM".to_string(),
+                model_code: "F0LG0".to_string(),
+                check_digit: vin.as_str().chars().nth(8).unwrap(),
+                year: 2024,
+                plant: 'M',
+                serial: 42,
+            }
+        );
+        assert_eq!(Vin::parse(vin.as_str()), Ok(vin));
+    }
+
+    #[test]
+    fn generating_reports_what_is_wrong() {
+        assert_eq!(Vin::generate("Fiat", "Panda", 2024, 'M', 1), Err(VinError::InvalidWmi("FIAT".to_string())));
+        assert_eq!(Vin::generate("ZOF", "Panda", 2024, 'M', 1), Err(VinError::InvalidWmi("ZOF".to_string())));
+        assert_eq!(Vin::generate("ZFF", "Roma", 2009, 'M', 1), Err(VinError::YearOutOfRange(2009)));
+        assert_eq!(Vin::generate("ZFF", "Roma", 2024, 'O', 1), Err(VinError::InvalidPlant('O')));
+        assert_eq!(Vin::generate("ZFF", "Roma", 2024, 'M', 1_000_000), Err(VinError::SerialOutOfRange(1_000_000)));
+    }
+
+    #[test]
+    fn validation_reports_why_a_vin_is_invalid() {
+        let vin = Vin::generate("ZFF", "Roma", 2023, 'M', 7).unwrap().to_string();
+
+        assert_eq!(Vin::parse(&vin[..16]), Err(VinError::WrongLength(16)));
+        assert_eq!(
+            Vin::parse(&format!("{}O{}", &vin[..3], &vin[4..])),
+            Err(VinError::IllegalCharacter { position: 4, character: 'O' })
+        );
+        assert!(matches!(Vin::parse(&format!("{}9", &vin[..16])), Err(VinError::WrongCheckDigit { .. })));
+        assert_eq!(Vin::parse(&vin.to_lowercase()).map(|vin| vin.to_string()), Ok(vin));
+    }
+
+    proptest! {
+        #[test]
+        fn generated_vins_always_validate(
+            wmi in "[A-HJ-NPR-Z0-9]{3}",
+            model in "[ -~]{0,20}",
+            year in FIRST_YEAR..=LAST_YEAR,
+            plant in prop::sample::select("0123456789ABCDEFGHJKLMNPRSTUVWXYZ".chars().collect::<Vec<_>>()),
+            serial in 0u32..=999_999,
+        ) {
+            let vin = Vin::generate(&wmi, &model, year, plant, serial).unwrap();
+            let parsed = Vin::parse(vin.as_str()).unwrap();
+            let parts = parsed.decode();
+
+            prop_assert_eq!(parts.wmi, wmi);
+            prop_assert_eq!(parts.year, year);
+            prop_assert_eq!(parts.plant, plant);
+            prop_assert_eq!(parts.serial, serial);
+        }
+
+        #[test]
+        fn changing_one_character_breaks_the_check_digit(
+            serial in 0u32..=999_999,
+            position in (0usize..LENGTH).prop_filter("the check digit itself", |p| *p != 8),
+            replacement in prop::sample::select("0123456789ABCDEFGHJKLMNPRSTUVWXYZ".chars().collect::<Vec<_>>()),
+        ) {
+            let vin = Vin::generate("ZFF", "Roma", 2024, 'M', serial).unwrap();
+            let mut chars: Vec<char> = vin.as_str().chars().collect();
+            prop_assume!(transliterate(chars[position]) != transliterate(replacement));
+            chars[position] = replacement;
+
+            let tampered: String = chars.into_iter().collect();
+            prop_assert!(Vin::parse(&tampered).is_err());
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use chrono::{Duration, TimeZone, Utc};
+use std::path::PathBuf;
+use uuid::Uuid;
+use Rust_Microsoft_Learn::car::{Car, CarOrder, Color, Transmission};
+use Rust_Microsoft_Learn::catalog::Catalog;
+use Rust_Microsoft_Learn::clock::{Clock, FakeClock};
+use Rust_Microsoft_Learn::customer::{Customer, CustomerRegistry};
+use Rust_Microsoft_Learn::inventory::{Inventory, InventoryError, OrderState};
+use Rust_Microsoft_Learn::money::Money;
+use Rust_Microsoft_Learn::pricing::{PricingEngine, PricingRules};
+use Rust_Microsoft_Learn::quote::{PostRequest, QuoteStatus};
+use Rust_Microsoft_Learn::quoting::{QuoteService, QuotingError};
+use Rust_Microsoft_Learn::totals::LineItem;
+
+fn folgore(catalog: &Catalog, color: Color) -> Car {
+    CarOrder::new()
+        .make("Maserati")
+        .model("Folgore")
+        .year(2024)
+        .color(color)
+        .transmission(Transmission::Automatic)
+        .roof(true)
+        .build_against(catalog)
+        .unwrap()
+}
+
+fn vehicle_line(catalog: &Catalog, sku: &str, car: Car, quantity: u32) -> LineItem {
+    let price = PricingEngine::new(catalog, PricingRules::default()).price(&car, &[]).unwrap();
+    LineItem::new(sku, &format!("{} {}", car.make(), car.model()), quantity, price.subtotal).for_vehicle(car)
+}
+
+#[test]
+fn accepted_quotes_become_car_orders_once() {
+    let dir = std::env::temp_dir().join(format!("quote-to-order-{}", Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+    let file = |name: &str| -> PathBuf { dir.join(name) };
+    let catalog = Catalog::load(PathBuf::from("catalog.json")).unwrap();
+    let clock = FakeClock::new(Utc.with_ymd_and_hms(2023, 6, 1, 9, 0, 0).unwrap());
+
+    // A registered customer asks for two blue cars, one silver one and some mats
+    let mut customers = CustomerRegistry::open(file("customers.json")).unwrap();
+    let customer_id = customers.add(Customer::new("Acme Rentals", "1 Main St, Springfield", "US123456789")).unwrap();
+    let mut quotes = QuoteService::open(file("quotes.json"), PricingRules::default(), customers, &clock)
+        .unwrap()
+        .with_catalog(catalog.clone());
+    let request = PostRequest {
+        customer_id,
+        items: vec![
+            vehicle_line(&catalog, "FOLGORE-BLUE", folgore(&catalog, Color::Blue), 2),
+            vehicle_line(&catalog, "FOLGORE-SILVER", folgore(&catalog, Color::Silver), 1),
+            LineItem::new("MAT-01", "Floor mats", 3, Money::from_cents(4_999)),
+        ],
+        discounts: Vec::new(),
+    };
+    let response = quotes.quote(&request).unwrap();
+    let uuid = Uuid::parse_str(&response.quote_uuid).unwrap();
+
+    // Nothing can be ordered before the customer accepts
+    let mut inventory = Inventory::open(file("inventory.json")).unwrap();
+    assert_eq!(
+        quotes.place_orders(&uuid, &mut inventory),
+        Err(QuotingError::NotAccepted { status: QuoteStatus::Issued })
+    );
+
+    clock.advance(Duration::days(3));
+    assert_eq!(quotes.accept(&uuid).unwrap().status(), QuoteStatus::Accepted);
+    let ids = quotes.place_orders(&uuid, &mut inventory).unwrap();
+
+    // One order per car, linked to the quote; the mats aren't cars
+    assert_eq!(ids, vec![1, 2, 3]);
+    let colors: Vec<Color> = inventory.for_quote(uuid).iter().map(|order| order.car.color).collect();
+    assert_eq!(colors, vec![Color::Blue, Color::Blue, Color::Silver]);
+    for order in inventory.for_quote(uuid) {
+        assert_eq!(order.state, OrderState::Placed);
+        assert_eq!(order.placed_at(), clock.now());
+        assert!(order.car.vin.is_some());
+    }
+
+    // After a restart the quote is still accepted, and still can't be ordered twice, not even
+    // from another inventory
+    drop(inventory);
+    let customers = CustomerRegistry::open(file("customers.json")).unwrap();
+    let mut quotes = QuoteService::open(file("quotes.json"), PricingRules::default(), customers, &clock).unwrap();
+    let mut inventory = Inventory::open(file("inventory.json")).unwrap();
+    assert!(quotes.get(&uuid).unwrap().latest().is_accepted());
+    assert_eq!(quotes.get(&uuid).unwrap().orders(), &ids[..]);
+    assert_eq!(quotes.for_customer(customer_id).len(), 1);
+    assert_eq!(quotes.place_orders(&uuid, &mut inventory), Err(QuotingError::AlreadyOrdered { orders: ids.clone() }));
+    let mut elsewhere = Inventory::open(file("other-inventory.json")).unwrap();
+    assert_eq!(quotes.place_orders(&uuid, &mut elsewhere), Err(QuotingError::AlreadyOrdered { orders: ids.clone() }));
+    assert_eq!(inventory.orders().len(), 3);
+    assert!(elsewhere.orders().is_empty());
+
+    // The inventory keeps its own guard for anything that orders a quote without the service
+    assert_eq!(
+        inventory.place_for_quote(uuid, vec![folgore(&catalog, Color::Blue)], clock.now()),
+        Err(InventoryError::AlreadyOrdered { quote: uuid, orders: ids })
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}